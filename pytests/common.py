@@ -15,6 +15,26 @@ def toDictionary(res, max_recursion=1000):
         return [toDictionary(r, max_recursion - 1) for r in res]
     return {res[i]: toDictionary(res[i + 1], max_recursion - 1) for i in range(0, len(res), 2)}
 
+def gearsModuleArgs(args):
+    # the backends are given on the command line module arguments, keep them and add the given arguments
+    module_args = Defaults.module_args
+    if isinstance(module_args, list):
+        module_args = module_args[0] if len(module_args) > 0 else []
+    if isinstance(module_args, str):
+        module_args = module_args.split()
+    return [list(module_args or []) + args]
+
+def aofRewriteDone(env):
+    info = env.cmd('INFO', 'persistence')
+    return info['aof_rewrite_in_progress'] == 0 and info['aof_rewrite_scheduled'] == 0
+
+def rewriteAofAndLoad(env):
+    # enabling the AOF starts a rewrite, wait for it before starting a new one
+    runUntil(env, True, lambda: aofRewriteDone(env), timeout=5)
+    env.cmd('BGREWRITEAOF')
+    runUntil(env, True, lambda: aofRewriteDone(env), timeout=5)
+    env.expect('DEBUG', 'LOADAOF').equal('OK')
+
 def runUntil(env, expected_result, callback, sleep_time=0.1, timeout=1):
     with TimeLimit(timeout, env, "Failed waiting for callback to return '%s'" % str(expected_result)):
         while True:
@@ -1 +1 @@
-RLTest --module ../target/debug/libredisgears.so --module-args "../../target/debug/libredisgears_v8_plugin.so" $@
\ No newline at end of file
+RLTest --module ../target/debug/libredisgears.so --module-args "BACKEND ../../target/debug/libredisgears_v8_plugin.so" $@
\ No newline at end of file
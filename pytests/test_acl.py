@@ -1,6 +1,7 @@
 from common import gearsTest
 from common import toDictionary
 from common import runUntil
+from common import runFor
 
 @gearsTest()
 def testAclOnSyncFunction(env):
@@ -217,3 +218,51 @@ redis.register_stream_consumer("consumer", "", 1, false, async function(client){
     last_error = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['last_error']
     env.assertContains("can't access at least one of the keys mentioned in the command", last_error)
 
+
+@gearsTest()
+def testRestoreAsCurrentUser(env):
+    """#!js name=lib
+redis.register_function("get", function(client, key){
+    return client.call('get', key);
+})
+    """
+    payload = env.cmd('RG.FUNCTION', 'DUMP')
+    env.expect('ACL', 'SETUSER', 'alice', 'on', '>pass', '~cached:*', '+get', '+rg.function').equal('OK')
+    c = env.getConnection()
+    c.execute_command('AUTH', 'alice', 'pass')
+    c.execute_command('RG.FUNCTION', 'RESTORE', payload, 'REPLACE')
+    user = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['user']
+    env.assertEqual(user, 'alice')
+    env.cmd('set', 'x', '1')
+    try:
+        c.execute_command('RG.FUNCTION', 'CALL', 'lib', 'get', 'x')
+        env.assertTrue(False, message='Command succeeded though should have failed')
+    except Exception as e:
+        env.assertContains('acl verification failed', str(e))
+
+@gearsTest()
+def testAclOnDeadLetterStream(env):
+    script = """#!js name=lib
+var num_attempts = 0;
+redis.register_function("num_attempts", function(){
+    return num_attempts;
+});
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    num_attempts++;
+    throw 'failed processing record';
+}, {dead_letter_stream: "dlq"});
+    """
+    # alice has permissions on the dead letter stream key but can not run XADD
+    env.expect('ACL', 'SETUSER', 'alice', 'on', '>pass', '~*', '+@all', '-xadd').equal('OK')
+    c = env.getConnection()
+    c.execute_command('AUTH', 'alice', 'pass')
+    env.assertEqual(c.execute_command('RG.FUNCTION', 'LOAD', script), 'OK')
+
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    runUntil(env, 1, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_attempts'))
+    runFor(0, lambda: env.cmd('exists', 'dlq'), timeout=0.5)
+
+    # the record is written as the library user
+    env.expect('ACL', 'SETUSER', 'alice', '+xadd').equal('OK')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    runUntil(env, 1, lambda: env.cmd('xlen', 'dlq'))
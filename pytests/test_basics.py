@@ -1,6 +1,15 @@
 from common import gearsTest
 from common import toDictionary
 from common import runUntil
+from common import TimeLimit
+from common import gearsModuleArgs
+from common import rewriteAofAndLoad
+import io
+import os
+import socket
+import json
+import tempfile
+import zipfile
 
 '''
 todo:
@@ -376,3 +385,929 @@ redis.register_function("test1", function(client){
     """
     env.expect('config', 'set', 'redisgears_2.lock-redis-timeout', '1000000000').equal('OK')
     env.expect('RG.FUNCTION', 'CALL', 'lib', 'test1').error().contains('Execution was terminated due to OOM or timeout')
+
+@gearsTest()
+def testFCallWithKeys(env):
+    """#!js name=lib
+redis.register_function("set", function(client, keys, val){
+    return client.call('set', keys[0], val);
+});
+redis.register_function("get", function(client, keys){
+    return client.call('get', 'y');
+});
+redis.register_function("mget", function(client, keys){
+    return client.call('mget', ...keys);
+});
+redis.register_function("sort", function(client, keys){
+    return client.call('sort', keys[0], 'store', 'z');
+});
+redis.register_function("encoding", function(client, keys, key){
+    return client.call('object', 'encoding', key);
+});
+redis.register_function("memory", function(client, keys, key){
+    return client.call('memory', 'usage', key) > 0;
+});
+redis.register_function("xinfo", function(client, keys, key){
+    return client.call('xinfo', 'stream', key)[0];
+});
+redis.register_function("ping", function(client, keys){
+    return client.call('ping');
+});
+    """
+    env.expect('RG.FCALL', 'lib', 'set', '1', 'x', '1').equal('OK')
+    env.expect('get', 'x').equal('1')
+    env.expect('RG.FCALL', 'lib', 'get', '1', 'x').error().contains("Key 'y' was not declared as a key argument of the function")
+    env.expect('RG.FCALL', 'lib', 'set', '3', 'x').error().contains('Number of keys can not be greater than number of arguments')
+    env.expect('COMMAND', 'GETKEYS', 'RG.FCALL', 'lib', 'set', '1', 'x', '1').equal(['x'])
+    env.expect('RG.FCALL', 'lib', 'mget', '2', 'x', 'y').equal(['1', None])
+    # sort has movable keys, the store key must also be declared
+    env.expect('RG.FCALL', 'lib', 'sort', '1', 'x').error().contains("Key 'z' was not declared as a key argument of the function")
+    env.assertEqual(env.cmd('COMMAND', 'INFO', 'RG.FCALL')[0][3], 4)
+    # the keys of commands with subcommands are taken from the subcommand
+    env.expect('RG.FCALL', 'lib', 'encoding', '1', 'x', 'x').equal('int')
+    env.expect('RG.FCALL', 'lib', 'encoding', '1', 'x', 'y').error().contains("Key 'y' was not declared as a key argument of the function")
+    env.expect('RG.FCALL', 'lib', 'memory', '1', 'x', 'x').equal(True)
+    env.expect('RG.FCALL', 'lib', 'memory', '1', 'x', 'y').error().contains("Key 'y' was not declared as a key argument of the function")
+    env.cmd('XADD', 's', '*', 'foo', 'bar')
+    env.expect('RG.FCALL', 'lib', 'xinfo', '1', 's', 's').equal('length')
+    env.expect('RG.FCALL', 'lib', 'xinfo', '1', 'x', 's').error().contains("Key 's' was not declared as a key argument of the function")
+    # commands without keys are allowed
+    env.expect('RG.FCALL', 'lib', 'ping', '0').equal('PONG')
+
+@gearsTest(envArgs={'moduleArgs': gearsModuleArgs(['COMMAND', 'lib.set', 'lib', 'set', 'WRITE', 'COMMAND', 'lib.get', 'lib', 'get', 'READONLY', 'COMMAND', 'lib.set_readonly', 'lib', 'set', 'READONLY'])})
+def testFunctionCommands(env):
+    """#!js name=lib
+redis.register_function("set", function(client, keys, val){
+    return client.call('set', keys[0], val);
+});
+redis.register_function("get", function(client, keys){
+    return client.call('get', keys[0]);
+}, ['no-writes']);
+    """
+    env.expect('lib.set', '1', 'x', '1').equal('OK')
+    env.expect('lib.get', '1', 'x').equal('1')
+    env.expect('lib.get', '1', 'x', 'y').equal('1')
+    env.expect('lib.get', '2', 'x').error().contains('Number of keys can not be greater than number of arguments')
+    env.expect('COMMAND', 'GETKEYS', 'lib.set', '1', 'x', '1').equal(['x'])
+    env.assertEqual(env.cmd('COMMAND', 'INFO', 'lib.set')[0][3], 2)
+    env.assertContains('write', env.cmd('COMMAND', 'INFO', 'lib.set')[0][2])
+    env.assertContains('readonly', env.cmd('COMMAND', 'INFO', 'lib.get')[0][2])
+
+    # a read only command can not invoke a function that might perform writes
+    env.expect('lib.set_readonly', '1', 'x', '2').error().contains('might perform writes and can not be invoked by read only command lib.set_readonly')
+
+    # the commands are kept when the library is deleted and invoke the function once it is loaded again
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('lib.get', '1', 'x').error().contains('Unknown library lib')
+
+    # the commands are subject to the ACL rules like any other command
+    env.expect('ACL', 'SETUSER', 'alice', 'on', 'nopass', '~*', '&*', '+@all', '-lib.set').equal('OK')
+    env.expect('AUTH', 'alice', 'pass').equal(True)
+    env.expect('lib.set', '1', 'x', '1').error().contains('permission')
+
+def legacyModuleArgs(args):
+    # the backend path is given as the first argument, without the BACKEND keyword
+    module_args = gearsModuleArgs([])[0]
+    if len(module_args) > 0 and module_args[0].lower() == 'backend':
+        module_args = module_args[1:]
+    return [module_args + args]
+
+@gearsTest(envArgs={'moduleArgs': legacyModuleArgs(['library-path-user', 'backend'])})
+def testLegacyModuleArgsWithBackendValue(env):
+    env.expect('RG.CONFIG', 'GET', 'library-path-user').equal('backend')
+    env.assertEqual([b['name'] for b in toDictionary(env.cmd('RG.FUNCTION', 'BACKENDS'))], ['js'])
+
+@gearsTest(decodeResponses=False)
+def testBinaryData(env):
+    code = """#!js name=lib
+redis.register_function("set", function(client, key, val){
+    return client.call('set', key, val);
+}, ['raw-arguments']);
+redis.register_function("get", function(client, key){
+    return client.call_raw('get', key);
+});
+redis.register_function("len", function(client, key){
+    return new Uint8Array(client.call_raw('get', key)).length;
+});
+redis.register_function("get_string", function(client, key){
+    return client.call('get', key);
+});
+redis.register_function("echo", function(client, val){
+    return val;
+});
+redis.register_function("sub_array", function(client, key){
+    // only the bytes covered by the view are used
+    var arr = new Uint8Array(client.call_raw('get', key)).subarray(1, 3);
+    client.call('set', 'y', arr);
+    return arr;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).equal(b'OK')
+    blob = b'\x00\xff\xfe\x80'
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'set', 'x', blob).equal(b'OK')
+    env.expect('GET', 'x').equal(blob)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get', 'x').equal(blob)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'len', 'x').equal(4)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_string', 'x').error().contains("use 'call_raw' to get it as an ArrayBuffer")
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'sub_array', 'x').equal(b'\xff\xfe')
+    env.expect('GET', 'y').equal(b'\xff\xfe')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'echo', blob).error().contains('Can not convert argument to string')
+
+@gearsTest()
+def testResp3TypesDowngradedOnResp2(env):
+    """#!js name=lib
+redis.register_function("map", function(){
+    return new Map([['foo', 'bar']]);
+});
+redis.register_function("set", function(){
+    return new Set(['foo']);
+});
+redis.register_function("bool", function(){
+    return true;
+});
+redis.register_function("big_number", function(){
+    return BigInt('1234567890123456789012345678901234567890');
+});
+    """
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'map').equal(['foo', 'bar'])
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'set').equal(['foo'])
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'bool').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'big_number').equal('1234567890123456789012345678901234567890')
+
+@gearsTest()
+def testCallResp3IsOptIn(env):
+    """#!js name=lib
+redis.register_function("call_type", function(client){
+    return Array.isArray(client.call('hgetall', 'h')) ? 'array' : 'other';
+});
+redis.register_function("call_resp3_type", function(client){
+    return (client.call_resp3('hgetall', 'h') instanceof Map) ? 'map' : 'other';
+});
+redis.register_function("call_resp3", function(client){
+    return client.call_resp3('hgetall', 'h');
+});
+redis.register_function("verbatim", function(){
+    return new String('foo');
+});
+    """
+    env.cmd('HSET', 'h', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'call_type').equal('array')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'call_resp3_type').equal('map')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'call_resp3').equal(['foo', 'bar'])
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'verbatim').equal('foo')
+
+    # the reply types given to client.call do not depend on the protocol of the calling client
+    port = env.getConnection().connection_pool.connection_kwargs['port']
+    s = socket.create_connection(('localhost', port))
+    s.sendall(b'HELLO 3\r\nRG.FUNCTION CALL lib call_type\r\nRG.FUNCTION CALL lib verbatim\r\n')
+    data = b''
+    with TimeLimit(2):
+        while not data.endswith(b'=7\r\ntxt:foo\r\n'):
+            data += s.recv(1024)
+    s.close()
+    env.assertContains(b'$5\r\narray\r\n', data)
+
+@gearsTest()
+def testListBackends(env):
+    res = toDictionary(env.cmd('RG.FUNCTION', 'BACKENDS'))
+    env.assertEqual(len(res), 1)
+    env.assertEqual(res[0]['name'], 'js')
+    env.assertTrue(len(res[0]['version']) > 0)
+    env.expect('RG.FUNCTION', 'BACKENDS', 'foo').error().contains("Unknown option 'foo'")
+
+@gearsTest()
+def testInfo(env):
+    """#!js name=lib
+redis.register_function("test", function(){
+    return 1;
+});
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    return 1;
+});
+redis.register_notifications_consumer("notifications_consumer", "key", function(){
+    return 1;
+});
+    """
+    res = env.cmd('INFO', 'redisgears_2')
+    env.assertEqual(res['redisgears_2_number_of_libraries'], 1)
+    env.assertEqual(res['redisgears_2_number_of_functions'], 1)
+    env.assertEqual(res['redisgears_2_number_of_stream_consumers'], 1)
+    env.assertEqual(res['redisgears_2_number_of_notifications_consumers'], 1)
+    env.assertEqual(res['redisgears_2_running_library'], 'none')
+    env.assertEqual(res['redisgears_2_lib']['pending_jobs'], 0)
+    env.assertTrue(res['redisgears_2_lib']['used_heap_size'] > 0)
+    env.assertEqual(res['redisgears_2_lib.consumer']['pending_records'], 0)
+
+    env.expect('RG.FUNCTION', 'LOAD', "#!js name=foo:bar\nredis.register_function('test', function(){return 1;});").equal('OK')
+    res = env.cmd('INFO', 'redisgears_2')
+    env.assertEqual(res['redisgears_2_foo_bar']['engine'], 'js')
+
+@gearsTest()
+def testFunctionStats(env):
+    """#!js name=lib
+redis.register_function("test", function(client, arg){
+    if (arg == 'error') {
+        throw 'Error';
+    }
+    return 1;
+});
+redis.register_function("test_async", async function(client){
+    return 1;
+});
+    """
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test', 'foo').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test', 'error').error().contains('Error')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test_async').equal(1)
+
+    def get_stats():
+        res = toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'v'), 6)
+        return {f['name']: f for f in res[0]['functions']}
+
+    stats = get_stats()
+    env.assertEqual(stats['test']['num_calls'], 2)
+    env.assertEqual(stats['test']['num_errors'], 1)
+    env.assertEqual(stats['test']['num_sync_completions'], 2)
+    env.assertContains('Error', stats['test']['last_error'])
+    env.assertEqual(stats['test_async']['num_calls'], 1)
+    env.assertEqual(stats['test_async']['num_async_completions'], 1)
+
+    env.expect('RG.FUNCTION', 'STATS', 'RESET', 'lib').equal('OK')
+    stats = get_stats()
+    env.assertEqual(stats['test']['num_calls'], 0)
+    env.assertEqual(stats['test']['last_error'], 'None')
+    env.assertEqual(float(stats['test']['avg_exection_time_us']), 0)
+    env.expect('RG.FUNCTION', 'STATS', 'RESET', 'foo').error().contains('Unknown library foo')
+
+@gearsTest(decodeResponses=False)
+def testDumpRestore(env):
+    code = """#!js name=lib
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    code2 = """#!js name=lib2
+redis.register_function("test", function(){
+    return 2;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).equal(b'OK')
+    env.expect('RG.FUNCTION', 'LOAD', code2).equal(b'OK')
+    payload = env.cmd('RG.FUNCTION', 'DUMP')
+    lib_payload = env.cmd('RG.FUNCTION', 'DUMP', 'LIBRARY', 'lib')
+
+    env.expect('RG.FUNCTION', 'RESTORE', payload).error().contains('already exists')
+    env.expect('RG.FUNCTION', 'RESTORE', payload[:-1] + b'x').error().contains('checksum')
+
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal(b'OK')
+    env.expect('RG.FUNCTION', 'RESTORE', lib_payload).equal(b'OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+
+    # restore is all or nothing, lib2 already exists so lib should not be restored
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal(b'OK')
+    env.expect('RG.FUNCTION', 'RESTORE', payload).error().contains('Library lib2 already exists')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').error().contains('Unknown library')
+
+    env.expect('RG.FUNCTION', 'RESTORE', payload, 'REPLACE').equal(b'OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'test').equal(2)
+
+    env.expect('RG.FUNCTION', 'RESTORE', lib_payload, 'FLUSH').equal(b'OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'test').error().contains('Unknown library')
+
+@gearsTest()
+def testDeploy(env):
+    code1 = """#!js name=lib1
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    code2 = """#!js name=lib2
+redis.register_function("test", function(){
+    return 2;
+});
+    """
+    code1_upgraded = """#!js name=lib1
+redis.register_function("test", function(){
+    return 3;
+});
+    """
+    bad_code = """#!js name=lib3
+redis.register_function("test", function(){
+    return 4;
+    """
+    env.expect('RG.FUNCTION', 'DEPLOY', code1, code2).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'test').equal(2)
+
+    # one library fails, the upgrade of lib1 must be reverted
+    env.expect('RG.FUNCTION', 'DEPLOY', 'UPGRADE', code1_upgraded, bad_code).error().contains('Failed library compilation')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'CALL', 'lib3', 'test').error().contains('Unknown library')
+
+    env.expect('RG.FUNCTION', 'DEPLOY', code1_upgraded).error().contains('Library lib1 already exists')
+    env.expect('RG.FUNCTION', 'DEPLOY', 'UPGRADE', code1_upgraded, code1).error().contains('was given more than once')
+    env.expect('RG.FUNCTION', 'DEPLOY', 'UPGRADE', code1_upgraded, code2).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal(3)
+
+@gearsTest()
+def testLibraryMetadata(env):
+    """#!js name=lib version=1.2.0 author=me description="some library" api_version=1.0 min_gears_version=0.1 x-team=core
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['version'], '1.2.0')
+    env.assertEqual(res['author'], 'me')
+    env.assertEqual(res['description'], 'some library')
+    env.assertEqual(res['api_version'], '1.0')
+    env.assertEqual(res['min_gears_version'], '0.1')
+    env.assertEqual(res['properties'], {'x-team': 'core'})
+
+    downgrade = """#!js name=lib version=1.1.9
+redis.register_function("test", function(){
+    return 2;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', downgrade).error().contains('is older than the loaded version 1.2.0')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', 'FORCE', downgrade).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(2)
+
+@gearsTest()
+def testLibraryMetadataErrors(env):
+    code = """#!js name=lib %s
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code % 'foo=bar').error().contains("unknown property 'foo'")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'version=1.x').error().contains("Invalid version '1.x'")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'description="foo').error().contains("unterminated quotes")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'min_gears_version=100.0.0').error().contains("requires RedisGears version 100.0.0")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'min_gears_version=99.99.100').error().contains("requires RedisGears version 99.99.100")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'min_gears_version=18446744073709551615.0.0').error().contains("requires RedisGears version")
+    env.expect('RG.FUNCTION', 'LOAD', code % 'min_gears_version=0.0.1000000').equal('OK')
+
+@gearsTest()
+def testMinGearsVersion(env):
+    code = """#!js name=lib min_gears_version=%s
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    modules = [m if isinstance(m, dict) else toDictionary(m) for m in env.cmd('MODULE', 'LIST')]
+    version = int([m for m in modules if m['name'] == 'redisgears_2'][0]['ver'])
+    major, minor, patch = version // 10000, version // 100 % 100, version % 100
+    env.expect('RG.FUNCTION', 'LOAD', code % ('%d.%d.%d' % (major, minor, patch + 1))).error().contains("requires RedisGears version")
+    env.expect('RG.FUNCTION', 'LOAD', code % ('%d.%d' % (major, minor + 1))).error().contains("requires RedisGears version")
+    env.expect('RG.FUNCTION', 'LOAD', code % ('%d' % (major + 1))).error().contains("requires RedisGears version")
+    env.expect('RG.FUNCTION', 'LOAD', code % ('%d.%d.%d' % (major, minor, patch))).equal('OK')
+
+@gearsTest()
+def testLoadDryRun(env):
+    """#!js name=lib
+redis.register_function("test", function(){
+    return 1;
+});
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    return 1;
+});
+    """
+    code = """#!js name=%s
+redis.register_function("test", function(){
+    return 2;
+});
+redis.register_function("test2", function(){
+    return 2;
+});
+redis.register_stream_consumer("consumer", "%s", 1, false, function(){
+    return 2;
+});
+redis.register_notifications_consumer("notifications", "key", function(){
+    return 2;
+});
+    """
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LOAD', 'DRYRUN', code % ('lib1', 'foo')))
+    env.assertEqual(res['name'], 'lib1')
+    env.assertEqual(res['functions'], ['test', 'test2'])
+    env.assertEqual(res['stream_consumers'], [{'name': 'consumer', 'prefix': 'foo', 'streams_type': 'prefix', 'streams_spec': 'foo'}])
+    env.assertEqual(res['notifications_consumers'], [{'name': 'notifications', 'key': 'key'}])
+    env.assertEqual(res['conflicts'], [])
+    # nothing was loaded
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').error().contains('Unknown library')
+
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LOAD', 'DRYRUN', code % ('lib', 'stream')))
+    env.assertEqual(res['conflicts'], ['Library lib already exists'])
+
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LOAD', 'UPGRADE', 'DRYRUN', code % ('lib', 'foo')))
+    env.assertContains('Can not upgrade an existing consumer with different streams', res['conflicts'][0])
+
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LOAD', 'DRYRUN', code % ('lib1', 'stream1')))
+    env.assertContains("overlaps with stream consumer 'lib.consumer'", res['conflicts'][0])
+
+    # the existing library was not touched
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+    env.expect('RG.FUNCTION', 'LOAD', 'DRYRUN', '#!js name=lib1\n 1').error().contains('No function nor registrations was registered')
+
+@gearsTest()
+def testRollback(env):
+    code = """#!js name=lib version=%s
+redis.register_function("test", function(){
+    return '%s';
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code % ('1.0.0', '1.0.0')).equal('OK')
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib').error().contains('has no previous versions')
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib1').error().contains('Unknown library')
+
+    env.expect('CONFIG', 'SET', 'redisgears_2.library-history-size', '2').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code % ('1.1.0', '1.1.0')).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code % ('1.2.0', '1.2.0')).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code % ('1.3.0', '1.3.0')).equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual([v['version'] for v in res['previous_versions']], ['1.2.0', '1.1.0'])
+
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib', '1.0.0').error().contains('has no previous version 1.0.0')
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.2.0')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual([v['version'] for v in res['previous_versions']], ['1.1.0'])
+
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib', '1.1.0').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.1.0')
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib').error().contains('has no previous versions')
+
+@gearsTest()
+def testRollbackConfigAndMaxMemory(env):
+    code = """#!js name=lib version=%s
+redis.register_function("test", function(){
+    return redis.config.value;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'CONFIG', '{"value": 1}', 'MAXMEMORY', str(64 * 1024 * 1024), code % '1.0.0').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', 'CONFIG', '{"value": 2}', 'MAXMEMORY', str(32 * 1024 * 1024), code % '1.1.0').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(2)
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(1)
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 64 * 1024 * 1024)
+
+    # internal commands are only accepted from the master or the AOF
+    env.expect('_rg_internals.function_rollback', 'lib', code % '2.0.0').error().contains('Internal command can only be sent by the master')
+    env.expect('_rg_internals.update_stream_last_read_id', 'lib', 'consumer', 'stream', '1', '0').error().contains('Internal command can only be sent by the master')
+
+@gearsTest()
+def testSharedModules(env):
+    module = """#!js name=helpers
+export function get_value(){
+    return 'helpers_value';
+}
+    """
+    code = """#!js name=lib
+import { get_value } from 'helpers';
+redis.register_function("test", function(){
+    return get_value();
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains("Unknown module 'helpers'")
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module).error().contains('Module helpers already exists')
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('helpers_value')
+
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['modules'], ['helpers'])
+    res = toDictionary(env.cmd('RG.FUNCTION', 'MODULES'), 6)[0]
+    env.assertEqual(res['libraries'], ['lib'])
+
+    env.expect('RG.FUNCTION', 'DELMODULE', 'helpers').error().contains('Module helpers is used by libraries: lib')
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'DELMODULE', 'helpers').equal('OK')
+    env.expect('RG.FUNCTION', 'MODULES').equal([])
+
+@gearsTest()
+def testSharedModuleUpgrade(env):
+    module = """#!js name=helpers
+export function get_value(){
+    return '%s';
+}
+    """
+    code = """#!js name=%s
+import { get_value } from 'helpers';
+redis.register_function("test", function(){
+    return get_value();
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module % 'helpers_value').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code % 'lib1').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code % 'lib2').equal('OK')
+
+    # the libraries that import the module are reloaded with it
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', 'UPGRADE', module % 'other_value').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal('other_value')
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'test').equal('other_value')
+    env.expect('RG.FUNCTION', 'ROLLBACK', 'lib1').error().contains('has no previous versions')
+
+    # a module that does not compile is rejected
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', 'UPGRADE', module % "'").error().contains('Failed compiling module helpers')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal('other_value')
+
+    # a module that breaks one of the libraries is rejected and none of the libraries is reloaded
+    broken = """#!js name=helpers
+export function other_function(){
+    return 'broken_value';
+}
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', 'UPGRADE', broken).error().contains('Failed reloading the libraries that import module helpers')
+    env.expect('RG.FUNCTION', 'CALL', 'lib1', 'test').equal('other_value')
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'test').equal('other_value')
+    # the module was not replaced
+    env.expect('RG.FUNCTION', 'LOAD', code % 'lib3').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib3', 'test').equal('other_value')
+
+@gearsTest()
+def testSharedModuleDependency(env):
+    common = """#!js name=common
+export const state = {evaluations: 0};
+state.evaluations++;
+    """
+    helpers_a = """#!js name=helpers_a
+import { state } from 'common';
+export function a(){
+    return state;
+}
+    """
+    helpers_b = """#!js name=helpers_b
+import { state } from 'common';
+export function b(){
+    return state;
+}
+    """
+    code = """#!js name=lib
+import { a } from 'helpers_a';
+import { b } from 'helpers_b';
+redis.register_function("test", function(){
+    return [a() === b(), a().evaluations];
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', common).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', helpers_a).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', helpers_b).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    # the shared dependency is compiled and evaluated once
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal([1, 1])
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['modules'], ['common', 'helpers_a', 'helpers_b'])
+
+@gearsTest()
+def testCircularModules(env):
+    module_a = """#!js name=module_a
+import { b } from 'module_b';
+export function a(){
+    return 'a';
+}
+export function call_b(){
+    return b();
+}
+    """
+    module_b = """#!js name=module_b
+import { a } from 'module_a';
+export function b(){
+    return 'b' + a();
+}
+    """
+    code = """#!js name=lib
+import { call_b } from 'module_a';
+redis.register_function("test", function(){
+    return call_b();
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module_a).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module_b).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('ba')
+
+@gearsTest()
+def testLibraryWithoutImportsIsNotAModule(env):
+    # libraries that do not import modules are not compiled in strict mode
+    code = """#!js name=lib
+var self = this;
+undeclared = 'value';
+redis.register_function("test", function(){
+    return [typeof self, undeclared];
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(['object', 'value'])
+
+@gearsTest()
+def testDumpRestoreModules(env):
+    module = """#!js name=helpers
+export function get_value(){
+    return 'helpers_value';
+}
+    """
+    code = """#!js name=lib
+import { get_value } from 'helpers';
+redis.register_function("test", function(){
+    return get_value();
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    payload = env.cmd('RG.FUNCTION', 'DUMP', 'LIBRARY', 'lib')
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'DELMODULE', 'helpers').equal('OK')
+
+    env.expect('RG.FUNCTION', 'RESTORE', payload).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('helpers_value')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'MODULES'), 6)[0]
+    env.assertEqual(res['name'], 'helpers')
+    env.assertEqual(res['libraries'], ['lib'])
+
+    # an existing module with the same code is kept
+    env.expect('RG.FUNCTION', 'RESTORE', payload, 'REPLACE').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('helpers_value')
+
+    # an existing module with a different code is only replaced with REPLACE or FLUSH
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', 'UPGRADE', module.replace('helpers_value', 'other_value')).equal('OK')
+    env.expect('RG.FUNCTION', 'RESTORE', payload).error().contains('Module helpers already exists')
+    env.expect('RG.FUNCTION', 'RESTORE', payload, 'REPLACE').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('helpers_value')
+
+def createBundle(files):
+    buf = io.BytesIO()
+    with zipfile.ZipFile(buf, 'w', zipfile.ZIP_DEFLATED) as z:
+        for name, content in files.items():
+            z.writestr(name, content)
+    return buf.getvalue()
+
+@gearsTest()
+def testLibraryBundle(env):
+    manifest = {'engine': 'js', 'entry': 'index.js', 'name': 'lib', 'version': '1.0.0'}
+    files = {
+        'manifest.json': json.dumps(manifest),
+        'index.js': """
+import { add_prefix } from './utils.js';
+import config from './config.json';
+redis.register_function("test", function(client, name){
+    return add_prefix(config.prefix, name);
+});
+""",
+        'utils.js': """
+export function add_prefix(prefix, s) {
+    return prefix + s;
+}
+""",
+        'config.json': json.dumps({'prefix': 'foo_'}),
+    }
+    env.expect('RG.FUNCTION', 'LOAD', createBundle(files)).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test', 'bar').equal('foo_bar')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'WITHCODE'), 6)[0]
+    env.assertEqual(res['version'], '1.0.0')
+    env.assertTrue(res['code'].startswith('#!bundle '))
+
+    missing_entry = dict(files)
+    del missing_entry['index.js']
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', createBundle(missing_entry)).error().contains("bundle entry point 'index.js' was not found")
+
+    no_manifest = dict(files)
+    del no_manifest['manifest.json']
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', createBundle(no_manifest)).error().contains('bundle does not contain a manifest.json file')
+
+    invalid_json = dict(files)
+    invalid_json['config.json'] = '{"prefix": '
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', createBundle(invalid_json)).error().contains("bundle file 'config.json' is not a valid json")
+
+    # the files are compressed, their uncompressed size is limited
+    too_big = dict(files)
+    too_big['big.txt'] = ' ' * (64 * 1024 * 1024 + 1)
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', createBundle(too_big)).error().contains('bundle uncompressed size exceeds the limit')
+
+@gearsTest()
+def testLibraryBundleRelativeImports(env):
+    manifest = {'engine': 'js', 'entry': 'src/index.js', 'name': 'lib'}
+    files = {
+        'manifest.json': json.dumps(manifest),
+        'src/index.js': """
+import { get_value } from './lib/utils.js';
+import settings from '../config/settings.json';
+redis.register_function("test", function(){
+    return [get_value(), settings.name];
+});
+""",
+        'src/lib/utils.js': """
+import { value } from '../common/values.js';
+import { suffix } from './suffix.js';
+export function get_value() {
+    return value + suffix;
+}
+""",
+        'src/lib/suffix.js': "export const suffix = '_suffix';",
+        'src/common/values.js': "export const value = 'value';",
+        'config/settings.json': json.dumps({'name': "it's \u2028 \"quoted\""}),
+    }
+    env.expect('RG.FUNCTION', 'LOAD', createBundle(files)).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(['value_suffix', "it's \u2028 \"quoted\""])
+
+    # relative imports can not go above the bundle root
+    outside = dict(files)
+    outside['src/index.js'] = "import { value } from '../../values.js';"
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', createBundle(outside)).error().contains("Unknown module '../../values.js'")
+
+@gearsTest()
+def testLibraryConfig(env):
+    code = """#!js name=lib
+var threshold = redis.config.threshold;
+redis.on_config_changed(function(config){
+    if (typeof config.threshold !== 'number') {
+        throw 'threshold must be a number';
+    }
+    threshold = config.threshold;
+});
+redis.register_function("threshold", function(){
+    return threshold;
+});
+redis.register_function("prefix", function(){
+    return redis.config.prefix;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', 'CONFIG', '[1]', code).error().contains('Library configuration must be a json object')
+    env.expect('RG.FUNCTION', 'LOAD', 'CONFIG', '{"threshold": 10, "prefix": "foo"}', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'threshold').equal(10)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'prefix').equal('foo')
+
+    env.expect('RG.FUNCTION', 'CONFIG', 'SET', 'lib', 'threshold', '20').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'threshold').equal(20)
+    env.expect('RG.FUNCTION', 'CONFIG', 'SET', 'lib', 'threshold', 'bar').error().contains('threshold must be a number')
+    env.expect('RG.FUNCTION', 'CONFIG', 'GET', 'lib', 'threshold').equal(20)
+    env.expect('RG.FUNCTION', 'CONFIG', 'SET', 'lib', 'prefix', 'bar').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'prefix').equal('bar')
+    env.expect('RG.FUNCTION', 'CONFIG', 'GET', 'lib').equal(['prefix', 'bar', 'threshold', 20])
+    env.expect('RG.FUNCTION', 'CONFIG', 'GET', 'lib1').error().contains('Unknown library lib1')
+
+    # upgrade keeps the configuration
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'threshold').equal(20)
+
+    env.expect('DEBUG', 'RELOAD').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'threshold').equal(20)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'prefix').equal('bar')
+
+@gearsTest()
+def testLibraryMaxMemory(env):
+    code = """#!js name=lib maxmemory=33554432
+redis.register_function("test", function(){
+    return 1;
+});
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 32 * 1024 * 1024)
+    env.assertGreater(res['used_memory'], 0)
+
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', 'MAXMEMORY', str(64 * 1024 * 1024), code).equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 64 * 1024 * 1024)
+
+    # the limit given on load is kept on upgrade and on reload
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code).equal('OK')
+    env.expect('DEBUG', 'RELOAD').equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 64 * 1024 * 1024)
+
+    # the library property can not raise the configured limit (1G by default)
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', code.replace('33554432', str(2 * 1024 * 1024 * 1024))).equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 1024 * 1024 * 1024)
+
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', 'MAXMEMORY', '1024', code).error().contains("Invalid maxmemory '1024'")
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', code.replace('33554432', 'foo')).error().contains("Invalid maxmemory 'foo'")
+
+def libraryPathEnvArgs(files, module_args=[], config_lines=[]):
+    # the library-path can only be set on startup, so it is given on the module arguments
+    path = tempfile.mkdtemp()
+    for name, content in files.items():
+        with open(os.path.join(path, name), 'wb') as f:
+            f.write(content if isinstance(content, bytes) else content.encode())
+    env_args = {'moduleArgs': gearsModuleArgs(['library-path', path] + module_args)}
+    if len(config_lines) > 0:
+        config_file = os.path.join(path, 'redis.conf')
+        with open(config_file, 'w') as f:
+            f.write('\n'.join(config_lines) + '\n')
+        env_args['redisConfigFile'] = config_file
+    return env_args
+
+LIBRARY_PATH_CODE = """#!js name=%s version=%s
+redis.register_function("test", function(){
+    return '%s';
+});
+"""
+
+@gearsTest(skipOnCluster=True, envArgs=libraryPathEnvArgs({
+    '1_lib.js': LIBRARY_PATH_CODE % ('lib', '1.0.0', '1.0.0'),
+    '2_invalid.js': '#!js name=invalid\nredis.register_function("test", function(){',
+    '3_not_utf8.js': b'#!js name=not_utf8\n\xff\xfe',
+    '4_lib.js': LIBRARY_PATH_CODE % ('lib4', '1.0.0', '4'),
+    'lib.txt': '#!js name=txt\nredis.register_function("test", function(){return 1;});',
+}, ['library-path-user', 'alice'], ['user alice on nopass ~* &* +@all']))
+def testLibraryPathLoadOnStart(env):
+    def get_config(name):
+        res = env.cmd('CONFIG', 'GET', name)
+        return res[name] if isinstance(res, dict) else res[1]
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)
+    env.assertEqual(sorted([l['name'] for l in res]), ['lib', 'lib4'])
+    env.assertEqual([l['user'] for l in res], ['alice', 'alice'])
+
+    # files that fail to load are reported on the log and skipped, the files after them are loaded
+    env.expect('RG.FUNCTION', 'CALL', 'lib4', 'test').equal('4')
+    log_file = os.path.join(get_config('dir'), get_config('logfile'))
+    with open(log_file) as f:
+        log = f.read()
+        env.assertContains("Failed loading library from '%s'" % os.path.join(get_config('redisgears_2.library-path'), '2_invalid.js'), log)
+        env.assertContains("Failed reading library file '%s'" % os.path.join(get_config('redisgears_2.library-path'), '3_not_utf8.js'), log)
+
+    # the files are loaded again after the RDB is loaded
+    env.expect('RG.FUNCTION', 'DEL', 'lib').equal('OK')
+    env.expect('DEBUG', 'RELOAD').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+
+# the library on the RDB is loaded first, then the library-path file conflicts with it
+def loadLibraryPathConflict(env, code):
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', 'FORCE', code).equal('OK')
+    env.expect('DEBUG', 'RELOAD').equal('OK')
+
+@gearsTest(skipOnCluster=True, envArgs=libraryPathEnvArgs({'lib.js': LIBRARY_PATH_CODE % ('lib', '1.0.0', '1.0.0')}))
+def testLibraryPathConflictPolicyKeep(env):
+    # keep is the default, the library from the RDB is kept
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '2.0.0', '2.0.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('2.0.0')
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '0.1.0', '0.1.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('0.1.0')
+
+@gearsTest(skipOnCluster=True, envArgs=libraryPathEnvArgs({'lib.js': LIBRARY_PATH_CODE % ('lib', '1.0.0', '1.0.0')}, ['library-path-conflict-policy', 'upgrade']))
+def testLibraryPathConflictPolicyUpgrade(env):
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+    # a newer library from the RDB is not downgraded
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '2.0.0', '2.0.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('2.0.0')
+    # an older library from the RDB is upgraded
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '0.1.0', '0.1.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+
+@gearsTest(skipOnCluster=True, envArgs=libraryPathEnvArgs({'lib.js': LIBRARY_PATH_CODE % ('lib', '1.0.0', '1.0.0')}, ['library-path-conflict-policy', 'replace']))
+def testLibraryPathConflictPolicyReplace(env):
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+    # the library from the RDB is replaced even with an older version
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '2.0.0', '2.0.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('1.0.0')
+    # the policy can be changed at runtime
+    env.expect('CONFIG', 'SET', 'redisgears_2.library-path-conflict-policy', 'keep').equal('OK')
+    loadLibraryPathConflict(env, LIBRARY_PATH_CODE % ('lib', '2.0.0', '2.0.0'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal('2.0.0')
+
+def aofRewrite(env, preamble):
+    module = """#!js name=helpers
+export function get_value(){
+    return 'helpers_value';
+}
+    """
+    code = """#!js name=lib
+import { get_value } from 'helpers';
+redis.register_function("test", function(){
+    return [get_value(), redis.config.value];
+});
+    """
+    env.expect('CONFIG', 'SET', 'aof-use-rdb-preamble', preamble).equal('OK')
+    env.expect('CONFIG', 'SET', 'appendonly', 'yes').equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'MODULE', module).equal('OK')
+    env.expect('RG.FUNCTION', 'LOAD', 'CONFIG', '{"value": 1}', 'MAXMEMORY', str(64 * 1024 * 1024), code).equal('OK')
+    env.cmd('SET', 'x', '1')
+
+    rewriteAofAndLoad(env)
+
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(['helpers_value', 1])
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST'), 6)[0]
+    env.assertEqual(res['maxmemory'], 64 * 1024 * 1024)
+    env.assertEqual(res['modules'], ['helpers'])
+    # the key that emits the libraries is only added on the rewrite process
+    env.expect('KEYS', '*').equal(['x'])
+
+    # the libraries that were loaded from the rewritten AOF are kept on the next rewrite
+    rewriteAofAndLoad(env)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'test').equal(['helpers_value', 1])
+    env.expect('KEYS', '*').equal(['x'])
+
+@gearsTest()
+def testAofRewriteWithoutRdbPreamble(env):
+    aofRewrite(env, 'no')
+
+@gearsTest()
+def testAofRewriteWithRdbPreamble(env):
+    aofRewrite(env, 'yes')
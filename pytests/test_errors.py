@@ -1,4 +1,5 @@
 from common import gearsTest
+from common import toDictionary
 
 @gearsTest()
 def testWrongEngine(env):
@@ -112,3 +113,40 @@ test();
 redis.register_function('test', test);
     """
     env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', script).error().contains("Maximum call stack size exceeded")
+
+@gearsTest()
+def testErrorCodePrefix(env):
+    """#!js name=foo
+redis.register_function("test1", function(client){
+    while (true);
+});
+redis.register_function("test2", function(client){
+    throw new Error("some error");
+});
+    """
+    env.expect('config', 'set', 'redisgears_2.lock-redis-timeout', '100').equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'foo', 'test1').error().contains('TIMEOUT Execution was terminated due to OOM or timeout')
+    env.expect('RG.FUNCTION', 'CALL', 'foo', 'test2').error().contains('some error')
+
+@gearsTest()
+def testCompilationErrorPrefix(env):
+    script = '''#!js name=foo
+redis.register_function("test", function(client){
+    return 2
+})}
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', 'UPGRADE', script).error().contains('COMPILE Failed library compilation')
+
+@gearsTest()
+def testErrorLocation(env):
+    """#!js name=foo
+redis.register_function("test", function(client){
+    throw new Error("some error");
+});
+    """
+    env.expect('RG.FUNCTION', 'CALL', 'foo', 'test').error().contains('some error (line 3, column 11)')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'v'), 6)
+    stats = res[0]['functions'][0]
+    env.assertContains('(line 3, column 11)', stats['last_error'])
+    env.assertEqual('exception', stats['last_error_kind'])
+    env.assertContains('Error: some error', stats['last_error_stack_trace'])
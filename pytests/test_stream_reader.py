@@ -3,6 +3,7 @@ from common import TimeLimit
 from common import toDictionary
 from common import runUntil
 from common import runFor
+from common import rewriteAofAndLoad
 import time
 
 '''
@@ -72,7 +73,7 @@ redis.register_stream_consumer("consumer", "stream", 1, false, function(){
     """
     env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
     res = toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'vv'), 6)
-    env.assertEqual('Error', res[0]['stream_consumers'][0]['streams'][0]['last_error'])
+    env.assertEqual('ERR Error', res[0]['stream_consumers'][0]['streams'][0]['last_error'])
 
 @gearsTest()
 def testStreamWindow(env):
@@ -399,6 +400,43 @@ redis.register_stream_consumer("consumer", "stream", 1, false, async function(cl
 
     env.assertEqual(id_to_read_from1, id_to_read_from2)
 
+@gearsTest()
+def testAofRewriteWithoutRdbPreamble(env):
+    """#!js name=lib
+var num_events = 0;
+redis.register_function("num_events", function(){
+    return num_events;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    num_events++;
+})
+    """
+    env.expect('CONFIG', 'SET', 'aof-use-rdb-preamble', 'no').equal('OK')
+    env.expect('CONFIG', 'SET', 'appendonly', 'yes').equal('OK')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:2', '*', 'foo', 'bar')
+    runUntil(env, 3, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'))
+    env.expect('RG.FUNCTION', 'STREAM', 'PAUSE', 'lib', 'consumer', 'stream:2').equal('OK')
+
+    def ids_to_read_from():
+        streams = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams']
+        return {s['name']: s['id_to_read_from'] for s in streams}
+
+    ids_to_read_from1 = ids_to_read_from()
+
+    rewriteAofAndLoad(env)
+
+    env.assertEqual(ids_to_read_from1, ids_to_read_from())
+
+    # the records that were already processed are not processed again
+    runFor(0, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'), timeout=0.5)
+    # the paused stream stays paused
+    env.cmd('xadd', 'stream:2', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    runUntil(env, 1, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'))
+    runFor(1, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'), timeout=0.5)
+
 @gearsTest()
 def testCallingRedisCommandOnStreamConsumer(env):
     """#!js name=lib
@@ -455,3 +493,281 @@ redis.register_stream_consumer("consumer", "stream", 1, true, async function(cli
     env.assertEqual(2, res)
 
     env.cmd('slaveof', 'no', 'one')
+
+@gearsTest()
+def testStreamConsumerStartPosition(env):
+    """#!js name=lib
+var num_events = 0;
+redis.register_function("num_events", function(){
+    return num_events;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    num_events++;
+}, {start: "$"})
+    """
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'num_events').equal(0)
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'num_events').equal(1)
+    env.cmd('xadd', 'stream:2', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:2', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'num_events').equal(3)
+
+    # a new library starting from '$' should not process the existing elements
+    code = '''#!js name=lib2
+var num_events = 0;
+redis.register_function("num_events", function(){
+    return num_events;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    num_events++;
+}, {start: "$"})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'num_events').equal(0)
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib2', 'num_events').equal(1)
+
+    # seek back to the beginning of the stream and reprocess it
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib2', 'consumer', 'stream:2', '0-0').equal('OK')
+    runUntil(env, 3, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib2', 'num_events'))
+
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib2', 'consumer', 'foo', '0-0').error().contains('does not match the prefix')
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib2', 'consumer1', 'stream:2', '0-0').error().contains('Unknown stream consumer')
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib2', 'consumer', 'stream:2', 'foo').error().contains('Invalid stream id')
+
+@gearsTest()
+def testStreamConsumerStartPositionExplicitIds(env):
+    # '$' is resolved from the stream last entry, the same as SEEK '$', and not from the current time
+    env.cmd('xadd', 'stream:1', '99999999999999-1', 'foo', 'bar')
+    code = """#!js name=lib
+var ids = [];
+redis.register_function("ids", function(){
+    return ids;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(c, data){
+    ids.push(data.id[0].toString() + "-" + data.id[1].toString());
+}, {start: "$"})
+    """
+    env.expect('RG.FUNCTION', 'LOAD', code).equal('OK')
+    runUntil(env, '99999999999999-1', lambda: toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['id_to_read_from'])
+    env.cmd('xadd', 'stream:1', '99999999999999-2', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'ids').equal(['99999999999999-2'])
+
+    # a stream that is created after the consumer was registered is processed entirely
+    env.cmd('xadd', 'stream:2', '1-1', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'ids').equal(['99999999999999-2', '1-1'])
+
+@gearsTest(envArgs={'useSlaves': True})
+def testStreamConsumerSeekWhileProcessing(env):
+    """#!js name=lib
+var resolves = [];
+var ids = [];
+redis.register_function("ids", function(){
+    return ids;
+})
+redis.register_function("release", function(){
+    resolves.forEach((r) => r());
+    resolves = [];
+    return "OK";
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, async function(c, data){
+    ids.push(data.id[0].toString() + "-" + data.id[1].toString());
+    await new Promise((resolve, reject) => {
+        resolves.push(resolve);
+    });
+})
+    """
+    slave_conn = env.getSlaveConnection()
+    env.expect('WAIT', '1', '7000').equal(1)
+
+    env.cmd('xadd', 'stream:1', '1-1', 'foo', 'bar')
+    runUntil(env, ['1-1'], lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'ids'))
+    env.cmd('xadd', 'stream:1', '2-1', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib', 'consumer', 'stream:1', '$').equal('OK')
+    res = toDictionary(env.cmd('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]
+    env.assertEqual(res['pending_ids'], [])
+    env.assertEqual(res['id_to_read_from'], '2-1')
+
+    # the in flight record is forgotten, it does not hold the window
+    env.cmd('xadd', 'stream:1', '3-1', 'foo', 'bar')
+    runUntil(env, ['1-1', '3-1'], lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'ids'))
+
+    # the ack of the record that was processed before the seek does not move the replica back
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'release').equal('OK')
+    env.expect('WAIT', '1', '7000').equal(1)
+    res = toDictionary(slave_conn.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]
+    env.assertEqual(res['id_to_read_from'], '3-1')
+
+@gearsTest()
+def testStreamConsumerInvalidOptions(env):
+    code = '''#!js name=lib
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){}, {foo: "bar"})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains("Unknown option 'foo'")
+    code = '''#!js name=lib
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){}, {start: "foo"})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains("Invalid start position 'foo'")
+
+@gearsTest()
+def testStreamConsumerBatch(env):
+    """#!js name=lib
+var batches = [];
+redis.register_function("get_batches", function(){
+    return batches;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(c, batch){
+    batches.push(batch.length);
+}, {batch_size: 3, batch_max_wait: 500})
+    """
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_batches').equal([3])
+
+    # a partial batch is processed once the max wait time passed
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_batches').equal([3])
+    runUntil(env, [3, 1], lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'get_batches'))
+    runUntil(env, 4, lambda: toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['total_record_processed'])
+
+    # a partial batch that fills up is processed as a single batch
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_batches').equal([3, 1])
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_batches').equal([3, 1, 3])
+    runUntil(env, 7, lambda: toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['total_record_processed'])
+
+@gearsTest()
+def testStreamConsumerBatchPerRecordAck(env):
+    """#!js name=lib
+redis.register_stream_consumer("consumer", "stream", 1, false, async function(c, batch){
+    return batch.map((data) => data.record[0][1] == 'bar' ? true : 'bad value ' + data.record[0][1]);
+}, {batch_size: 2})
+    """
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'baz')
+    runUntil(env, 2, lambda: toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['total_record_processed'])
+    env.assertContains('bad value baz', toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['last_error'])
+
+    code = '''#!js name=lib2
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){}, {batch_max_wait: 10})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains('batch_max_wait can only be set together with batch_size')
+
+@gearsTest()
+def testStreamConsumerRetryAndDeadLetter(env):
+    """#!js name=lib
+var num_attempts = 0;
+redis.register_function("num_attempts", function(){
+    return num_attempts;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(c, data){
+    num_attempts++;
+    if (data.record[0][1] == 'fail') {
+        throw 'failed processing record';
+    }
+}, {max_attempts: 3, retry_backoff: 10, dead_letter_stream: "dlq"})
+    """
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'fail')
+    runUntil(env, 1, lambda: env.cmd('xlen', 'dlq'))
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'num_attempts').equal(3)
+
+    record = toDictionary(env.cmd('xrange', 'dlq', '-', '+')[0][1])
+    env.assertEqual(record['library'], 'lib')
+    env.assertEqual(record['consumer'], 'consumer')
+    env.assertEqual(record['stream'], 'stream:1')
+    env.assertEqual(record['attempts'], '3')
+    env.assertContains('failed processing record', record['error'])
+    env.assertEqual(record['foo'], 'fail')
+
+    stream_info = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]
+    env.assertEqual(stream_info['records_retried'], 2)
+    env.assertEqual(stream_info['records_dead_lettered'], 1)
+
+    # a successful record is not retried
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    runUntil(env, 4, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_attempts'))
+    env.expect('xlen', 'dlq').equal(1)
+
+    code = '''#!js name=lib2
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){}, {dead_letter_stream: "stream:dlq"})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains("can not match the consumer prefix")
+
+@gearsTest()
+def testStreamConsumerPauseResumeReset(env):
+    """#!js name=lib
+var num_events = 0;
+redis.register_function("num_events", function(){
+    return num_events;
+})
+redis.register_stream_consumer("consumer", "stream", 1, false, function(){
+    num_events++;
+})
+    """
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    runUntil(env, 1, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'))
+
+    env.expect('RG.FUNCTION', 'STREAM', 'PAUSE', 'lib', 'consumer').equal('OK')
+    env.cmd('xadd', 'stream:1', '*', 'foo', 'bar')
+    time.sleep(0.5)
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'num_events').equal(1)
+
+    # paused state is kept after reload
+    env.expect('DEBUG', 'RELOAD').equal('OK')
+    consumer_info = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]
+    env.assertEqual(consumer_info['state'], 'paused')
+
+    env.expect('RG.FUNCTION', 'STREAM', 'RESUME', 'lib', 'consumer').equal('OK')
+    # the library was reloaded so the counter starts from 0
+    runUntil(env, 1, lambda: env.cmd('RG.FUNCTION', 'CALL', 'lib', 'num_events'))
+    runUntil(env, 1, lambda: toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]['total_record_processed'])
+
+    env.expect('RG.FUNCTION', 'STREAM', 'RESET', 'lib', 'consumer', 'stream:1').equal('OK')
+    stream_info = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 6)[0]['stream_consumers'][0]['streams'][0]
+    env.assertEqual(stream_info['total_record_processed'], 0)
+    env.assertEqual(stream_info['state'], 'running')
+
+    env.expect('RG.FUNCTION', 'STREAM', 'PAUSE', 'lib', 'consumer', 'foo:1').error().contains('does not match the prefix')
+    env.expect('RG.FUNCTION', 'STREAM', 'FOO', 'lib', 'consumer').error().contains('Unknown subcommand')
+
+@gearsTest()
+def testStreamConsumerPatternAndStreams(env):
+    """#!js name=lib
+var streams = [];
+redis.register_function("get_streams", function(){
+    return streams;
+})
+redis.register_stream_consumer("pattern_consumer", {pattern: "orders:{*}:events"}, 1, false, function(c, data){
+    streams.push(data.stream_name);
+})
+redis.register_stream_consumer("streams_consumer", ["payments", "refunds"], 1, false, function(c, data){
+    streams.push(data.stream_name);
+})
+    """
+    env.cmd('xadd', 'orders:{1}:events', '*', 'foo', 'bar')
+    env.cmd('xadd', 'orders:{1}:other', '*', 'foo', 'bar')
+    env.cmd('xadd', 'payments', '*', 'foo', 'bar')
+    env.cmd('xadd', 'payments:1', '*', 'foo', 'bar')
+    env.cmd('xadd', 'refunds', '*', 'foo', 'bar')
+    env.expect('RG.FUNCTION', 'CALL', 'lib', 'get_streams').equal(['orders:{1}:events', 'payments', 'refunds'])
+
+    # stop at the consumer level so the streams list is not turned into a dictionary
+    consumers = toDictionary(env.execute_command('RG.FUNCTION', 'LIST', 'vvv'), 4)[0]['stream_consumers']
+    consumers = {c['name']: c for c in consumers}
+    env.assertEqual(consumers['pattern_consumer']['prefix'], None)
+    env.assertEqual(consumers['pattern_consumer']['streams_type'], 'pattern')
+    env.assertEqual(consumers['pattern_consumer']['streams_spec'], 'orders:{*}:events')
+    env.assertEqual(consumers['streams_consumer']['prefix'], None)
+    env.assertEqual(consumers['streams_consumer']['streams_type'], 'streams')
+    env.assertEqual(consumers['streams_consumer']['streams_spec'], ['payments', 'refunds'])
+    env.assertEqual(sorted([toDictionary(s)['name'] for s in consumers['streams_consumer']['streams']]), ['payments', 'refunds'])
+
+    env.expect('RG.FUNCTION', 'STREAM', 'SEEK', 'lib', 'streams_consumer', 'payments:1', '0-0').error().contains('does not match the streams')
+
+    code = '''#!js name=lib2
+redis.register_stream_consumer("consumer", [], 1, false, function(){})
+    '''
+    env.expect('RG.FUNCTION', 'LOAD', code).error().contains("can not be an empty array")
@@ -1,6 +1,6 @@
 use redisgears_plugin_api::redisgears_plugin_api::{
     run_function_ctx::BackgroundRunFunctionCtxInterface, run_function_ctx::RedisClientCtxInterface,
-    GearsApiError,
+    GearsApiError, GearsApiErrorKind,
 };
 
 use crate::background_run_scope_guard::BackgroundRunScopeGuardCtx;
@@ -33,13 +33,14 @@ impl BackgroundRunFunctionCtxInterface for BackgroundRunCtx {
     fn lock<'a>(&'a self) -> Result<Box<dyn RedisClientCtxInterface>, GearsApiError> {
         let ctx_guard = ThreadSafeContext::new().lock();
         if !verify_ok_on_replica(self.call_options.flags) {
-            return Err(GearsApiError::Msg(
+            return Err(GearsApiError::new(
                 "Can not lock redis for write on replica".to_string(),
             ));
         }
         if !verify_oom(self.call_options.flags) {
-            return Err(GearsApiError::Msg(
-                "OOM Can not lock redis for write".to_string(),
+            return Err(GearsApiError::new_with_kind(
+                GearsApiErrorKind::OutOfMemory,
+                "Can not lock redis for write".to_string(),
             ));
         }
         Ok(Box::new(BackgroundRunScopeGuardCtx::new(
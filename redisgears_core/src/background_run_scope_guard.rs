@@ -37,13 +37,12 @@ impl BackgroundRunScopeGuardCtx {
 }
 
 impl RedisClientCtxInterface for BackgroundRunScopeGuardCtx {
-    fn call(&self, command: &str, args: &[&str]) -> CallResult {
-        call_redis_command(
-            self.user.as_ref(),
-            command,
-            &self.call_options.call_options,
-            args,
-        )
+    fn call(&self, command: &str, args: &[&[u8]]) -> CallResult {
+        call_redis_command(self.user.as_ref(), command, &self.call_options, false, args)
+    }
+
+    fn call_resp3(&self, command: &str, args: &[&[u8]]) -> CallResult {
+        call_redis_command(self.user.as_ref(), command, &self.call_options, true, args)
     }
 
     fn get_background_redis_client(&self) -> Box<dyn BackgroundRunFunctionCtxInterface> {
@@ -0,0 +1,163 @@
+use redis_module::RedisError;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+// A bundle is kept as a string so it can be persisted and replicated the same
+// as any other library code: '#!bundle <base64 encoded zip payload>'.
+const BUNDLE_CODE_PREFIX: &str = "#!bundle ";
+const BUNDLE_MANIFEST: &str = "manifest.json";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+// limits the total uncompressed size of the bundle files, the sizes that are
+// written on the zip headers can not be trusted.
+const BUNDLE_MAX_UNCOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+pub(crate) struct LibraryBundle {
+    pub(crate) engine: String,
+    pub(crate) entry: String,
+    pub(crate) entry_code: String,
+    // all the properties from the manifest other than the engine and the entry
+    pub(crate) properties: Vec<(String, String)>,
+    // source files and assets by their path on the bundle, without the entry point
+    pub(crate) files: HashMap<String, String>,
+}
+
+pub(crate) fn is_zip_payload(payload: &[u8]) -> bool {
+    payload.starts_with(ZIP_MAGIC)
+}
+
+pub(crate) fn is_bundle_code(code: &str) -> bool {
+    code.starts_with(BUNDLE_CODE_PREFIX)
+}
+
+pub(crate) fn bundle_code_from_payload(payload: &[u8]) -> String {
+    format!("{}{}", BUNDLE_CODE_PREFIX, base64::encode(payload))
+}
+
+fn read_manifest_string(
+    manifest: &mut serde_json::Map<String, serde_json::Value>,
+    name: &str,
+) -> Result<String, RedisError> {
+    match manifest.remove(name) {
+        Some(serde_json::Value::String(s)) => Ok(s),
+        Some(_) => Err(RedisError::String(format!(
+            "bundle manifest property '{}' must be a string",
+            name
+        ))),
+        None => Err(RedisError::String(format!(
+            "bundle manifest is missing the '{}' property",
+            name
+        ))),
+    }
+}
+
+pub(crate) fn parse_bundle(code: &str) -> Result<LibraryBundle, RedisError> {
+    let payload = base64::decode(code.strip_prefix(BUNDLE_CODE_PREFIX).unwrap_or(code))
+        .map_err(|e| RedisError::String(format!("Failed decoding bundle, {}", e)))?;
+    let mut archive = zip::ZipArchive::new(Cursor::new(payload))
+        .map_err(|e| RedisError::String(format!("Failed reading bundle, {}", e)))?;
+
+    let mut files = HashMap::new();
+    let mut total_size = 0;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| RedisError::String(format!("Failed reading bundle, {}", e)))?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        let mut content = Vec::new();
+        (&mut file)
+            .take(BUNDLE_MAX_UNCOMPRESSED_SIZE - total_size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| {
+                RedisError::String(format!("Failed reading bundle file '{}', {}", name, e))
+            })?;
+        total_size += content.len() as u64;
+        if total_size > BUNDLE_MAX_UNCOMPRESSED_SIZE {
+            return Err(RedisError::String(format!(
+                "bundle uncompressed size exceeds the limit of {} bytes",
+                BUNDLE_MAX_UNCOMPRESSED_SIZE
+            )));
+        }
+        let content = String::from_utf8(content).map_err(|e| {
+            RedisError::String(format!("Failed reading bundle file '{}', {}", name, e))
+        })?;
+        // json assets are validated here so a broken asset fails the load with a clear error
+        if name.ends_with(".json") && name != BUNDLE_MANIFEST {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(&content) {
+                return Err(RedisError::String(format!(
+                    "bundle file '{}' is not a valid json, {}",
+                    name, e
+                )));
+            }
+        }
+        files.insert(name, content);
+    }
+
+    let manifest = match files.remove(BUNDLE_MANIFEST) {
+        Some(m) => m,
+        None => {
+            return Err(RedisError::Str(
+                "bundle does not contain a manifest.json file",
+            ))
+        }
+    };
+    let mut manifest = match serde_json::from_str(&manifest) {
+        Ok(serde_json::Value::Object(m)) => m,
+        Ok(_) => return Err(RedisError::Str("bundle manifest must be a json object")),
+        Err(e) => {
+            return Err(RedisError::String(format!(
+                "Failed parsing bundle manifest, {}",
+                e
+            )))
+        }
+    };
+    let engine = read_manifest_string(&mut manifest, "engine")?;
+    let entry = read_manifest_string(&mut manifest, "entry")?;
+    let entry_code = match files.remove(&entry) {
+        Some(c) => c,
+        None => {
+            return Err(RedisError::String(format!(
+                "bundle entry point '{}' was not found",
+                entry
+            )))
+        }
+    };
+    let properties = manifest
+        .into_iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(v) => Ok((k, v)),
+            _ => Err(RedisError::String(format!(
+                "bundle manifest property '{}' must be a string",
+                k
+            ))),
+        })
+        .collect::<Result<Vec<(String, String)>, RedisError>>()?;
+
+    Ok(LibraryBundle {
+        engine: engine,
+        entry: entry,
+        entry_code: entry_code,
+        properties: properties,
+        files: files,
+    })
+}
+
+// Resolves a relative import ('./' or '../') against the directory of the importing
+// bundle file, None if the path goes above the bundle root.
+pub(crate) fn resolve_bundle_path(referrer: &str, name: &str) -> Option<String> {
+    let mut path = referrer.split('/').collect::<Vec<&str>>();
+    // the file name of the referrer
+    path.pop();
+    for part in name.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                path.pop()?;
+            }
+            p => path.push(p),
+        }
+    }
+    Some(path.join("/"))
+}
@@ -1,16 +1,21 @@
+use crate::bundle::resolve_bundle_path;
 use crate::{execute_on_pool, get_ctx, get_globals};
 use redisgears_plugin_api::redisgears_plugin_api::backend_ctx::CompiledLibraryInterface;
-use std::collections::LinkedList;
+use std::collections::{HashMap, HashSet, LinkedList};
 use std::sync::{Arc, Mutex};
 
 pub(crate) struct CompiledLibraryInternals {
     mutex: Mutex<LinkedList<Box<dyn FnOnce() + Send>>>,
+    used_modules: Mutex<HashSet<String>>,
+    maxmemory: usize,
 }
 
 impl CompiledLibraryInternals {
-    fn new() -> CompiledLibraryInternals {
+    fn new(maxmemory: usize) -> CompiledLibraryInternals {
         CompiledLibraryInternals {
             mutex: Mutex::new(LinkedList::new()),
+            used_modules: Mutex::new(HashSet::new()),
+            maxmemory: maxmemory,
         }
     }
 
@@ -51,16 +56,48 @@ impl CompiledLibraryInternals {
         let queue = self.mutex.lock().unwrap();
         queue.len()
     }
+
+    pub(crate) fn maxmemory(&self) -> usize {
+        self.maxmemory
+    }
+
+    pub(crate) fn uses_module(&self, name: &str) -> bool {
+        self.used_modules.lock().unwrap().contains(name)
+    }
+
+    pub(crate) fn used_modules(&self) -> Vec<String> {
+        let mut modules = self
+            .used_modules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>();
+        modules.sort();
+        modules
+    }
 }
 
 pub(crate) struct CompiledLibraryAPI {
     internals: Arc<CompiledLibraryInternals>,
+    engine: String,
+    // path of the entry point on the bundle, relative imports of the library are resolved from it
+    bundle_entry: String,
+    bundle_files: HashMap<String, String>,
 }
 
 impl CompiledLibraryAPI {
-    pub(crate) fn new() -> CompiledLibraryAPI {
+    pub(crate) fn new(
+        engine: &str,
+        bundle_entry: String,
+        bundle_files: HashMap<String, String>,
+        maxmemory: usize,
+    ) -> CompiledLibraryAPI {
         CompiledLibraryAPI {
-            internals: Arc::new(CompiledLibraryInternals::new()),
+            internals: Arc::new(CompiledLibraryInternals::new(maxmemory)),
+            engine: engine.to_string(),
+            bundle_entry: bundle_entry,
+            bundle_files: bundle_files,
         }
     }
 
@@ -83,6 +120,31 @@ impl CompiledLibraryInterface for CompiledLibraryAPI {
     }
 
     fn get_maxmemory(&self) -> usize {
-        get_globals().config.library_maxmemory.size as usize
+        self.internals.maxmemory
+    }
+
+    fn get_module(&self, name: &str, referrer: Option<&str>) -> Option<(String, String)> {
+        // relative imports are files of the library bundle, resolved from the
+        // directory of the importing file, the resolved names keep the './' prefix
+        // so they can not be mistaken for the shared modules.
+        if name.starts_with("./") || name.starts_with("../") {
+            let referrer = match referrer {
+                Some(r) => r.strip_prefix("./")?,
+                None => self.bundle_entry.as_str(),
+            };
+            let path = resolve_bundle_path(referrer, name)?;
+            let code = self.bundle_files.get(&path)?;
+            return Some((format!("./{}", path), code.to_string()));
+        }
+        let module = get_globals()
+            .modules
+            .get(name)
+            .filter(|m| m.meta_data.engine == self.engine)?;
+        self.internals
+            .used_modules
+            .lock()
+            .unwrap()
+            .insert(name.to_string());
+        Some((name.to_string(), module.meta_data.code.to_string()))
     }
 }
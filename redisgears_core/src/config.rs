@@ -294,12 +294,252 @@ impl RedisNumberConfigCtx for LockRedisTimeout {
     }
 }
 
+pub(crate) struct LibraryHistorySize {
+    pub(crate) size: usize,
+    flags: ConfigFlags,
+}
+
+impl LibraryHistorySize {
+    fn new() -> LibraryHistorySize {
+        LibraryHistorySize {
+            size: 1,
+            flags: ConfigFlags::new(),
+        }
+    }
+}
+
+impl fmt::Display for LibraryHistorySize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.size)
+    }
+}
+
+impl RedisConfigCtx for LibraryHistorySize {
+    fn name(&self) -> &'static str {
+        "library-history-size"
+    }
+
+    fn apply(&self, _ctx: &Context) -> Result<(), RedisError> {
+        Ok(())
+    }
+
+    fn flags(&self) -> &ConfigFlags {
+        &self.flags
+    }
+}
+
+impl RedisNumberConfigCtx for LibraryHistorySize {
+    fn default(&self) -> i64 {
+        1
+    }
+
+    fn min(&self) -> i64 {
+        0
+    }
+    fn max(&self) -> i64 {
+        100
+    }
+
+    fn get(&self, _name: &str) -> i64 {
+        self.size as i64
+    }
+
+    fn set(&mut self, _name: &str, value: i64) -> Result<(), RedisError> {
+        self.size = value as usize;
+        Ok(())
+    }
+}
+
+pub(crate) struct LibraryPath {
+    pub(crate) path: String,
+    flags: ConfigFlags,
+}
+
+impl LibraryPath {
+    fn new() -> LibraryPath {
+        LibraryPath {
+            path: "".to_string(),
+            flags: ConfigFlags::new().emmutable(),
+        }
+    }
+}
+
+impl fmt::Display for LibraryPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl RedisConfigCtx for LibraryPath {
+    fn name(&self) -> &'static str {
+        "library-path"
+    }
+
+    fn apply(&self, _ctx: &Context) -> Result<(), RedisError> {
+        Ok(())
+    }
+
+    fn flags(&self) -> &ConfigFlags {
+        &self.flags
+    }
+}
+
+impl RedisStringConfigCtx for LibraryPath {
+    fn default(&self) -> Option<String> {
+        Some("".to_string())
+    }
+
+    fn get(&self, _name: &str) -> RedisString {
+        RedisString::create(std::ptr::null_mut(), &self.path)
+    }
+
+    fn set(&mut self, _name: &str, value: RedisString) -> Result<(), RedisError> {
+        self.path = value.try_as_str().unwrap().to_string();
+        Ok(())
+    }
+}
+
+// The user that loads the libraries of the library-path directory.
+pub(crate) struct LibraryPathUser {
+    pub(crate) user: String,
+    flags: ConfigFlags,
+}
+
+impl LibraryPathUser {
+    fn new() -> LibraryPathUser {
+        LibraryPathUser {
+            user: "default".to_string(),
+            flags: ConfigFlags::new().emmutable(),
+        }
+    }
+}
+
+impl fmt::Display for LibraryPathUser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.user)
+    }
+}
+
+impl RedisConfigCtx for LibraryPathUser {
+    fn name(&self) -> &'static str {
+        "library-path-user"
+    }
+
+    fn apply(&self, _ctx: &Context) -> Result<(), RedisError> {
+        Ok(())
+    }
+
+    fn flags(&self) -> &ConfigFlags {
+        &self.flags
+    }
+}
+
+impl RedisStringConfigCtx for LibraryPathUser {
+    fn default(&self) -> Option<String> {
+        Some("default".to_string())
+    }
+
+    fn get(&self, _name: &str) -> RedisString {
+        RedisString::create(std::ptr::null_mut(), &self.user)
+    }
+
+    fn set(&mut self, _name: &str, value: RedisString) -> Result<(), RedisError> {
+        self.user = value.try_as_str().unwrap().to_string();
+        Ok(())
+    }
+}
+
+// What to do with a library from the library-path directory
+// that was already loaded (from the RDB or the AOF).
+#[derive(Clone)]
+pub(crate) enum LibraryPathConflictPolicy {
+    Keep = 0,
+    Upgrade = 1,
+    Replace = 2,
+}
+
+pub(crate) struct LibraryPathConflict {
+    pub(crate) policy: LibraryPathConflictPolicy,
+    flags: ConfigFlags,
+}
+
+impl LibraryPathConflict {
+    fn new() -> LibraryPathConflict {
+        LibraryPathConflict {
+            policy: LibraryPathConflictPolicy::Keep,
+            flags: ConfigFlags::new(),
+        }
+    }
+}
+
+impl fmt::Display for LibraryPathConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.policy {
+            LibraryPathConflictPolicy::Keep => write!(f, "keep"),
+            LibraryPathConflictPolicy::Upgrade => write!(f, "upgrade"),
+            LibraryPathConflictPolicy::Replace => write!(f, "replace"),
+        }
+    }
+}
+
+impl RedisConfigCtx for LibraryPathConflict {
+    fn name(&self) -> &'static str {
+        "library-path-conflict-policy"
+    }
+
+    fn apply(&self, _ctx: &Context) -> Result<(), RedisError> {
+        Ok(())
+    }
+
+    fn flags(&self) -> &ConfigFlags {
+        &self.flags
+    }
+}
+
+impl RedisEnumConfigCtx for LibraryPathConflict {
+    fn default(&self) -> i32 {
+        self.policy.clone() as i32
+    }
+
+    fn values(&self) -> Vec<(&str, i32)> {
+        vec![
+            ("keep", LibraryPathConflictPolicy::Keep as i32),
+            ("upgrade", LibraryPathConflictPolicy::Upgrade as i32),
+            ("replace", LibraryPathConflictPolicy::Replace as i32),
+        ]
+    }
+
+    fn get(&self, _name: &str) -> i32 {
+        self.policy.clone() as i32
+    }
+
+    fn set(&mut self, _name: &str, value: i32) -> Result<(), RedisError> {
+        match value {
+            x if x == LibraryPathConflictPolicy::Keep as i32 => {
+                self.policy = LibraryPathConflictPolicy::Keep
+            }
+            x if x == LibraryPathConflictPolicy::Upgrade as i32 => {
+                self.policy = LibraryPathConflictPolicy::Upgrade
+            }
+            x if x == LibraryPathConflictPolicy::Replace as i32 => {
+                self.policy = LibraryPathConflictPolicy::Replace
+            }
+            _ => return Err(RedisError::Str("unsupported value were given")),
+        }
+        Ok(())
+    }
+}
+
 pub(crate) struct Config {
     pub(crate) execution_threads: ExecutionThreads,
     pub(crate) library_maxmemory: LibraryMaxMemory,
     pub(crate) gears_box_address: GearBoxAddress,
     pub(crate) libraray_fatal_failure_policy: LibraryOnFatalFailurePolicy,
     pub(crate) lock_regis_timeout: LockRedisTimeout,
+    pub(crate) library_history_size: LibraryHistorySize,
+    pub(crate) library_path: LibraryPath,
+    pub(crate) library_path_user: LibraryPathUser,
+    pub(crate) library_path_conflict: LibraryPathConflict,
 }
 
 impl Config {
@@ -310,6 +550,10 @@ impl Config {
             gears_box_address: GearBoxAddress::new(),
             libraray_fatal_failure_policy: LibraryOnFatalFailurePolicy::new(),
             lock_regis_timeout: LockRedisTimeout::new(),
+            library_history_size: LibraryHistorySize::new(),
+            library_path: LibraryPath::new(),
+            library_path_user: LibraryPathUser::new(),
+            library_path_conflict: LibraryPathConflict::new(),
         }
     }
 
@@ -391,6 +635,18 @@ impl Config {
             x if x == self.lock_regis_timeout.name() => {
                 Self::set_numeric_value(&mut self.lock_regis_timeout, val)
             }
+            x if x == self.library_history_size.name() => {
+                Self::set_numeric_value(&mut self.library_history_size, val)
+            }
+            x if x == self.library_path.name() => {
+                Self::set_string_value(&mut self.library_path, val)
+            }
+            x if x == self.library_path_user.name() => {
+                Self::set_string_value(&mut self.library_path_user, val)
+            }
+            x if x == self.library_path_conflict.name() => {
+                Self::set_enum_value(&mut self.library_path_conflict, val)
+            }
             _ => {
                 return Err(RedisError::String(format!(
                     "No such configuration {}",
@@ -421,6 +677,16 @@ impl Config {
             x if x == self.lock_regis_timeout.name() => {
                 Self::is_emmutable(&mut self.lock_regis_timeout)
             }
+            x if x == self.library_history_size.name() => {
+                Self::is_emmutable(&mut self.library_history_size)
+            }
+            x if x == self.library_path.name() => Self::is_emmutable(&mut self.library_path),
+            x if x == self.library_path_user.name() => {
+                Self::is_emmutable(&mut self.library_path_user)
+            }
+            x if x == self.library_path_conflict.name() => {
+                Self::is_emmutable(&mut self.library_path_conflict)
+            }
             _ => {
                 return Err(RedisError::String(format!(
                     "No such configuration {}",
@@ -446,6 +712,14 @@ impl Config {
                 Ok(format!("{}", self.libraray_fatal_failure_policy))
             }
             x if x == self.lock_regis_timeout.name() => Ok(format!("{}", self.lock_regis_timeout)),
+            x if x == self.library_history_size.name() => {
+                Ok(format!("{}", self.library_history_size))
+            }
+            x if x == self.library_path.name() => Ok(format!("{}", self.library_path)),
+            x if x == self.library_path_user.name() => Ok(format!("{}", self.library_path_user)),
+            x if x == self.library_path_conflict.name() => {
+                Ok(format!("{}", self.library_path_conflict))
+            }
             _ => Err(RedisError::String(format!(
                 "No such configuration {}",
                 name
@@ -0,0 +1,109 @@
+use crate::gears_box::GearsBoxLibraryInfo;
+use redis_module::RedisError;
+use serde::{Deserialize, Serialize};
+
+// Payload layout: <json encoded dump><dump version (2 bytes LE)><crc64 (8 bytes LE)>
+// the checksum covers everything that comes before it. Version 1 payloads only
+// contain the json encoded libraries array.
+pub(crate) static DUMP_VERSION: u16 = 2;
+const DUMP_FOOTER_LEN: usize = 2 + 8;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DumpedStream {
+    pub(crate) name: String,
+    pub(crate) ms: u64,
+    pub(crate) seq: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DumpedStreamConsumer {
+    pub(crate) name: String,
+    pub(crate) streams: Vec<DumpedStream>,
+    #[serde(default)]
+    pub(crate) paused: bool,
+    #[serde(default)]
+    pub(crate) paused_streams: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DumpedLibrary {
+    pub(crate) name: String,
+    pub(crate) code: String,
+    pub(crate) user: String,
+    pub(crate) gears_box_info: Option<GearsBoxLibraryInfo>,
+    // missing on payloads that were dumped before libraries had a configuration
+    #[serde(default)]
+    pub(crate) config: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub(crate) maxmemory: Option<usize>,
+    pub(crate) stream_consumers: Vec<DumpedStreamConsumer>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DumpedModule {
+    pub(crate) name: String,
+    pub(crate) code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Dump {
+    pub(crate) libraries: Vec<DumpedLibrary>,
+    // the modules imported by the dumped libraries
+    #[serde(default)]
+    pub(crate) modules: Vec<DumpedModule>,
+}
+
+// crc-64-jones, same as used by Redis on DUMP payloads. The input and output are
+// reflected, so the polynomial (0xad93d23594c935a9) is given bit reversed.
+// crc64("123456789") = 0xe9c6d914c4b8d9ca, same as the Redis crc64 test.
+fn crc64(data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    let mut crc: u64 = 0;
+    for b in data {
+        crc ^= *b as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+pub(crate) fn dump_libraries(dump: &Dump) -> Result<Vec<u8>, RedisError> {
+    let mut payload = serde_json::to_vec(dump)
+        .map_err(|e| RedisError::String(format!("Failed serializing libraries, {}", e)))?;
+    payload.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let checksum = crc64(&payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    Ok(payload)
+}
+
+pub(crate) fn restore_libraries(payload: &[u8]) -> Result<Dump, RedisError> {
+    if payload.len() < DUMP_FOOTER_LEN {
+        return Err(RedisError::Str("payload version or checksum are wrong"));
+    }
+    let (data, checksum) = payload.split_at(payload.len() - 8);
+    if crc64(data).to_le_bytes() != checksum {
+        return Err(RedisError::Str("payload version or checksum are wrong"));
+    }
+    let (data, version) = data.split_at(data.len() - 2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version > DUMP_VERSION {
+        return Err(RedisError::String(format!(
+            "payload version {} is not supported, max supported version {}",
+            version, DUMP_VERSION
+        )));
+    }
+    let res = if version < 2 {
+        serde_json::from_slice(data).map(|libraries| Dump {
+            libraries: libraries,
+            modules: Vec::new(),
+        })
+    } else {
+        serde_json::from_slice(data)
+    };
+    res.map_err(|e| RedisError::String(format!("Failed deserializing payload, {}", e)))
+}
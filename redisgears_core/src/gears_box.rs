@@ -32,7 +32,7 @@ pub(crate) fn gears_box_search(token: &str) -> Result<serde_json::Value, RedisEr
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub(crate) struct GearsBoxLibraryVersionInfo {
     pub(crate) id: String,
@@ -46,7 +46,7 @@ pub(crate) struct GearsBoxLibraryVersionInfo {
     pub(crate) sha256: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub(crate) struct GearsBoxLibraryInfo {
     pub(crate) id: String,
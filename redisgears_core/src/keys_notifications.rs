@@ -1,16 +1,30 @@
-use redisgears_plugin_api::redisgears_plugin_api::RefCellWrapper;
+use redisgears_plugin_api::redisgears_plugin_api::{GearsApiError, RefCellWrapper};
 use std::cell::RefCell;
 use std::sync::{Arc, Weak};
 use std::time::SystemTime;
 
 pub(crate) type NotificationCallback =
-    Box<dyn Fn(&str, &str, Box<dyn FnOnce(Result<(), String>) + Send + Sync>)>;
+    Box<dyn Fn(&str, &str, Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>)>;
 
 pub(crate) enum ConsumerKey {
     Key(String),
     Prefix(String),
 }
 
+impl ConsumerKey {
+    // whether there are keys that are matched by both
+    pub(crate) fn overlaps(&self, other: &ConsumerKey) -> bool {
+        match (self, other) {
+            (ConsumerKey::Key(k1), ConsumerKey::Key(k2)) => k1 == k2,
+            (ConsumerKey::Key(k), ConsumerKey::Prefix(p))
+            | (ConsumerKey::Prefix(p), ConsumerKey::Key(k)) => k.starts_with(p.as_str()),
+            (ConsumerKey::Prefix(p1), ConsumerKey::Prefix(p2)) => {
+                p1.starts_with(p2.as_str()) || p2.starts_with(p1.as_str())
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct NotificationConsumerStats {
     pub(crate) num_trigger: usize,
@@ -59,6 +73,10 @@ impl NotificationConsumer {
         old_key.unwrap()
     }
 
+    pub(crate) fn get_key(&self) -> &ConsumerKey {
+        self.key.as_ref().unwrap()
+    }
+
     pub(crate) fn get_stats(&self) -> NotificationConsumerStats {
         self.stats.ref_cell.borrow().clone()
     }
@@ -87,7 +105,7 @@ fn fire_event(consumer: &Arc<RefCell<NotificationConsumer>>, event: &str, key: &
             stats.total_execution_time += duration;
             if let Err(e) = res {
                 stats.num_failed += 1;
-                stats.last_error = Some(e);
+                stats.last_error = Some(e.get_error_string());
             } else {
                 stats.num_success += 1;
             }
@@ -1,37 +1,49 @@
 extern crate redis_module;
 
-use redis_module::raw::{RedisModule_GetDetachedThreadSafeContext, RedisModule__Assert};
+use redis_module::context::configuration::RedisNumberConfigCtx;
+use redis_module::raw::{
+    RedisModuleStreamID, RedisModule_GetDetachedThreadSafeContext, RedisModule__Assert,
+};
 use threadpool::ThreadPool;
 
 use redis_module::{
     context::keys_cursor::KeysCursor, context::server_events::FlushSubevent,
     context::server_events::LoadingSubevent, context::server_events::ServerEventData,
-    context::server_events::ServerRole, context::AclPermissions, context::CallOptions,
+    context::server_events::ServerRole, context::AclPermissions, context::CallOptionsBuilder, raw,
     raw::KeyType::Stream, redis_command, redis_event_handler, redis_module, Context, InfoContext,
-    NextArg, NotifyEvent, RedisError, RedisResult, RedisString, RedisValue, Status,
+    NextArg, NotifyEvent, RedisError, RedisResult, RedisString, RedisValue, RedisValueKey, Status,
     ThreadSafeContext,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::{
-    backend_ctx::BackendCtx, backend_ctx::BackendCtxInterface, function_ctx::FunctionCtxInterface,
+    backend_ctx::BackendCtx, backend_ctx::BackendCtxInterface,
+    backend_ctx::BackendPluginDescriptor, backend_ctx::GEARS_PLUGIN_API_VERSION,
+    backend_ctx::GEARS_PLUGIN_DESCRIPTOR_SYMBOL, function_ctx::FunctionCtxInterface,
     keys_notifications_consumer_ctx::KeysNotificationsConsumerCtxInterface,
     load_library_ctx::LibraryCtxInterface, load_library_ctx::LoadLibraryCtxInterface,
-    load_library_ctx::RegisteredKeys, load_library_ctx::FUNCTION_FLAG_ALLOW_OOM,
-    load_library_ctx::FUNCTION_FLAG_NO_WRITES, stream_ctx::StreamCtxInterface, CallResult,
-    GearsApiError,
+    load_library_ctx::RegisteredKeys, load_library_ctx::RegisteredStreams,
+    load_library_ctx::StreamConsumerOptions, load_library_ctx::StreamStartPosition,
+    load_library_ctx::FUNCTION_FLAG_ALLOW_OOM, load_library_ctx::FUNCTION_FLAG_NO_WRITES,
+    load_library_ctx::FUNCTION_FLAG_RAW_ARGUMENTS, stream_ctx::StreamCtxInterface,
+    stream_ctx::StreamRecordInterface, CallResult, GearsApiError, GearsApiErrorKind,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::RefCellWrapper;
 
-use crate::run_ctx::RunCtx;
+use crate::run_ctx::{FunctionStats, RedisClientCallOptions, RunCtx};
 
 use libloading::{Library, Symbol};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::CStr;
+use std::os::raw::c_void;
 
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::stream_reader::{ConsumerData, StreamReaderCtx};
+use crate::stream_reader::{
+    ConsumerData, ConsumerOptions, ConsumerStart, ConsumerStreams, StreamReaderCtx,
+};
 use std::iter::Skip;
 use std::vec::IntoIter;
 
@@ -44,9 +56,14 @@ use crate::keys_notifications::{KeysNotificationsCtx, NotificationCallback, Noti
 use crate::keys_notifications_ctx::KeysNotificationsRunCtx;
 use crate::stream_run_ctx::{GearsStreamConsumer, GearsStreamRecord};
 
-use crate::config::Config;
+use crate::config::{Config, LibraryPathConflictPolicy};
+
+use crate::dump::{
+    dump_libraries, restore_libraries, Dump, DumpedLibrary, DumpedModule, DumpedStream,
+    DumpedStreamConsumer,
+};
 
-use rdb::REDIS_GEARS_TYPE;
+use rdb::{add_aof_rewrite_key, REDIS_GEARS_TYPE};
 
 use std::cell::RefCell;
 
@@ -54,8 +71,10 @@ use crate::keys_notifications::ConsumerKey;
 
 mod background_run_ctx;
 mod background_run_scope_guard;
+mod bundle;
 mod compiled_library_api;
 mod config;
+mod dump;
 mod gears_box;
 mod keys_notifications;
 mod keys_notifications_ctx;
@@ -68,11 +87,25 @@ struct GearsLibraryMataData {
     name: String,
     engine: String,
     code: String,
+    version: Option<String>,
+    description: Option<String>,
+    author: Option<String>,
+    api_version: Option<String>,
+    min_gears_version: Option<String>,
+    // heap limit in bytes, overrides the library-maxmemory configuration
+    maxmemory: Option<usize>,
+    // user defined 'x-' properties, in the order they were given
+    extra_properties: Vec<(String, String)>,
 }
 
+// Deploy time configuration of a library, set with RG.FUNCTION LOAD CONFIG
+// and RG.FUNCTION CONFIG SET and kept across upgrades.
+type LibraryConfig = serde_json::Map<String, serde_json::Value>;
+
 struct GearsFunctionCtx {
     func: Box<dyn FunctionCtxInterface>,
     flags: u8,
+    stats: Arc<Mutex<FunctionStats>>,
 }
 
 impl GearsFunctionCtx {
@@ -80,6 +113,7 @@ impl GearsFunctionCtx {
         GearsFunctionCtx {
             func: func,
             flags: flags,
+            stats: Arc::new(Mutex::new(FunctionStats::new())),
         }
     }
 }
@@ -87,20 +121,50 @@ impl GearsFunctionCtx {
 struct GearsLibraryCtx {
     user: Arc<RefCellWrapper<String>>,
     meta_data: GearsLibraryMataData,
+    config: LibraryConfig,
+    // heap limit that was given with RG.FUNCTION LOAD MAXMEMORY
+    maxmemory: Option<usize>,
     functions: HashMap<String, GearsFunctionCtx>,
     stream_consumers:
         HashMap<String, Arc<RefCellWrapper<ConsumerData<GearsStreamRecord, GearsStreamConsumer>>>>,
-    revert_stream_consumers: Vec<(String, GearsStreamConsumer, usize, bool)>,
+    revert_stream_consumers: Vec<StreamConsumerRevertData>,
     notifications_consumers: HashMap<String, Arc<RefCell<NotificationConsumer>>>,
     revert_notifications_consumers: Vec<(String, ConsumerKey, NotificationCallback)>,
     old_lib: Option<Box<GearsLibrary>>,
+    // set on RG.FUNCTION LOAD DRYRUN, consumers are only recorded and
+    // are not registered on the global stream and notifications ctx.
+    dry_run: Option<DryRunRegistrations>,
+}
+
+struct DryRunRegistrations {
+    stream_consumers: Vec<(String, ConsumerStreams)>,
+    notifications_consumers: Vec<(String, ConsumerKey)>,
+}
+
+// A version of the library that was replaced by an upgrade, kept so the
+// library can be rolled back to it.
+struct PreviousLibraryVersion {
+    meta_data: GearsLibraryMataData,
+    user: String,
+    gears_box_lib: Option<GearsBoxLibraryInfo>,
+    config: LibraryConfig,
+    maxmemory: Option<usize>,
+}
+
+// A module that can be imported by libraries of the same engine, the module
+// is compiled as part of each library that imports it.
+struct GearsModule {
+    meta_data: GearsLibraryMataData,
+    user: String,
 }
 
 struct GearsLibrary {
     gears_lib_ctx: GearsLibraryCtx,
-    _lib_ctx: Box<dyn LibraryCtxInterface>,
+    lib_ctx: Box<dyn LibraryCtxInterface>,
     compile_lib_internals: Arc<CompiledLibraryInternals>,
     gears_box_lib: Option<GearsBoxLibraryInfo>,
+    // newest first, bounded by the library-history-size configuration
+    previous_versions: VecDeque<PreviousLibraryVersion>,
 }
 
 fn redis_value_to_call_reply(r: RedisValue) -> CallResult {
@@ -108,7 +172,14 @@ fn redis_value_to_call_reply(r: RedisValue) -> CallResult {
         RedisValue::SimpleString(s) => CallResult::SimpleStr(s),
         RedisValue::SimpleStringStatic(s) => CallResult::SimpleStr(s.to_string()),
         RedisValue::BulkString(s) => CallResult::BulkStr(s.to_string()),
-        RedisValue::BulkRedisString(s) => CallResult::BulkStr(s.try_as_str().unwrap().to_string()),
+        RedisValue::BulkRedisString(s) => match s.try_as_str() {
+            Ok(s) => CallResult::BulkStr(s.to_string()),
+            Err(_) => CallResult::StringBuffer(s.as_slice().to_vec()),
+        },
+        RedisValue::StringBuffer(s) => match String::from_utf8(s) {
+            Ok(s) => CallResult::BulkStr(s),
+            Err(e) => CallResult::StringBuffer(e.into_bytes()),
+        },
         RedisValue::Integer(i) => CallResult::Long(i),
         RedisValue::Float(f) => CallResult::Double(f),
         RedisValue::Array(a) => {
@@ -118,8 +189,85 @@ fn redis_value_to_call_reply(r: RedisValue) -> CallResult {
                 .collect::<Vec<CallResult>>();
             CallResult::Array(res)
         }
+        RedisValue::Map(m) => CallResult::Map(
+            m.into_iter()
+                .map(|(k, v)| {
+                    (
+                        redis_value_key_to_call_reply(k),
+                        redis_value_to_call_reply(v),
+                    )
+                })
+                .collect::<Vec<(CallResult, CallResult)>>(),
+        ),
+        RedisValue::Set(s) => CallResult::Set(
+            s.into_iter()
+                .map(|v| redis_value_key_to_call_reply(v))
+                .collect::<Vec<CallResult>>(),
+        ),
+        RedisValue::Bool(b) => CallResult::Bool(b),
+        RedisValue::BigNumber(s) => CallResult::BigNumber(s),
+        RedisValue::VerbatimString((_format, s)) => {
+            CallResult::VerbatimString(String::from_utf8_lossy(&s).into_owned())
+        }
+        RedisValue::Attribute { attributes, data } => CallResult::Attribute(
+            attributes
+                .into_iter()
+                .map(|(k, v)| {
+                    (
+                        redis_value_key_to_call_reply(k),
+                        redis_value_to_call_reply(v),
+                    )
+                })
+                .collect::<Vec<(CallResult, CallResult)>>(),
+            Box::new(redis_value_to_call_reply(*data)),
+        ),
         RedisValue::Null => CallResult::Null,
-        _ => panic!("not yet implemented"),
+        _ => CallResult::Error("Unsupported reply type".to_string()),
+    }
+}
+
+fn redis_value_key_to_call_reply(k: RedisValueKey) -> CallResult {
+    match k {
+        RedisValueKey::Integer(i) => CallResult::Long(i),
+        RedisValueKey::String(s) => CallResult::BulkStr(s),
+        RedisValueKey::BulkRedisString(s) => {
+            redis_value_to_call_reply(RedisValue::BulkRedisString(s))
+        }
+        RedisValueKey::BulkString(s) => match String::from_utf8(s) {
+            Ok(s) => CallResult::BulkStr(s),
+            Err(e) => CallResult::StringBuffer(e.into_bytes()),
+        },
+        RedisValueKey::Bool(b) => CallResult::Bool(b),
+    }
+}
+
+// The stream consumer settings from before an upgrade, restored if the upgrade fails.
+struct StreamConsumerRevertData {
+    name: String,
+    ctx: GearsStreamConsumer,
+    window: usize,
+    trim: bool,
+    batch: (usize, u64),
+    retry_policy: (usize, u64, Option<String>),
+}
+
+// Where to start reading streams the consumer did not read before, reading
+// continues right after the given id.
+fn stream_start_position_to_consumer_start(start: StreamStartPosition) -> ConsumerStart {
+    let before = |ms: u64| match ms {
+        0 => None,
+        ms => Some(RedisModuleStreamID {
+            ms: ms - 1,
+            seq: u64::MAX,
+        }),
+    };
+    match start {
+        StreamStartPosition::Beginning => ConsumerStart::After(None),
+        StreamStartPosition::NewEntries => ConsumerStart::LastEntry,
+        StreamStartPosition::Id(ms, seq) => {
+            ConsumerStart::After(Some(RedisModuleStreamID { ms: ms, seq: seq }))
+        }
+        StreamStartPosition::Timestamp(ts) => ConsumerStart::After(before(ts)),
     }
 }
 
@@ -131,7 +279,7 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
         flags: u8,
     ) -> Result<(), GearsApiError> {
         if self.functions.contains_key(name) {
-            return Err(GearsApiError::Msg(format!(
+            return Err(GearsApiError::new(format!(
                 "Function {} already exists",
                 name
             )));
@@ -144,45 +292,115 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
     fn register_stream_consumer(
         &mut self,
         name: &str,
-        prefix: &str,
+        streams: RegisteredStreams,
         ctx: Box<dyn StreamCtxInterface>,
         window: usize,
         trim: bool,
+        options: StreamConsumerOptions,
     ) -> Result<(), GearsApiError> {
         if self.stream_consumers.contains_key(name) {
-            return Err(GearsApiError::Msg(
+            return Err(GearsApiError::new(
                 "Stream registration already exists".to_string(),
             ));
         }
 
+        let streams = match streams {
+            RegisteredStreams::Prefix(p) => ConsumerStreams::Prefix(p),
+            RegisteredStreams::Pattern(p) => ConsumerStreams::Pattern(p),
+            RegisteredStreams::Streams(mut s) => {
+                if s.is_empty() {
+                    return Err(GearsApiError::new(
+                        "Stream consumer must be registered on at least one stream".to_string(),
+                    ));
+                }
+                s.sort();
+                s.dedup();
+                ConsumerStreams::Streams(s)
+            }
+        };
+
+        if let Some(dead_letter_stream) = options.dead_letter_stream.as_ref() {
+            if streams.matches(dead_letter_stream) {
+                return Err(GearsApiError::new(format!(
+                    "Dead letter stream '{}' can not match the consumer {}",
+                    dead_letter_stream, streams
+                )));
+            }
+        }
+
+        if let Some(dry_run) = self.dry_run.as_mut() {
+            if dry_run.stream_consumers.iter().any(|(n, _)| n == name) {
+                return Err(GearsApiError::new(
+                    "Stream registration already exists".to_string(),
+                ));
+            }
+            dry_run.stream_consumers.push((name.to_string(), streams));
+            return Ok(());
+        }
+
         let stream_registration = if let Some(old_consumer) = self
             .old_lib
             .as_ref()
             .map_or(None, |v| v.gears_lib_ctx.stream_consumers.get(name))
         {
             let mut o_c = old_consumer.ref_cell.borrow_mut();
-            if o_c.prefix != prefix {
-                return Err(GearsApiError::Msg(
-                    format!("Can not upgrade an existing consumer with different prefix, consumer: '{}', old_prefix: {}, new_prefix: {}.",
-                    name, o_c.prefix, prefix)
+            if o_c.streams != streams {
+                return Err(GearsApiError::new(
+                    format!("Can not upgrade an existing consumer with different streams, consumer: '{}', old: {}, new: {}.",
+                    name, o_c.streams, streams)
                 ));
             }
-            let old_ctx = o_c.set_consumer(GearsStreamConsumer::new(&self.user, 0, ctx));
+            let old_ctx = o_c.set_consumer(GearsStreamConsumer::new(
+                &self.meta_data.name,
+                &self.user,
+                0,
+                options.batch_size.is_some(),
+                ctx,
+            ));
             let old_window = o_c.set_window(window);
             let old_trim = o_c.set_trim(trim);
-            self.revert_stream_consumers
-                .push((name.to_string(), old_ctx, old_window, old_trim));
+            let old_batch = o_c.set_batch(options.batch_size.unwrap_or(1), options.batch_max_wait);
+            let old_retry_policy = o_c.set_retry_policy(
+                options.max_attempts,
+                options.retry_backoff,
+                options.dead_letter_stream,
+            );
+            self.revert_stream_consumers.push(StreamConsumerRevertData {
+                name: name.to_string(),
+                ctx: old_ctx,
+                window: old_window,
+                trim: old_trim,
+                batch: old_batch,
+                retry_policy: old_retry_policy,
+            });
             Arc::clone(old_consumer)
         } else {
             let globals = get_globals_mut();
             let stream_ctx = &mut globals.stream_ctx;
             let lib_name = self.meta_data.name.clone();
             let consumer_name = name.to_string();
+            let failed_lib_name = self.meta_data.name.clone();
+            let failed_consumer_name = name.to_string();
+            let user = Arc::clone(&self.user);
             let consumer = stream_ctx.add_consumer(
-                prefix,
-                GearsStreamConsumer::new(&self.user, 0, ctx),
-                window,
-                trim,
+                streams,
+                GearsStreamConsumer::new(
+                    &self.meta_data.name,
+                    &self.user,
+                    0,
+                    options.batch_size.is_some(),
+                    ctx,
+                ),
+                ConsumerOptions {
+                    window: window,
+                    trim: trim,
+                    batch_size: options.batch_size.unwrap_or(1),
+                    batch_max_wait: options.batch_max_wait,
+                    start: stream_start_position_to_consumer_start(options.start),
+                    max_attempts: options.max_attempts,
+                    retry_backoff: options.retry_backoff,
+                    dead_letter_stream: options.dead_letter_stream,
+                },
                 Some(Box::new(move |stream_name, ms, seq| {
                     redis_module::replicate(
                         get_ctx().ctx,
@@ -196,6 +414,47 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
                         ],
                     );
                 })),
+                Some(Box::new(
+                    move |dead_letter_stream, stream_name, record, error, attempts| {
+                        // written as the library user, with the same checks as a call from a function
+                        let user = user.ref_cell.borrow().to_string();
+                        let (ms, seq) = record.get_id();
+                        let id = format!("{}-{}", ms, seq);
+                        let attempts = attempts.to_string();
+                        let mut args = vec![
+                            dead_letter_stream.as_bytes(),
+                            "*".as_bytes(),
+                            "library".as_bytes(),
+                            failed_lib_name.as_bytes(),
+                            "consumer".as_bytes(),
+                            failed_consumer_name.as_bytes(),
+                            "stream".as_bytes(),
+                            stream_name.as_bytes(),
+                            "id".as_bytes(),
+                            id.as_bytes(),
+                            "error".as_bytes(),
+                            error.as_bytes(),
+                            "attempts".as_bytes(),
+                            attempts.as_bytes(),
+                        ];
+                        for (field, value) in record.fields() {
+                            args.push(field);
+                            args.push(value);
+                        }
+                        if let CallResult::Error(e) = call_redis_command(
+                            Some(&user),
+                            "xadd",
+                            &RedisClientCallOptions::new(0),
+                            false,
+                            args.as_slice(),
+                        ) {
+                            get_ctx().log_warning(&format!(
+                                "Failed writing record to dead letter stream '{}' as user '{}', {}.",
+                                dead_letter_stream, user, e
+                            ));
+                        }
+                    },
+                )),
             );
             if get_ctx().is_primary() {
                 // trigger a key scan
@@ -216,12 +475,33 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
         keys_notifications_consumer_ctx: Box<dyn KeysNotificationsConsumerCtxInterface>,
     ) -> Result<(), GearsApiError> {
         if self.notifications_consumers.contains_key(name) {
-            return Err(GearsApiError::Msg(
+            return Err(GearsApiError::new(
                 "Notification consumer already exists".to_string(),
             ));
         }
 
+        if let Some(dry_run) = self.dry_run.as_mut() {
+            if dry_run
+                .notifications_consumers
+                .iter()
+                .any(|(n, _)| n == name)
+            {
+                return Err(GearsApiError::new(
+                    "Notification consumer already exists".to_string(),
+                ));
+            }
+            let key = match key {
+                RegisteredKeys::Key(s) => ConsumerKey::Key(s.to_string()),
+                RegisteredKeys::Prefix(s) => ConsumerKey::Prefix(s.to_string()),
+            };
+            dry_run
+                .notifications_consumers
+                .push((name.to_string(), key));
+            return Ok(());
+        }
+
         let user_name = Arc::clone(&self.user);
+        let library_name = self.meta_data.name.to_string();
         let mut permissions = AclPermissions::new();
         permissions.add_full_permission();
         let fire_event_callback: NotificationCallback =
@@ -231,13 +511,17 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
                 if let Err(e) =
                     get_ctx().acl_check_key_permission(&user, &key_redis_str, &permissions)
                 {
-                    done_callback(Err(format!(
-                        "User '{}' has no permissions on key '{}', {}.",
-                        user, key, e
+                    done_callback(Err(GearsApiError::new_with_kind(
+                        GearsApiErrorKind::NoPermission,
+                        format!(
+                            "User '{}' has no permissions on key '{}', {}.",
+                            user, key, e
+                        ),
                     )));
                     return;
                 }
                 let _notification_blocker = get_notification_blocker();
+                let _running_library_guard = get_running_library_guard(&library_name);
                 let val = keys_notifications_consumer_ctx.on_notification_fired(
                     event,
                     key,
@@ -285,10 +569,15 @@ impl LoadLibraryCtxInterface for GearsLibraryCtx {
             .insert(name.to_string(), consumer);
         Ok(())
     }
+
+    fn get_library_config(&self) -> String {
+        serde_json::to_string(&self.config).unwrap()
+    }
 }
 
 struct GlobalCtx {
     libraries: HashMap<String, GearsLibrary>,
+    modules: HashMap<String, GearsModule>,
     backends: HashMap<String, Box<dyn BackendCtxInterface>>,
     redis_ctx: Context,
     authenticated_redis_ctx: Context,
@@ -299,6 +588,14 @@ struct GlobalCtx {
     notifications_ctx: KeysNotificationsCtx,
     config: Config,
     avoid_key_space_notifications: bool,
+    running_library: Option<String>,
+    // set while loading the RDB or the AOF, the library-path is loaded again when it ends
+    loading_from_disk: bool,
+    // the key positions of the commands invoked by functions that declared their keys
+    commands_keys_positions: HashMap<String, CommandKeysPositions>,
+    // the commands that were registered on the module arguments to invoke a function,
+    // by their lower case name
+    function_commands: HashMap<String, FunctionCommand>,
 }
 
 static mut GLOBALS: Option<GlobalCtx> = None;
@@ -316,6 +613,25 @@ impl<'a> Drop for NotificationBlocker {
     }
 }
 
+// Marks the library as running on the main thread so it will be reported
+// on the crash report, the previous value is restored when dropped.
+pub(crate) struct RunningLibraryGuard {
+    prev: Option<String>,
+}
+
+pub(crate) fn get_running_library_guard(library_name: &str) -> RunningLibraryGuard {
+    let prev = get_globals_mut()
+        .running_library
+        .replace(library_name.to_string());
+    RunningLibraryGuard { prev: prev }
+}
+
+impl Drop for RunningLibraryGuard {
+    fn drop(&mut self) {
+        get_globals_mut().running_library = self.prev.take();
+    }
+}
+
 fn get_globals() -> &'static GlobalCtx {
     unsafe { GLOBALS.as_ref().unwrap() }
 }
@@ -366,12 +682,135 @@ pub(crate) fn execute_on_pool<F: FnOnce() + Send + 'static>(job: F) {
     });
 }
 
+// the legacy key positions (first, last, step) of a command, as reported by COMMAND INFO.
+// commands with movable keys, and commands whose keys are only described by their
+// subcommands (like XINFO or OBJECT), are asked for their keys on each invocation.
+#[derive(Clone)]
+enum CommandKeysPositions {
+    Range(i64, i64, i64),
+    Movable,
+    NoKeys,
+}
+
+fn get_command_keys_positions(command: &str) -> Result<CommandKeysPositions, String> {
+    let command = command.to_lowercase();
+    if let Some(positions) = get_globals().commands_keys_positions.get(&command) {
+        return Ok(positions.clone());
+    }
+    let info = match get_ctx().call_ext(
+        "command",
+        &CallOptionsBuilder::new().constract(),
+        &["info".as_bytes(), command.as_bytes()],
+    ) {
+        Ok(RedisValue::Array(mut info)) if info.len() == 1 => info.pop().unwrap(),
+        _ => return Err(format!("Failed getting command '{}' info", command)),
+    };
+    let positions = match info {
+        RedisValue::Array(info) if info.len() >= 6 => {
+            let movable = match &info[2] {
+                RedisValue::Array(flags) => flags.iter().any(|f| match f {
+                    RedisValue::SimpleString(f) | RedisValue::BulkString(f) => f == "movablekeys",
+                    RedisValue::SimpleStringStatic(f) => *f == "movablekeys",
+                    _ => false,
+                }),
+                _ => false,
+            };
+            // the key specifications and the subcommands, not empty if the
+            // command might get keys that are not on the legacy range.
+            let has_keys_info = info
+                .iter()
+                .skip(8)
+                .any(|v| matches!(v, RedisValue::Array(a) if a.len() > 0));
+            match (movable, &info[3], &info[4], &info[5]) {
+                (true, _, _, _) => CommandKeysPositions::Movable,
+                (
+                    false,
+                    RedisValue::Integer(first),
+                    RedisValue::Integer(last),
+                    RedisValue::Integer(step),
+                ) if *first > 0 && *step > 0 => CommandKeysPositions::Range(*first, *last, *step),
+                (false, RedisValue::Integer(_), RedisValue::Integer(_), RedisValue::Integer(_)) => {
+                    if has_keys_info {
+                        CommandKeysPositions::Movable
+                    } else {
+                        CommandKeysPositions::NoKeys
+                    }
+                }
+                _ => return Err(format!("Failed parsing command '{}' info", command)),
+            }
+        }
+        _ => return Err(format!("Unknown command '{}'", command)),
+    };
+    get_globals_mut()
+        .commands_keys_positions
+        .insert(command, positions.clone());
+    Ok(positions)
+}
+
+// The keys the command will access, an error is returned if they can not be
+// determined so the command is denied.
+fn get_command_keys(command: &str, args: &[&[u8]]) -> Result<Vec<Vec<u8>>, String> {
+    let (first, last, step) = match get_command_keys_positions(command)? {
+        CommandKeysPositions::Range(first, last, step) => (first, last, step),
+        CommandKeysPositions::NoKeys => return Ok(Vec::new()),
+        CommandKeysPositions::Movable => {
+            let mut getkeys_args = vec!["getkeys".as_bytes(), command.as_bytes()];
+            getkeys_args.extend_from_slice(args);
+            let err = || format!("Failed getting the keys of command '{}'", command);
+            return match get_ctx().call_ext(
+                "command",
+                &CallOptionsBuilder::new().constract(),
+                getkeys_args.as_slice(),
+            ) {
+                Ok(RedisValue::Array(keys)) => keys
+                    .into_iter()
+                    .map(|key| match redis_value_to_call_reply(key) {
+                        CallResult::BulkStr(s) | CallResult::SimpleStr(s) => Ok(s.into_bytes()),
+                        CallResult::StringBuffer(s) => Ok(s),
+                        _ => Err(err()),
+                    })
+                    .collect(),
+                _ => Err(err()),
+            };
+        }
+    };
+    // positions include the command name, the args do not
+    let argc = args.len() as i64 + 1;
+    let last = if last < 0 { argc + last } else { last };
+    Ok((first..std::cmp::min(last + 1, argc))
+        .step_by(step as usize)
+        .map(|i| args[(i - 1) as usize].to_vec())
+        .collect())
+}
+
+fn verify_declared_keys(
+    command: &str,
+    args: &[&[u8]],
+    allowed_keys: &[Vec<u8>],
+) -> Result<(), String> {
+    for key in get_command_keys(command, args)? {
+        if !allowed_keys.iter().any(|k| k == &key) {
+            return Err(format!(
+                "Key '{}' was not declared as a key argument of the function",
+                String::from_utf8_lossy(&key)
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn call_redis_command(
     user: Option<&String>,
     command: &str,
-    call_options: &CallOptions,
-    args: &[&str],
+    call_options: &RedisClientCallOptions,
+    resp3: bool,
+    args: &[&[u8]],
 ) -> CallResult {
+    if let Some(allowed_keys) = call_options.allowed_keys.as_ref() {
+        if let Err(e) = verify_declared_keys(command, args, allowed_keys) {
+            return CallResult::Error(e);
+        }
+    }
     let ctx = match user {
         Some(u) => {
             let ctx = &get_globals().authenticated_redis_ctx;
@@ -382,7 +821,12 @@ pub(crate) fn call_redis_command(
         }
         None => get_ctx(),
     };
-    let res = ctx.call_ext(command, call_options, args);
+    let options = if resp3 {
+        &call_options.resp3_call_options
+    } else {
+        &call_options.call_options
+    };
+    let res = ctx.call_ext(command, options, args);
     match res {
         Ok(r) => redis_value_to_call_reply(r),
         Err(e) => match e {
@@ -394,8 +838,94 @@ pub(crate) fn call_redis_command(
     }
 }
 
+// A command that invokes a function, commands can only be created while the
+// module is loaded so they are given on the module arguments and not by the libraries.
+struct FunctionCommand {
+    library: String,
+    function: String,
+    read_only: bool,
+}
+
+// Module arguments are: [BACKEND <backend path> ...]
+// [COMMAND <command name> <library> <function> <WRITE|READONLY> ...] [<config name> <config value> ...]
+// for backward compatibility, when the first argument is not a keyword nor a
+// configuration name it is a backend path.
+fn split_module_args(
+    args: &[RedisString],
+) -> Result<(Vec<&str>, Vec<(&str, FunctionCommand)>, Vec<&RedisString>), String> {
+    let mut backends_paths = Vec::new();
+    let mut commands = Vec::new();
+    let mut configs = Vec::new();
+    let args_str = args
+        .iter()
+        .map(|arg| {
+            arg.try_as_str().map_err(|_| {
+                "Failed parsing module arguments, arguments must be strings".to_string()
+            })
+        })
+        .collect::<Result<Vec<&str>, String>>()?;
+    let mut args = args.iter().zip(args_str.into_iter()).peekable();
+    if let Some(&(_, first)) = args.peek() {
+        let keyword = ["backend", "command"].contains(&first.to_lowercase().as_str());
+        if !keyword && Config::new().get(first).is_err() {
+            backends_paths.push(first);
+            args.next();
+        }
+    }
+    while let Some((arg, arg_str)) = args.next() {
+        if arg_str.to_lowercase() == "backend" {
+            match args.next() {
+                Some((_, path)) => backends_paths.push(path),
+                None => {
+                    return Err(
+                        "BACKEND argument must be followed by a backend plugin path".to_string()
+                    )
+                }
+            }
+        } else if arg_str.to_lowercase() == "command" {
+            let (name, library, function, flags) =
+                match (args.next(), args.next(), args.next(), args.next()) {
+                    (Some((_, n)), Some((_, l)), Some((_, f)), Some((_, flags))) => (n, l, f, flags),
+                    _ => return Err("COMMAND argument must be followed by a command name, a library name, a function name and WRITE or READONLY".to_string()),
+                };
+            let read_only = match flags.to_lowercase().as_ref() {
+                "write" => false,
+                "readonly" => true,
+                _ => {
+                    return Err(format!(
+                        "Invalid flags '{}' for command '{}', must be WRITE or READONLY",
+                        flags, name
+                    ))
+                }
+            };
+            commands.push((
+                name,
+                FunctionCommand {
+                    library: library.to_string(),
+                    function: function.to_string(),
+                    read_only: read_only,
+                },
+            ));
+        } else {
+            // a configuration name and its value
+            configs.push(arg);
+            if let Some((value, _)) = args.next() {
+                configs.push(value);
+            }
+        }
+    }
+    Ok((backends_paths, commands, configs))
+}
+
 fn js_post_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
-    let mut args = args.into_iter().skip(1); // skip the plugin
+    let configs = match split_module_args(args) {
+        Ok((_, _, configs)) => configs,
+        Err(e) => {
+            ctx.log_warning(&e);
+            return Status::Err;
+        }
+    };
+    let mut args = configs.into_iter();
     while let Some(config_key) = args.next() {
         let key = match config_key.try_as_str() {
             Ok(s) => s,
@@ -433,9 +963,192 @@ fn js_post_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
     globals.pool = Some(Mutex::new(ThreadPool::new(
         globals.config.execution_threads.size,
     )));
+    unsafe {
+        raw::RedisModule_SubscribeToServerEvent.unwrap()(
+            ctx.ctx,
+            raw::RedisModuleEvent {
+                id: raw::REDISMODULE_EVENT_PERSISTENCE as u64,
+                dataver: 1,
+            },
+            Some(on_persistence_event),
+        )
+    };
+    load_libraries_from_path(ctx);
     Status::Ok
 }
 
+// Loads the '.js' files on the library-path directory (in lexicographic order),
+// libraries that were already loaded are handled according to the conflict policy.
+fn load_libraries_from_path(ctx: &Context) {
+    let config = &get_globals().config;
+    let path = config.library_path.path.to_string();
+    if path.is_empty() {
+        return;
+    }
+    let user = config.library_path_user.user.to_string();
+    let (upgrade, force) = match config.library_path_conflict.policy {
+        LibraryPathConflictPolicy::Keep => (false, false),
+        LibraryPathConflictPolicy::Upgrade => (true, false),
+        LibraryPathConflictPolicy::Replace => (true, true),
+    };
+    let mut files = match std::fs::read_dir(&path) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map_or(false, |ext| ext == "js"))
+            .collect::<Vec<std::path::PathBuf>>(),
+        Err(e) => {
+            ctx.log_warning(&format!("Failed reading library path '{}', {}", path, e));
+            return;
+        }
+    };
+    files.sort();
+    for file in files {
+        let code = match std::fs::read_to_string(&file) {
+            Ok(c) => c,
+            Err(e) => {
+                ctx.log_warning(&format!(
+                    "Failed reading library file '{}', {}",
+                    file.display(),
+                    e
+                ));
+                continue;
+            }
+        };
+        if !upgrade {
+            if let Ok(meta_data) = library_extract_matadata(&code) {
+                if get_libraries().contains_key(&meta_data.name) {
+                    ctx.log_notice(&format!(
+                        "Library {} from '{}' is already loaded, keeping the loaded version",
+                        meta_data.name,
+                        file.display()
+                    ));
+                    continue;
+                }
+            }
+        }
+        match function_load_intrernal(user.to_string(), &code, upgrade, force, None, None, None) {
+            Ok(_) => ctx.log_notice(&format!("Loaded library from '{}'", file.display())),
+            Err(e) => ctx.log_warning(&format!(
+                "Failed loading library from '{}', {}",
+                file.display(),
+                e
+            )),
+        }
+    }
+}
+
+// The AOF rewrite start event is fired on the rewrite child, the key that is added
+// there emits the libraries on the rewritten AOF, see rdb::aof_rewrite.
+extern "C" fn on_persistence_event(
+    ctx: *mut raw::RedisModuleCtx,
+    _eid: raw::RedisModuleEvent,
+    subevent: u64,
+    _data: *mut c_void,
+) {
+    if subevent != raw::REDISMODULE_SUBEVENT_PERSISTENCE_AOF_START as u64 {
+        return;
+    }
+    let ctx = Context::new(ctx);
+    // with the RDB preamble the libraries are saved on the aux data
+    if get_redis_config(&ctx, "aof-use-rdb-preamble").as_deref() != Some("no") {
+        return;
+    }
+    if get_globals().modules.is_empty() && get_libraries().is_empty() {
+        return;
+    }
+    add_aof_rewrite_key(&ctx);
+}
+
+fn get_redis_config(ctx: &Context, name: &str) -> Option<String> {
+    let res = ctx
+        .call_ext(
+            "config",
+            &CallOptionsBuilder::new().constract(),
+            &["get".as_bytes(), name.as_bytes()],
+        )
+        .ok()?;
+    match redis_value_to_call_reply(res) {
+        CallResult::Array(mut arr) if arr.len() == 2 => match arr.pop() {
+            Some(CallResult::BulkStr(s)) | Some(CallResult::SimpleStr(s)) => Some(s),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn load_backend(ctx: &Context, global_ctx: &mut GlobalCtx, path: &str) -> Result<(), String> {
+    let lib =
+        unsafe { Library::new(path) }.map_err(|e| format!("Failed loading '{}', {}", path, e))?;
+    {
+        let descriptor: Symbol<*const BackendPluginDescriptor> =
+            unsafe { lib.get(GEARS_PLUGIN_DESCRIPTOR_SYMBOL) }.map_err(|_| {
+                format!(
+                    "'{}' does not export a plugin descriptor, it was probably built against an older plugin API (expected plugin API version {})",
+                    path, GEARS_PLUGIN_API_VERSION
+                )
+            })?;
+        let descriptor = unsafe { &**descriptor };
+        if descriptor.api_version != GEARS_PLUGIN_API_VERSION {
+            return Err(format!(
+                "'{}' was built against plugin API version {} while version {} is required, please rebuild the plugin",
+                path, descriptor.api_version, GEARS_PLUGIN_API_VERSION
+            ));
+        }
+        let (plugin_name, build_info) = unsafe {
+            (
+                CStr::from_ptr(descriptor.name).to_string_lossy(),
+                CStr::from_ptr(descriptor.build_info).to_string_lossy(),
+            )
+        };
+        ctx.log_notice(&format!(
+            "loading backend plugin '{}' ({}), plugin API version {}",
+            plugin_name, build_info, descriptor.api_version
+        ));
+        if global_ctx.backends.contains_key(plugin_name.as_ref()) {
+            return Err(format!("Backend {} already exists", plugin_name));
+        }
+
+        let func: Symbol<unsafe fn() -> *mut dyn BackendCtxInterface> =
+            unsafe { lib.get(b"initialize_plugin") }.map_err(|e| {
+                format!(
+                    "Failed finding 'initialize_plugin' symbol on '{}', {}",
+                    path, e
+                )
+            })?;
+        let backend = unsafe { Box::from_raw(func()) };
+        let name = backend.get_name();
+        if name != plugin_name {
+            return Err(format!(
+                "Backend name '{}' does not match the plugin descriptor name '{}'",
+                name, plugin_name
+            ));
+        }
+        ctx.log_notice(&format!(
+            "registering backend: {}, version: {}",
+            name,
+            backend.get_version()
+        ));
+        backend
+            .initialize(BackendCtx {
+                allocator: &redis_module::ALLOC,
+                log: Box::new(|msg| get_ctx().log_notice(msg)),
+                get_on_oom_policy: Box::new(|| {
+                    get_globals()
+                        .config
+                        .libraray_fatal_failure_policy
+                        .policy
+                        .clone()
+                }),
+                get_lock_timeout: Box::new(|| get_globals().config.lock_regis_timeout.size),
+            })
+            .map_err(|e| format!("Failed loading {} backend, {}", name, e.get_msg()))?;
+        global_ctx.backends.insert(name.to_string(), backend);
+    }
+    global_ctx.plugins.push(lib);
+    Ok(())
+}
+
 fn js_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
     match ctx.get_redis_version() {
         Ok(v) => {
@@ -470,6 +1183,7 @@ fn js_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
         let inner_autenticated_ctx = RedisModule_GetDetachedThreadSafeContext.unwrap()(ctx.ctx);
         let mut global_ctx = GlobalCtx {
             libraries: HashMap::new(),
+            modules: HashMap::new(),
             redis_ctx: Context::new(inner_ctx),
             authenticated_redis_ctx: Context::new(inner_autenticated_ctx),
             backends: HashMap::new(),
@@ -521,69 +1235,168 @@ fn js_init(ctx: &Context, args: &Vec<RedisString>) -> Status {
                         );
                     }
                 }),
+                Box::new(|ms, callback| {
+                    // used to wait for a stream batch to fill up
+                    get_ctx().create_timer(
+                        Duration::from_millis(ms),
+                        |_ctx, callback: Box<dyn FnOnce()>| callback(),
+                        callback,
+                    );
+                }),
+                Box::new(|key| match get_stream_last_id(key) {
+                    Ok((0, 0)) | Err(_) => None,
+                    Ok((ms, seq)) => Some(RedisModuleStreamID { ms: ms, seq: seq }),
+                }),
             ),
             notifications_ctx: KeysNotificationsCtx::new(),
             config: Config::new(),
             avoid_key_space_notifications: false,
+            running_library: None,
+            loading_from_disk: false,
+            commands_keys_positions: HashMap::new(),
+            function_commands: HashMap::new(),
         };
 
-        let v8_path = match args.into_iter().next() {
-            Some(a) => a,
-            None => {
-                ctx.log_warning("Path to libredisgears_v8_plugin.so must be specified");
-                return Status::Err;
-            }
-        }
-        .try_as_str();
-        let v8_path = match v8_path {
-            Ok(a) => a,
-            Err(_) => {
-                ctx.log_warning("Path to libredisgears_v8_plugin.so must be specified");
-                return Status::Err;
-            }
-        };
-        let lib = match Library::new(v8_path) {
-            Ok(l) => l,
+        let (backends_paths, commands, _) = match split_module_args(args) {
+            Ok(r) => r,
             Err(e) => {
-                ctx.log_warning(&format!("Failed loading '{}', {}", v8_path, e));
+                ctx.log_warning(&e);
                 return Status::Err;
             }
         };
-        {
-            let func: Symbol<unsafe fn() -> *mut dyn BackendCtxInterface> =
-                lib.get(b"initialize_plugin").unwrap();
-            let backend = Box::from_raw(func());
-            let name = backend.get_name();
-            ctx.log_notice(&format!("registering backend: {}", name));
-            if global_ctx.backends.contains_key(name) {
-                ctx.log_warning(&format!("Backend {} already exists", name));
+        if backends_paths.len() == 0 {
+            ctx.log_warning("At least one backend plugin must be specified (BACKEND <path>)");
+            return Status::Err;
+        }
+        for path in backends_paths {
+            if let Err(e) = load_backend(ctx, &mut global_ctx, path) {
+                ctx.log_warning(&e);
                 return Status::Err;
             }
-            if let Err(e) = backend.initialize(BackendCtx {
-                allocator: &redis_module::ALLOC,
-                log: Box::new(|msg| get_ctx().log_notice(msg)),
-                get_on_oom_policy: Box::new(|| {
-                    get_globals()
-                        .config
-                        .libraray_fatal_failure_policy
-                        .policy
-                        .clone()
-                }),
-                get_lock_timeout: Box::new(|| get_globals().config.lock_regis_timeout.size),
-            }) {
-                ctx.log_warning(&format!("Failed loading {} backend, {}", name, e.get_msg()));
+        }
+        for (name, command) in commands {
+            if let Err(e) = create_function_command(ctx, &mut global_ctx, name, command) {
+                ctx.log_warning(&e);
                 return Status::Err;
             }
-            global_ctx.backends.insert(name.to_string(), backend);
         }
-        global_ctx.plugins.push(lib);
 
         GLOBALS = Some(global_ctx);
     }
     Status::Ok
 }
 
-const fn js_info(_ctx: &InfoContext, _for_crash_report: bool) {}
+fn info_fields_to_str(fields: &[(String, String)]) -> String {
+    fields
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// library and consumer names are user given, replace the characters that
+// would break the INFO format the same way Redis does (getSafeInfoString).
+fn info_safe_name(name: &str) -> String {
+    name.replace(&['#', ':', '\r', '\n'][..], "_")
+}
+
+fn js_info(ctx: &InfoContext, for_crash_report: bool) {
+    let globals = match unsafe { GLOBALS.as_ref() } {
+        Some(g) => g,
+        None => return,
+    };
+
+    ctx.add_info_section(Some("general"));
+    ctx.add_info_field_long_long("number_of_libraries", globals.libraries.len() as i64);
+    ctx.add_info_field_str(
+        "running_library",
+        &globals
+            .running_library
+            .as_ref()
+            .map_or("none".to_string(), |v| info_safe_name(v)),
+    );
+
+    if for_crash_report {
+        // keep the crash report short and avoid taking locks that might be held by the crashing thread
+        return;
+    }
+
+    let (num_functions, num_stream_consumers, num_notifications_consumers) =
+        globals.libraries.values().fold((0, 0, 0), |(f, s, n), l| {
+            (
+                f + l.gears_lib_ctx.functions.len(),
+                s + l.gears_lib_ctx.stream_consumers.len(),
+                n + l.gears_lib_ctx.notifications_consumers.len(),
+            )
+        });
+    ctx.add_info_field_long_long("number_of_functions", num_functions as i64);
+    ctx.add_info_field_long_long("number_of_stream_consumers", num_stream_consumers as i64);
+    ctx.add_info_field_long_long(
+        "number_of_notifications_consumers",
+        num_notifications_consumers as i64,
+    );
+    let (queued_jobs, active_jobs) = globals.pool.as_ref().map_or((0, 0), |p| {
+        let p = p.lock().unwrap();
+        (p.queued_count(), p.active_count())
+    });
+    ctx.add_info_field_long_long("thread_pool_queued_jobs", queued_jobs as i64);
+    ctx.add_info_field_long_long("thread_pool_active_jobs", active_jobs as i64);
+
+    let mut backends = globals.backends.values().collect::<Vec<_>>();
+    backends.sort_by_key(|b| b.get_name());
+    for backend in backends {
+        ctx.add_info_section(Some(&format!("backend_{}", backend.get_name())));
+        for (name, val) in backend.get_info() {
+            ctx.add_info_field_str(&name, &val);
+        }
+    }
+
+    ctx.add_info_section(Some("libraries"));
+    for (name, l) in globals.libraries.iter() {
+        let mut fields = vec![
+            (
+                "engine".to_string(),
+                l.gears_lib_ctx.meta_data.engine.to_string(),
+            ),
+            (
+                "pending_jobs".to_string(),
+                l.compile_lib_internals.pending_jobs().to_string(),
+            ),
+        ];
+        fields.extend(l.lib_ctx.get_info());
+        ctx.add_info_field_str(&info_safe_name(name), &info_fields_to_str(&fields));
+    }
+
+    ctx.add_info_section(Some("stream_consumers"));
+    for (lib_name, l) in globals.libraries.iter() {
+        for (consumer_name, consumer) in l.gears_lib_ctx.stream_consumers.iter() {
+            let consumer = consumer.ref_cell.borrow();
+            let (max_lag, pending_records) =
+                consumer
+                    .consumed_streams
+                    .values()
+                    .fold((0, 0), |(lag, pending), info| {
+                        let info = info.ref_cell.borrow();
+                        (
+                            std::cmp::max(lag, info.last_lag),
+                            pending + info.pending_ids.len(),
+                        )
+                    });
+            let fields = vec![
+                (
+                    "streams".to_string(),
+                    consumer.consumed_streams.len().to_string(),
+                ),
+                ("pending_records".to_string(), pending_records.to_string()),
+                ("max_lag".to_string(), max_lag.to_string()),
+            ];
+            ctx.add_info_field_str(
+                &info_safe_name(&format!("{}.{}", lib_name, consumer_name)),
+                &info_fields_to_str(&fields),
+            );
+        }
+    }
+}
 
 pub(crate) fn verify_oom(flags: u8) -> bool {
     if (flags & FUNCTION_FLAG_NO_WRITES) == 0 {
@@ -610,12 +1423,13 @@ pub(crate) fn verify_ok_on_replica(flags: u8) -> bool {
     false
 }
 
-fn function_call_command(
+fn function_call_internal(
     ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
+    library_name: &str,
+    function_name: &str,
+    keys: Option<&[RedisString]>,
+    args: &[RedisString],
 ) -> RedisResult {
-    let library_name = args.next_arg()?.try_as_str()?;
-    let function_name = args.next_arg()?.try_as_str()?;
     let libraries = get_libraries();
 
     let lib = libraries.get(library_name);
@@ -649,71 +1463,320 @@ fn function_call_command(
         ));
     }
 
-    let args = args.collect::<Vec<redis_module::RedisString>>();
-    let args_iter = args.iter();
-
     {
         let _notification_blocker = get_notification_blocker();
-        function.func.call(&mut RunCtx {
-            ctx: ctx,
-            iter: args_iter,
-            flags: function.flags,
-        });
+        let _running_library_guard = get_running_library_guard(library_name);
+        function.func.call(&mut RunCtx::new(
+            ctx,
+            args,
+            keys,
+            function.flags,
+            &function.stats,
+        ));
     }
 
     Ok(RedisValue::NoReply)
 }
 
-fn library_extract_matadata(code: &str) -> Result<GearsLibraryMataData, RedisError> {
-    let shabeng = match code.split("\n").next() {
-        Some(s) => s,
-        None => return Err(RedisError::Str("could not extract library metadata")),
-    };
-    if !shabeng.starts_with("#!") {
-        return Err(RedisError::Str("could not find #! syntax"));
-    }
+fn function_call_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let library_name = args.next_arg()?.try_as_str()?;
+    let function_name = args.next_arg()?.try_as_str()?;
+    let args = args.collect::<Vec<RedisString>>();
+    function_call_internal(ctx, library_name, function_name, None, &args)
+}
 
-    let shabeng = shabeng.strip_prefix("#!").unwrap();
-    let mut data = shabeng.split(" ");
-    let engine = match data.next() {
-        Some(s) => s,
-        None => return Err(RedisError::Str("could not extract engine name")),
-    };
+// RG.FCALL <library name> <function name> <numkeys> [<key> ...] [<arg> ...]
+// the command is registered with the first key position so clients that do not
+// use COMMAND GETKEYS can still route it, the exact keys are given by the getkeys api.
+const FCALL_FIRST_KEY_POS: usize = 4;
 
-    let name = loop {
-        let d = match data.next() {
-            Some(s) => s,
-            None => return Err(RedisError::Str("Failed find 'name' property")),
-        };
-        let mut prop = d.split("=");
-        let prop_name = match prop.next() {
-            Some(s) => s,
-            None => return Err(RedisError::Str("could not extract property name")),
-        };
-        let prop_val = match prop.next() {
-            Some(s) => s,
-            None => return Err(RedisError::Str("could not extract property value")),
-        };
-        if prop_name.to_lowercase() != "name" {
-            return Err(RedisError::String(format!(
-                "unknown property '{}'",
-                prop_name
-            )));
+fn function_fcall_command(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let library_name = args.next_arg()?.try_as_str()?;
+    let function_name = args.next_arg()?.try_as_str()?;
+    function_call_with_keys(ctx, library_name, function_name, FCALL_FIRST_KEY_POS, args)
+}
+
+// <numkeys> [<key> ...] [<arg> ...], the first key is at the given position of the command.
+fn function_call_with_keys(
+    ctx: &Context,
+    library_name: &str,
+    function_name: &str,
+    first_key_pos: usize,
+    mut args: Skip<IntoIter<RedisString>>,
+) -> RedisResult {
+    let num_keys = args.next_u64()? as usize;
+    let args = args.collect::<Vec<RedisString>>();
+    if num_keys > args.len() {
+        return Err(RedisError::Str(
+            "Number of keys can not be greater than number of arguments",
+        ));
+    }
+
+    if ctx.is_keys_position_request() {
+        for i in 0..num_keys {
+            ctx.key_at_pos((first_key_pos + i) as i32);
         }
-        break prop_val;
+        return Ok(RedisValue::NoReply);
+    }
+
+    let (keys, args) = args.split_at(num_keys);
+    function_call_internal(ctx, library_name, function_name, Some(keys), args)
+}
+
+// <command name> <numkeys> [<key> ...] [<arg> ...]
+const FUNCTION_COMMAND_FIRST_KEY_POS: usize = 2;
+
+fn create_function_command(
+    ctx: &Context,
+    global_ctx: &mut GlobalCtx,
+    name: &str,
+    command: FunctionCommand,
+) -> Result<(), String> {
+    let flags = if command.read_only {
+        "readonly deny-script getkeys-api"
+    } else {
+        "write deny-script getkeys-api"
+    };
+    let name_c =
+        std::ffi::CString::new(name).map_err(|_| format!("Invalid command name '{}'", name))?;
+    let flags_c = std::ffi::CString::new(flags).unwrap();
+    let res = unsafe {
+        raw::RedisModule_CreateCommand.unwrap()(
+            ctx.ctx,
+            name_c.as_ptr(),
+            Some(function_command_handler),
+            flags_c.as_ptr(),
+            FUNCTION_COMMAND_FIRST_KEY_POS as i32,
+            FUNCTION_COMMAND_FIRST_KEY_POS as i32,
+            1,
+        )
+    };
+    if res != raw::REDISMODULE_OK as i32 {
+        return Err(format!("Failed creating command '{}'", name));
+    }
+    ctx.log_notice(&format!(
+        "registered command '{}' that invokes function {}.{}",
+        name, command.library, command.function
+    ));
+    global_ctx
+        .function_commands
+        .insert(name.to_lowercase(), command);
+    Ok(())
+}
+
+extern "C" fn function_command_handler(
+    ctx: *mut raw::RedisModuleCtx,
+    argv: *mut *mut raw::RedisModuleString,
+    argc: std::os::raw::c_int,
+) -> std::os::raw::c_int {
+    let context = Context::new(ctx);
+    let args = redis_module::decode_args(ctx, argv, argc);
+    let response = function_command_invoke(&context, args);
+    context.reply(response) as std::os::raw::c_int
+}
+
+fn function_command_invoke(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    let name = args[0].try_as_str()?.to_lowercase();
+    let command = match get_globals().function_commands.get(&name) {
+        Some(c) => c,
+        None => return Err(RedisError::String(format!("Unknown command {}", name))),
+    };
+    if command.read_only && !ctx.is_keys_position_request() {
+        // read only commands are allowed on replicas and on read only scripts,
+        // so they can only invoke functions that do not perform writes.
+        let flags = get_libraries()
+            .get(&command.library)
+            .and_then(|l| l.gears_lib_ctx.functions.get(&command.function))
+            .map(|f| f.flags);
+        if let Some(flags) = flags {
+            if (flags & FUNCTION_FLAG_NO_WRITES) == 0 {
+                return Err(RedisError::String(format!(
+                    "Function {}.{} might perform writes and can not be invoked by read only command {}",
+                    command.library, command.function, name
+                )));
+            }
+        }
+    }
+    function_call_with_keys(
+        ctx,
+        &command.library,
+        &command.function,
+        FUNCTION_COMMAND_FIRST_KEY_POS,
+        args.into_iter().skip(1),
+    )
+}
+
+// split the header into properties, values can be quoted to contain spaces.
+fn split_header_properties(header: &str) -> Result<Vec<String>, RedisError> {
+    let mut res = Vec::new();
+    let mut curr = String::new();
+    let mut in_quotes = false;
+    for c in header.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ' ' | '\t' | '\r' if !in_quotes => {
+                if curr.len() > 0 {
+                    res.push(std::mem::take(&mut curr));
+                }
+            }
+            _ => curr.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(RedisError::Str("unterminated quotes on library header"));
+    }
+    if curr.len() > 0 {
+        res.push(curr);
+    }
+    Ok(res)
+}
+
+// RedisGears 2.0.0, encoded as Redis encodes module versions, <major><minor:2 digits><patch:2 digits>
+const REDIS_GEARS_MODULE_VERSION: i32 = 20000;
+
+fn compare_versions(v1: &str, v2: &str) -> Result<std::cmp::Ordering, RedisError> {
+    let mut v1 = parse_version(v1)?;
+    let mut v2 = parse_version(v2)?;
+    let len = std::cmp::max(v1.len(), v2.len());
+    v1.resize(len, 0);
+    v2.resize(len, 0);
+    Ok(v1.cmp(&v2))
+}
+
+fn verify_min_gears_version(min_gears_version: &str) -> Result<(), RedisError> {
+    let version = REDIS_GEARS_MODULE_VERSION as u64;
+    let mut gears_version = vec![version / 10000, version / 100 % 100, version % 100];
+    let mut v = parse_version(min_gears_version)?;
+    let len = std::cmp::max(v.len(), gears_version.len());
+    v.resize(len, 0);
+    gears_version.resize(len, 0);
+    if v > gears_version {
+        return Err(RedisError::String(format!(
+            "Library requires RedisGears version {} or above",
+            min_gears_version
+        )));
+    }
+    Ok(())
+}
+
+// parse a version of the form <major>[.<minor>[.<patch>...]]
+fn parse_version(version: &str) -> Result<Vec<u64>, RedisError> {
+    version
+        .split(".")
+        .map(|v| v.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| RedisError::String(format!("Invalid version '{}'", version)))
+}
+
+// a library heap limit must be within the bounds of the library-maxmemory configuration
+fn parse_library_maxmemory(maxmemory: &str) -> Result<usize, RedisError> {
+    let config = &get_globals().config.library_maxmemory;
+    match maxmemory.parse::<i64>() {
+        Ok(v) if v >= config.min() && v <= config.max() => Ok(v as usize),
+        _ => Err(RedisError::String(format!(
+            "Invalid maxmemory '{}', must be a number of bytes between {} and {}",
+            maxmemory,
+            config.min(),
+            config.max()
+        ))),
+    }
+}
+
+fn library_extract_matadata(code: &str) -> Result<GearsLibraryMataData, RedisError> {
+    if bundle::is_bundle_code(code) {
+        let bundle = bundle::parse_bundle(code)?;
+        return library_matadata_from_properties(bundle.engine, bundle.properties, code);
+    }
+
+    let shabeng = match code.split("\n").next() {
+        Some(s) => s,
+        None => return Err(RedisError::Str("could not extract library metadata")),
+    };
+    if !shabeng.starts_with("#!") {
+        return Err(RedisError::Str("could not find #! syntax"));
+    }
+
+    let shabeng = shabeng.strip_prefix("#!").unwrap();
+    let mut data = split_header_properties(shabeng)?.into_iter();
+    let engine = match data.next() {
+        Some(s) => s,
+        None => return Err(RedisError::Str("could not extract engine name")),
+    };
+
+    let header_properties = data
+        .map(|d| match d.split_once("=") {
+            Some((n, v)) => Ok((n.to_string(), v.to_string())),
+            None => Err(RedisError::Str("could not extract property value")),
+        })
+        .collect::<Result<Vec<(String, String)>, RedisError>>()?;
+    library_matadata_from_properties(engine, header_properties, code)
+}
+
+// validates the library properties, given on the library header or on a bundle manifest
+fn library_matadata_from_properties(
+    engine: String,
+    library_properties: Vec<(String, String)>,
+    code: &str,
+) -> Result<GearsLibraryMataData, RedisError> {
+    let mut properties: HashMap<String, String> = HashMap::new();
+    let mut extra_properties = Vec::new();
+    for (prop_name, prop_val) in library_properties {
+        let prop_name = prop_name.to_lowercase();
+        match prop_name.as_ref() {
+            "name" | "version" | "description" | "author" | "api_version" | "min_gears_version"
+            | "maxmemory" => {
+                if properties.contains_key(&prop_name) {
+                    return Err(RedisError::String(format!(
+                        "property '{}' was given more than once",
+                        prop_name
+                    )));
+                }
+                properties.insert(prop_name, prop_val.to_string());
+            }
+            n if n.starts_with("x-") => {
+                extra_properties.push((prop_name.to_string(), prop_val.to_string()))
+            }
+            _ => {
+                return Err(RedisError::String(format!(
+                    "unknown property '{}'",
+                    prop_name
+                )))
+            }
+        }
+    }
+
+    let name = match properties.remove("name") {
+        Some(n) => n,
+        None => return Err(RedisError::Str("Failed find 'name' property")),
+    };
+
+    for version_prop in ["version", "api_version", "min_gears_version"] {
+        if let Some(v) = properties.get(version_prop) {
+            parse_version(v)?;
+        }
+    }
+
+    let maxmemory = match properties.get("maxmemory") {
+        Some(v) => Some(parse_library_maxmemory(v)?),
+        None => None,
     };
 
     Ok(GearsLibraryMataData {
-        engine: engine.to_string(),
-        name: name.to_string(),
+        engine: engine,
+        name: name,
         code: code.to_string(),
+        version: properties.remove("version"),
+        description: properties.remove("description"),
+        author: properties.remove("author"),
+        api_version: properties.remove("api_version"),
+        min_gears_version: properties.remove("min_gears_version"),
+        maxmemory: maxmemory,
+        extra_properties: extra_properties,
     })
 }
 
-fn function_del_command(
-    ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
-) -> RedisResult {
+fn function_del_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
     let name = args
         .next()
         .map_or(Err(RedisError::Str("function name was not given")), |s| {
@@ -729,26 +1792,406 @@ fn function_del_command(
     }
 }
 
+// RG.FUNCTION DELMODULE <module name>
+fn function_delmodule_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let name = args
+        .next()
+        .map_or(Err(RedisError::Str("module name was not given")), |s| {
+            s.try_as_str()
+        })?;
+    if !get_globals().modules.contains_key(name) {
+        return Err(RedisError::Str("module does not exists"));
+    }
+    let mut used_by = get_libraries()
+        .values()
+        .filter(|l| l.compile_lib_internals.uses_module(name))
+        .map(|l| l.gears_lib_ctx.meta_data.name.as_str())
+        .collect::<Vec<&str>>();
+    if used_by.len() > 0 {
+        used_by.sort();
+        return Err(RedisError::String(format!(
+            "Module {} is used by libraries: {}",
+            name,
+            used_by.join(", ")
+        )));
+    }
+    get_globals_mut().modules.remove(name);
+    ctx.replicate_verbatim();
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+// RG.FUNCTION MODULES [WITHCODE]
+fn function_modules_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let mut with_code = false;
+    while let Some(arg) = args.next() {
+        let arg_str = arg.try_as_str()?.to_lowercase();
+        match arg_str.as_ref() {
+            "withcode" => with_code = true,
+            _ => return Err(RedisError::String(format!("Unknown option '{}'", arg_str))),
+        }
+    }
+    let mut modules = get_globals()
+        .modules
+        .values()
+        .collect::<Vec<&GearsModule>>();
+    modules.sort_by(|m1, m2| m1.meta_data.name.cmp(&m2.meta_data.name));
+    Ok(RedisValue::Array(
+        modules
+            .into_iter()
+            .map(|m| {
+                let mut used_by = get_libraries()
+                    .values()
+                    .filter(|l| l.compile_lib_internals.uses_module(&m.meta_data.name))
+                    .map(|l| l.gears_lib_ctx.meta_data.name.to_string())
+                    .collect::<Vec<String>>();
+                used_by.sort();
+                let mut res = vec![
+                    RedisValue::BulkString("engine".to_string()),
+                    RedisValue::BulkString(m.meta_data.engine.to_string()),
+                    RedisValue::BulkString("name".to_string()),
+                    RedisValue::BulkString(m.meta_data.name.to_string()),
+                    RedisValue::BulkString("user".to_string()),
+                    RedisValue::BulkString(m.user.to_string()),
+                    RedisValue::BulkString("version".to_string()),
+                    optional_str_to_redis_value(&m.meta_data.version),
+                    RedisValue::BulkString("libraries".to_string()),
+                    RedisValue::Array(
+                        used_by
+                            .into_iter()
+                            .map(|l| RedisValue::BulkString(l))
+                            .collect::<Vec<RedisValue>>(),
+                    ),
+                ];
+                if with_code {
+                    res.push(RedisValue::BulkString("code".to_string()));
+                    res.push(RedisValue::BulkString(m.meta_data.code.to_string()));
+                }
+                RedisValue::Array(res)
+            })
+            .collect::<Vec<RedisValue>>(),
+    ))
+}
+
 fn function_call_result_to_redis_result(res: CallResult) -> RedisValue {
     match res {
         CallResult::Long(l) => RedisValue::Integer(l),
         CallResult::BulkStr(s) => RedisValue::BulkString(s),
         CallResult::SimpleStr(s) => RedisValue::SimpleString(s),
+        CallResult::StringBuffer(s) => RedisValue::StringBuffer(s),
         CallResult::Null => RedisValue::Null,
         CallResult::Double(d) => RedisValue::Float(d),
         CallResult::Error(s) => RedisValue::SimpleString(s),
-        CallResult::Array(arr) => RedisValue::Array(
+        CallResult::Array(arr) | CallResult::Set(arr) => RedisValue::Array(
             arr.into_iter()
                 .map(|v| function_call_result_to_redis_result(v))
                 .collect::<Vec<RedisValue>>(),
         ),
+        CallResult::Map(map) => RedisValue::Array(
+            map.into_iter()
+                .flat_map(|(k, v)| {
+                    [
+                        function_call_result_to_redis_result(k),
+                        function_call_result_to_redis_result(v),
+                    ]
+                })
+                .collect::<Vec<RedisValue>>(),
+        ),
+        CallResult::Bool(b) => RedisValue::Integer(b as i64),
+        CallResult::BigNumber(s) | CallResult::VerbatimString(s) => RedisValue::BulkString(s),
+        CallResult::Attribute(_, data) => function_call_result_to_redis_result(*data),
     }
 }
 
-fn function_debug_command(
-    _ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
-) -> RedisResult {
+// RG.FUNCTION DUMP [LIBRARY <library name> ...]
+fn function_dump_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        let arg_str = arg.try_as_str()?.to_lowercase();
+        match arg_str.as_ref() {
+            "library" => names.push(args.next_arg()?.try_as_str()?),
+            _ => return Err(RedisError::String(format!("Unknown option '{}'", arg_str))),
+        }
+    }
+    let libraries = get_libraries();
+    let dump_all = names.len() == 0;
+    let selected = if dump_all {
+        libraries.values().collect::<Vec<&GearsLibrary>>()
+    } else {
+        names
+            .into_iter()
+            .map(|n| {
+                libraries
+                    .get(n)
+                    .ok_or_else(|| RedisError::String(format!("Unknown library {}", n)))
+            })
+            .collect::<Result<Vec<&GearsLibrary>, RedisError>>()?
+    };
+    let dumped = selected
+        .iter()
+        .map(|l| DumpedLibrary {
+            name: l.gears_lib_ctx.meta_data.name.to_string(),
+            code: l.gears_lib_ctx.meta_data.code.to_string(),
+            user: l.gears_lib_ctx.user.ref_cell.borrow().to_string(),
+            gears_box_info: l.gears_box_lib.clone(),
+            config: l.gears_lib_ctx.config.clone(),
+            maxmemory: l.gears_lib_ctx.maxmemory,
+            stream_consumers: l
+                .gears_lib_ctx
+                .stream_consumers
+                .iter()
+                .map(|(name, c)| DumpedStreamConsumer {
+                    name: name.to_string(),
+                    streams: c
+                        .ref_cell
+                        .borrow()
+                        .get_streams_info()
+                        .map(|(name, ms, seq)| DumpedStream {
+                            name: name,
+                            ms: ms,
+                            seq: seq,
+                        })
+                        .collect(),
+                    paused: c.ref_cell.borrow().paused,
+                    paused_streams: c.ref_cell.borrow().get_paused_streams(),
+                })
+                .collect(),
+        })
+        .collect::<Vec<DumpedLibrary>>();
+    // the modules used by the dumped libraries, or all the modules if all the libraries are dumped
+    let modules = get_globals()
+        .modules
+        .values()
+        .filter(|m| {
+            dump_all
+                || selected
+                    .iter()
+                    .any(|l| l.compile_lib_internals.uses_module(&m.meta_data.name))
+        })
+        .map(|m| DumpedModule {
+            name: m.meta_data.name.to_string(),
+            code: m.meta_data.code.to_string(),
+        })
+        .collect::<Vec<DumpedModule>>();
+    Ok(RedisValue::StringBuffer(dump_libraries(&Dump {
+        libraries: dumped,
+        modules: modules,
+    })?))
+}
+
+enum RestorePolicy {
+    Append,
+    Replace,
+    Flush,
+}
+
+// Loads all the given libraries or none of them, on failure the libraries
+// are returned to the state they were before the restore. The libraries are
+// loaded as the given user and not as the user that is recorded on the payload,
+// otherwise any user could run code as another (possibly more privileged) user.
+// The modules of the payload are loaded (as the same user) before the libraries,
+// modules that already exist with the same code are kept as is.
+fn function_restore_internal(
+    user: &str,
+    dump: Dump,
+    policy: RestorePolicy,
+) -> Result<(), RedisError> {
+    let Dump {
+        libraries: dumped,
+        modules: dumped_modules,
+    } = dump;
+    let dumped_modules = dumped_modules
+        .into_iter()
+        .filter(|m| {
+            get_globals()
+                .modules
+                .get(&m.name)
+                .map_or(true, |e| e.meta_data.code != m.code)
+        })
+        .collect::<Vec<DumpedModule>>();
+    if let RestorePolicy::Append = policy {
+        if let Some(m) = dumped_modules
+            .iter()
+            .find(|m| get_globals().modules.contains_key(&m.name))
+        {
+            return Err(RedisError::String(format!(
+                "Module {} already exists",
+                m.name
+            )));
+        }
+    }
+
+    let libraries = get_libraries_mut();
+    let removed = match policy {
+        RestorePolicy::Flush => std::mem::take(libraries),
+        RestorePolicy::Replace => dumped
+            .iter()
+            .filter_map(|l| libraries.remove_entry(&l.name))
+            .collect::<HashMap<String, GearsLibrary>>(),
+        RestorePolicy::Append => {
+            if let Some(l) = dumped.iter().find(|l| libraries.contains_key(&l.name)) {
+                return Err(RedisError::String(format!(
+                    "Library {} already exists",
+                    l.name
+                )));
+            }
+            HashMap::new()
+        }
+    };
+
+    let mut replaced_modules = Vec::new();
+    let res = dumped_modules.into_iter().try_for_each(|m| {
+        if library_extract_matadata(&m.code)?.name != m.name {
+            return Err(RedisError::String(format!(
+                "Module name '{}' does not match the module code",
+                m.name
+            )));
+        }
+        let previous = get_globals_mut().modules.remove(&m.name);
+        replaced_modules.push((m.name.to_string(), previous));
+        function_load_module_internal(user.to_string(), &m.code, false)
+    });
+
+    let mut loaded = Vec::new();
+    let res = res.and_then(|_| {
+        dumped.into_iter().try_for_each(|l| {
+            if library_extract_matadata(&l.code)?.name != l.name {
+                return Err(RedisError::String(format!(
+                    "Library name '{}' does not match the library code",
+                    l.name
+                )));
+            }
+            function_load_intrernal(
+                user.to_string(),
+                &l.code,
+                false,
+                false,
+                l.gears_box_info,
+                Some(l.config),
+                l.maxmemory,
+            )?;
+            loaded.push(l.name.to_string());
+            // library was loaded, we must be able to find it
+            let lib = get_libraries().get(&l.name).unwrap();
+            for consumer in l.stream_consumers {
+                let consumer_data = match lib.gears_lib_ctx.stream_consumers.get(&consumer.name) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                for stream in consumer.streams {
+                    get_globals_mut().stream_ctx.update_stream_for_consumer(
+                        &stream.name,
+                        consumer_data,
+                        stream.ms,
+                        stream.seq,
+                    );
+                }
+                consumer_data.ref_cell.borrow_mut().paused = consumer.paused;
+                for stream in consumer.paused_streams {
+                    get_globals_mut()
+                        .stream_ctx
+                        .set_stream_paused(&stream, consumer_data, true);
+                }
+            }
+            Ok(())
+        })
+    });
+
+    if res.is_err() {
+        let libraries = get_libraries_mut();
+        for name in loaded {
+            libraries.remove(&name);
+        }
+        libraries.extend(removed);
+        let modules = &mut get_globals_mut().modules;
+        for (name, previous) in replaced_modules {
+            match previous {
+                Some(m) => modules.insert(name, m),
+                None => modules.remove(&name),
+            };
+        }
+    }
+    res
+}
+
+// RG.FUNCTION RESTORE <payload> [APPEND|REPLACE|FLUSH]
+fn function_restore_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let payload = args.next_arg()?;
+    let policy = match args.next() {
+        Some(p) => {
+            let p = p.try_as_str()?.to_lowercase();
+            match p.as_ref() {
+                "append" => RestorePolicy::Append,
+                "replace" => RestorePolicy::Replace,
+                "flush" => RestorePolicy::Flush,
+                _ => return Err(RedisError::String(format!("Unknown option '{}'", p))),
+            }
+        }
+        None => RestorePolicy::Append,
+    };
+    let dumped = restore_libraries(payload.as_slice())?;
+    let user = ctx.get_current_user()?;
+    function_restore_internal(&user, dumped, policy)?;
+    ctx.replicate_verbatim();
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+// RG.FUNCTION STATS RESET [<library name>]
+fn function_stats_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let sub_command = args.next_arg()?.try_as_str()?.to_lowercase();
+    if sub_command != "reset" {
+        return Err(RedisError::String(format!(
+            "Unknown subcommand {}",
+            sub_command
+        )));
+    }
+    let library_name = match args.next() {
+        Some(n) => Some(n.try_as_str()?),
+        None => None,
+    };
+    let libraries = get_libraries();
+    let libraries = match library_name {
+        Some(name) => match libraries.get(name) {
+            Some(l) => vec![l],
+            None => return Err(RedisError::String(format!("Unknown library {}", name))),
+        },
+        None => libraries.values().collect(),
+    };
+    for l in libraries {
+        for f in l.gears_lib_ctx.functions.values() {
+            *f.stats.lock().unwrap() = FunctionStats::new();
+        }
+    }
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+fn function_backends_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    if let Some(arg) = args.next() {
+        return Err(RedisError::String(format!(
+            "Unknown option '{}'",
+            arg.try_as_str()?
+        )));
+    }
+    let mut backends = get_backends_mut()
+        .values()
+        .map(|b| (b.get_name(), b.get_version()))
+        .collect::<Vec<(&str, String)>>();
+    backends.sort();
+    Ok(RedisValue::Array(
+        backends
+            .into_iter()
+            .map(|(name, version)| {
+                RedisValue::Array(vec![
+                    RedisValue::BulkString("name".to_string()),
+                    RedisValue::BulkString(name.to_string()),
+                    RedisValue::BulkString("version".to_string()),
+                    RedisValue::BulkString(version),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+fn function_debug_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
     let backend_name = args.next_arg()?.try_as_str()?;
     match backend_name {
         "panic_on_thread_pool" => {
@@ -781,11 +2224,16 @@ fn function_debug_command(
     let res = backend.debug(args.as_slice());
     match res {
         Ok(res) => Ok(function_call_result_to_redis_result(res)),
-        Err(e) => match e {
-            GearsApiError::Msg(msg) => Err(RedisError::String(msg)),
-        },
+        Err(e) => Err(gears_api_error_to_redis_error("", &e)),
     }
 }
+fn optional_str_to_redis_value(val: &Option<String>) -> RedisValue {
+    match val {
+        Some(v) => RedisValue::BulkString(v.to_string()),
+        None => RedisValue::Null,
+    }
+}
+
 fn function_list_command_flags(flags: u8) -> RedisValue {
     let mut res = Vec::new();
     if (flags & FUNCTION_FLAG_NO_WRITES) != 0 {
@@ -794,13 +2242,13 @@ fn function_list_command_flags(flags: u8) -> RedisValue {
     if (flags & FUNCTION_FLAG_ALLOW_OOM) != 0 {
         res.push(RedisValue::BulkString("allow-oom".to_string()));
     }
+    if (flags & FUNCTION_FLAG_RAW_ARGUMENTS) != 0 {
+        res.push(RedisValue::BulkString("raw-arguments".to_string()));
+    }
     RedisValue::Array(res)
 }
 
-fn function_list_command(
-    _ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
-) -> RedisResult {
+fn function_list_command(_ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
     let mut with_code = false;
     let mut lib = None;
     let mut verbosity = 0;
@@ -856,19 +2304,97 @@ fn function_list_command(
                     RedisValue::BulkString(l.gears_lib_ctx.meta_data.name.to_string()),
                     RedisValue::BulkString("pending_jobs".to_string()),
                     RedisValue::Integer(l.compile_lib_internals.pending_jobs() as i64),
+                    RedisValue::BulkString("maxmemory".to_string()),
+                    RedisValue::Integer(l.compile_lib_internals.maxmemory() as i64),
+                    RedisValue::BulkString("used_memory".to_string()),
+                    RedisValue::Integer(l.lib_ctx.get_used_memory() as i64),
                     RedisValue::BulkString("user".to_string()),
                     RedisValue::BulkString(l.gears_lib_ctx.user.ref_cell.borrow().to_string()),
+                    RedisValue::BulkString("version".to_string()),
+                    optional_str_to_redis_value(&l.gears_lib_ctx.meta_data.version),
+                    RedisValue::BulkString("description".to_string()),
+                    optional_str_to_redis_value(&l.gears_lib_ctx.meta_data.description),
+                    RedisValue::BulkString("author".to_string()),
+                    optional_str_to_redis_value(&l.gears_lib_ctx.meta_data.author),
+                    RedisValue::BulkString("api_version".to_string()),
+                    optional_str_to_redis_value(&l.gears_lib_ctx.meta_data.api_version),
+                    RedisValue::BulkString("min_gears_version".to_string()),
+                    optional_str_to_redis_value(&l.gears_lib_ctx.meta_data.min_gears_version),
+                    RedisValue::BulkString("properties".to_string()),
+                    RedisValue::Array(
+                        l.gears_lib_ctx
+                            .meta_data
+                            .extra_properties
+                            .iter()
+                            .flat_map(|(k, v)| {
+                                [
+                                    RedisValue::BulkString(k.to_string()),
+                                    RedisValue::BulkString(v.to_string()),
+                                ]
+                            })
+                            .collect::<Vec<RedisValue>>(),
+                    ),
+                    RedisValue::BulkString("modules".to_string()),
+                    RedisValue::Array(
+                        l.compile_lib_internals
+                            .used_modules()
+                            .into_iter()
+                            .map(|m| RedisValue::BulkString(m))
+                            .collect::<Vec<RedisValue>>(),
+                    ),
                     RedisValue::BulkString("functions".to_string()),
                     RedisValue::Array(if verbosity > 0 {
                         l.gears_lib_ctx
                             .functions
                             .iter()
                             .map(|(k, v)| {
+                                let stats = v.stats.lock().unwrap().clone();
+                                let num_finished =
+                                    stats.num_sync_completions + stats.num_async_completions;
                                 RedisValue::Array(vec![
                                     RedisValue::BulkString("name".to_string()),
                                     RedisValue::BulkString(k.to_string()),
                                     RedisValue::BulkString("flags".to_string()),
                                     function_list_command_flags(v.flags),
+                                    RedisValue::BulkString("num_calls".to_string()),
+                                    RedisValue::Integer(stats.num_calls as i64),
+                                    RedisValue::BulkString("num_errors".to_string()),
+                                    RedisValue::Integer(stats.num_errors as i64),
+                                    RedisValue::BulkString("num_sync_completions".to_string()),
+                                    RedisValue::Integer(stats.num_sync_completions as i64),
+                                    RedisValue::BulkString("num_async_completions".to_string()),
+                                    RedisValue::Integer(stats.num_async_completions as i64),
+                                    RedisValue::BulkString("last_error".to_string()),
+                                    RedisValue::BulkString(match &stats.last_error {
+                                        Some(e) => e.get_error_string(),
+                                        None => "None".to_string(),
+                                    }),
+                                    RedisValue::BulkString("last_error_kind".to_string()),
+                                    RedisValue::BulkString(match &stats.last_error {
+                                        Some(e) => e.get_kind().get_name().to_string(),
+                                        None => "None".to_string(),
+                                    }),
+                                    RedisValue::BulkString("last_error_stack_trace".to_string()),
+                                    RedisValue::BulkString(
+                                        match stats
+                                            .last_error
+                                            .as_ref()
+                                            .and_then(|e| e.get_stack_trace())
+                                        {
+                                            Some(s) => s.to_string(),
+                                            None => "None".to_string(),
+                                        },
+                                    ),
+                                    RedisValue::BulkString("total_exection_time_us".to_string()),
+                                    RedisValue::Integer(stats.total_execution_time as i64),
+                                    RedisValue::BulkString("avg_exection_time_us".to_string()),
+                                    RedisValue::Float(if num_finished > 0 {
+                                        stats.total_execution_time as f64 / num_finished as f64
+                                    } else {
+                                        0.0
+                                    }),
+                                    RedisValue::BulkString("max_exection_time_us".to_string()),
+                                    RedisValue::Integer(stats.max_execution_time as i64),
                                 ])
                             })
                             .collect::<Vec<RedisValue>>()
@@ -890,8 +2416,9 @@ fn function_list_command(
                                     let mut res = vec![
                                         RedisValue::BulkString("name".to_string()),
                                         RedisValue::BulkString(k.to_string()),
-                                        RedisValue::BulkString("prefix".to_string()),
-                                        RedisValue::BulkString(v.prefix.to_string()),
+                                    ];
+                                    res.extend(consumer_streams_to_redis_values(&v.streams));
+                                    res.extend(vec![
                                         RedisValue::BulkString("window".to_string()),
                                         RedisValue::Integer(v.window as i64),
                                         RedisValue::BulkString("trim".to_string()),
@@ -899,9 +2426,27 @@ fn function_list_command(
                                             (if v.trim { "enabled" } else { "disabled" })
                                                 .to_string(),
                                         ),
+                                        RedisValue::BulkString("batch_size".to_string()),
+                                        RedisValue::Integer(v.batch_size as i64),
+                                        RedisValue::BulkString("batch_max_wait".to_string()),
+                                        RedisValue::Integer(v.batch_max_wait as i64),
+                                        RedisValue::BulkString("max_attempts".to_string()),
+                                        RedisValue::Integer(v.max_attempts as i64),
+                                        RedisValue::BulkString("retry_backoff".to_string()),
+                                        RedisValue::Integer(v.retry_backoff as i64),
+                                        RedisValue::BulkString("dead_letter_stream".to_string()),
+                                        match &v.dead_letter_stream {
+                                            Some(s) => RedisValue::BulkString(s.to_string()),
+                                            None => RedisValue::Null,
+                                        },
+                                        RedisValue::BulkString("state".to_string()),
+                                        RedisValue::BulkString(
+                                            (if v.paused { "paused" } else { "running" })
+                                                .to_string(),
+                                        ),
                                         RedisValue::BulkString("num_streams".to_string()),
                                         RedisValue::Integer(v.consumed_streams.len() as i64),
-                                    ];
+                                    ]);
                                     if verbosity > 1 {
                                         res.push(RedisValue::BulkString("streams".to_string()));
                                         res.push(RedisValue::Array(
@@ -976,6 +2521,29 @@ fn function_list_command(
                                                             "None".to_string(),
                                                         )),
                                                     }
+                                                    res.push(RedisValue::BulkString(
+                                                        "state".to_string(),
+                                                    ));
+                                                    res.push(RedisValue::BulkString(
+                                                        (if v.paused {
+                                                            "paused"
+                                                        } else {
+                                                            "running"
+                                                        })
+                                                        .to_string(),
+                                                    ));
+                                                    res.push(RedisValue::BulkString(
+                                                        "records_retried".to_string(),
+                                                    ));
+                                                    res.push(RedisValue::Integer(
+                                                        v.records_retried as i64,
+                                                    ));
+                                                    res.push(RedisValue::BulkString(
+                                                        "records_dead_lettered".to_string(),
+                                                    ));
+                                                    res.push(RedisValue::Integer(
+                                                        v.records_dead_lettered as i64,
+                                                    ));
                                                     if verbosity > 2 {
                                                         res.push(RedisValue::BulkString(
                                                             "pending_ids".to_string(),
@@ -1044,6 +2612,25 @@ fn function_list_command(
                             })
                             .collect::<Vec<RedisValue>>(),
                     ),
+                    RedisValue::BulkString("previous_versions".to_string()),
+                    RedisValue::Array(
+                        l.previous_versions
+                            .iter()
+                            .map(|v| {
+                                let mut res = vec![
+                                    RedisValue::BulkString("version".to_string()),
+                                    optional_str_to_redis_value(&v.meta_data.version),
+                                    RedisValue::BulkString("user".to_string()),
+                                    RedisValue::BulkString(v.user.to_string()),
+                                ];
+                                if with_code {
+                                    res.push(RedisValue::BulkString("code".to_string()));
+                                    res.push(RedisValue::BulkString(v.meta_data.code.to_string()));
+                                }
+                                RedisValue::Array(res)
+                            })
+                            .collect::<Vec<RedisValue>>(),
+                    ),
                 ];
                 if with_code {
                     res.push(RedisValue::BulkString("code".to_string()));
@@ -1051,133 +2638,867 @@ fn function_list_command(
                         l.gears_lib_ctx.meta_data.code.to_string(),
                     ));
                 }
-                if verbosity > 0 {
-                    res.push(RedisValue::BulkString("gears_box_info".to_string()));
-                    let gears_box_info_str = serde_json::to_string(&l.gears_box_lib).unwrap();
-                    res.push(to_redis_value(
-                        serde_json::from_str(&gears_box_info_str).unwrap(),
-                    ));
+                if verbosity > 0 {
+                    res.push(RedisValue::BulkString("gears_box_info".to_string()));
+                    let gears_box_info_str = serde_json::to_string(&l.gears_box_lib).unwrap();
+                    res.push(to_redis_value(
+                        serde_json::from_str(&gears_box_info_str).unwrap(),
+                    ));
+                }
+                RedisValue::Array(res)
+            })
+            .collect::<Vec<RedisValue>>(),
+    ))
+}
+
+fn gears_api_error_to_redis_error(context: &str, err: &GearsApiError) -> RedisError {
+    let err = err.clone().wrap(err.get_kind(), context);
+    RedisError::String(err.get_error_string())
+}
+
+pub(crate) fn function_load_revert(
+    mut gears_library: GearsLibraryCtx,
+    libraries: &mut HashMap<String, GearsLibrary>,
+) {
+    if let Some(old_lib) = gears_library.old_lib.take() {
+        for revert_data in gears_library.revert_stream_consumers {
+            let stream_data = gears_library
+                .stream_consumers
+                .get(&revert_data.name)
+                .unwrap();
+            let mut s_d = stream_data.ref_cell.borrow_mut();
+            s_d.set_consumer(revert_data.ctx);
+            s_d.set_window(revert_data.window);
+            s_d.set_trim(revert_data.trim);
+            let (batch_size, batch_max_wait) = revert_data.batch;
+            s_d.set_batch(batch_size, batch_max_wait);
+            let (max_attempts, retry_backoff, dead_letter_stream) = revert_data.retry_policy;
+            s_d.set_retry_policy(max_attempts, retry_backoff, dead_letter_stream);
+        }
+
+        for (name, key, callback) in gears_library.revert_notifications_consumers {
+            let notification_consumer = gears_library.notifications_consumers.get(&name).unwrap();
+            let mut s_d = notification_consumer.borrow_mut();
+            s_d.set_key(key);
+            let _ = s_d.set_callback(callback);
+        }
+
+        libraries.insert(gears_library.meta_data.name, *old_lib);
+    }
+}
+
+// A library that was compiled and loaded but not yet committed, the library
+// it replaces (if any) is kept on the library ctx so it can be reverted.
+struct PendingLibrary {
+    gears_library: GearsLibraryCtx,
+    lib_ctx: Box<dyn LibraryCtxInterface>,
+    compile_lib_internals: Arc<CompiledLibraryInternals>,
+    gears_box_lib: Option<GearsBoxLibraryInfo>,
+}
+
+// the heap limit given on load takes precedence over the one given on the
+// library properties, if none was given the library-maxmemory configuration is used.
+// The library properties are set by the library author, so they can only lower
+// the configured limit.
+fn function_compile_library(
+    meta_data: &GearsLibraryMataData,
+    code: &str,
+    maxmemory: Option<usize>,
+) -> Result<(Box<dyn LibraryCtxInterface>, Arc<CompiledLibraryInternals>), RedisError> {
+    let backend_name = meta_data.engine.as_str();
+    let backend = get_backends_mut().get_mut(backend_name);
+    if backend.is_none() {
+        return Err(RedisError::String(format!(
+            "Unknown backend {}",
+            backend_name
+        )));
+    }
+    let backend = backend.unwrap();
+    // a bundle is compiled from its entry point, the other files can be imported by it
+    let (code, bundle_entry, bundle_files) = if bundle::is_bundle_code(code) {
+        let bundle = bundle::parse_bundle(code)?;
+        (bundle.entry_code, bundle.entry, bundle.files)
+    } else {
+        (code.to_string(), "".to_string(), HashMap::new())
+    };
+    let config_maxmemory = get_globals().config.library_maxmemory.size;
+    let maxmemory = maxmemory
+        .or(meta_data.maxmemory.map(|m| m.min(config_maxmemory)))
+        .unwrap_or(config_maxmemory);
+    let compile_lib_ctx =
+        CompiledLibraryAPI::new(backend_name, bundle_entry, bundle_files, maxmemory);
+    let compile_lib_internals = compile_lib_ctx.take_internals();
+    let lib_ctx = backend.compile_library(&code, Box::new(compile_lib_ctx));
+    match lib_ctx {
+        Err(e) => Err(gears_api_error_to_redis_error(
+            "Failed library compilation ",
+            &e,
+        )),
+        Ok(lib_ctx) => Ok((lib_ctx, compile_lib_internals)),
+    }
+}
+
+fn verify_no_downgrade(
+    old_lib: &GearsLibrary,
+    meta_data: &GearsLibraryMataData,
+) -> Result<(), RedisError> {
+    // versions were already validated when the metadata was extracted
+    let downgrade_from = match &meta_data.version {
+        Some(new_version) => {
+            old_lib
+                .gears_lib_ctx
+                .meta_data
+                .version
+                .as_ref()
+                .filter(|old_version| {
+                    matches!(
+                        compare_versions(new_version, old_version),
+                        Ok(std::cmp::Ordering::Less)
+                    )
+                })
+        }
+        None => None,
+    };
+    match downgrade_from {
+        Some(old_version) => Err(RedisError::String(format!(
+            "Library {} version {} is older than the loaded version {}, use FORCE to downgrade",
+            &meta_data.name,
+            meta_data.version.as_ref().unwrap(),
+            old_version
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn function_load_prepare(
+    user: String,
+    code: &str,
+    upgrade: bool,
+    force: bool,
+    gears_box_lib: Option<GearsBoxLibraryInfo>,
+    config: Option<LibraryConfig>,
+    maxmemory: Option<usize>,
+) -> Result<PendingLibrary, RedisError> {
+    let meta_data = library_extract_matadata(code)?;
+    if let Some(min_gears_version) = &meta_data.min_gears_version {
+        verify_min_gears_version(min_gears_version)?;
+    }
+    // as the configuration, the heap limit given on load is kept on upgrade
+    let maxmemory = maxmemory.or_else(|| {
+        get_libraries()
+            .get(&meta_data.name)
+            .and_then(|l| l.gears_lib_ctx.maxmemory)
+    });
+    let (lib_ctx, compile_lib_internals) = function_compile_library(&meta_data, code, maxmemory)?;
+    let libraries = get_libraries_mut();
+    let old_lib = libraries.remove(&meta_data.name);
+    if old_lib.is_some() && !upgrade {
+        let err = Err(RedisError::String(format!(
+            "Library {} already exists",
+            &meta_data.name
+        )));
+        libraries.insert(meta_data.name, old_lib.unwrap());
+        return err;
+    }
+    if !force {
+        if let Some(l) = &old_lib {
+            if let Err(e) = verify_no_downgrade(l, &meta_data) {
+                libraries.insert(meta_data.name, old_lib.unwrap());
+                return Err(e);
+            }
+        }
+    }
+    // an upgrade keeps the configuration unless a new one was given
+    let config = config.unwrap_or_else(|| {
+        old_lib
+            .as_ref()
+            .map_or_else(LibraryConfig::new, |l| l.gears_lib_ctx.config.clone())
+    });
+    let mut gears_library = GearsLibraryCtx {
+        user: Arc::new(RefCellWrapper {
+            ref_cell: RefCell::new(user),
+        }),
+        meta_data: meta_data,
+        config: config,
+        maxmemory: maxmemory,
+        functions: HashMap::new(),
+        stream_consumers: HashMap::new(),
+        notifications_consumers: HashMap::new(),
+        revert_stream_consumers: Vec::new(),
+        revert_notifications_consumers: Vec::new(),
+        old_lib: old_lib.map_or(None, |v| Some(Box::new(v))),
+        dry_run: None,
+    };
+    let res = lib_ctx.load_library(&mut gears_library);
+    if let Err(err) = res {
+        let ret = Err(gears_api_error_to_redis_error(
+            "Failed loading library, ",
+            &err,
+        ));
+        function_load_revert(gears_library, libraries);
+        return ret;
+    }
+    if gears_library.functions.len() == 0
+        && gears_library.stream_consumers.len() == 0
+        && gears_library.notifications_consumers.len() == 0
+    {
+        function_load_revert(gears_library, libraries);
+        return Err(RedisError::Str(
+            "No function nor registrations was registered",
+        ));
+    }
+    Ok(PendingLibrary {
+        gears_library: gears_library,
+        lib_ctx: lib_ctx,
+        compile_lib_internals: compile_lib_internals,
+        gears_box_lib: gears_box_lib,
+    })
+}
+
+fn function_load_commit(pending: PendingLibrary) {
+    let PendingLibrary {
+        mut gears_library,
+        lib_ctx,
+        compile_lib_internals,
+        gears_box_lib,
+    } = pending;
+    let previous_versions = match gears_library.old_lib.take() {
+        Some(old_lib) => {
+            let old_lib = *old_lib;
+            let mut previous_versions = old_lib.previous_versions;
+            previous_versions.push_front(PreviousLibraryVersion {
+                user: old_lib.gears_lib_ctx.user.ref_cell.borrow().to_string(),
+                meta_data: old_lib.gears_lib_ctx.meta_data,
+                gears_box_lib: old_lib.gears_box_lib,
+                config: old_lib.gears_lib_ctx.config,
+                maxmemory: old_lib.gears_lib_ctx.maxmemory,
+            });
+            previous_versions.truncate(get_globals().config.library_history_size.size);
+            previous_versions
+        }
+        None => VecDeque::new(),
+    };
+    get_libraries_mut().insert(
+        gears_library.meta_data.name.to_string(),
+        GearsLibrary {
+            gears_lib_ctx: gears_library,
+            lib_ctx: lib_ctx,
+            compile_lib_internals: compile_lib_internals,
+            gears_box_lib: gears_box_lib,
+            previous_versions: previous_versions,
+        },
+    );
+}
+
+// Re-loads the previous version at the given index of the library history,
+// stream consumers positions are kept as on any upgrade. The current version
+// and the versions newer than the one rolled back to are dropped.
+fn function_rollback_internal(name: &str, index: usize) -> Result<(), RedisError> {
+    // the library is moved out of the libraries dictionary while loading,
+    // copy whatever is needed from the previous version beforehand.
+    let (user, code, gears_box_lib, config, maxmemory) = match get_libraries().get(name) {
+        Some(l) => {
+            let v = &l.previous_versions[index];
+            (
+                v.user.to_string(),
+                v.meta_data.code.to_string(),
+                v.gears_box_lib.clone(),
+                v.config.clone(),
+                v.maxmemory,
+            )
+        }
+        None => return Err(RedisError::String(format!("Unknown library {}", name))),
+    };
+    let mut pending = function_load_prepare(
+        user,
+        &code,
+        true,
+        true,
+        gears_box_lib,
+        Some(config),
+        maxmemory,
+    )?;
+    let older_versions = pending
+        .gears_library
+        .old_lib
+        .as_mut()
+        .unwrap()
+        .previous_versions
+        .split_off(index + 1);
+    function_load_commit(pending);
+    get_libraries_mut().get_mut(name).unwrap().previous_versions = older_versions;
+    Ok(())
+}
+
+// RG.FUNCTION ROLLBACK <library> [<version>]
+fn function_rollback_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let library_name = args.next_arg()?.try_as_str()?;
+    let version = match args.next() {
+        Some(v) => Some(v.try_as_str()?),
+        None => None,
+    };
+    let library = match get_libraries().get(library_name) {
+        Some(l) => l,
+        None => {
+            return Err(RedisError::String(format!(
+                "Unknown library {}",
+                library_name
+            )))
+        }
+    };
+    let index = match version {
+        Some(version) => library
+            .previous_versions
+            .iter()
+            .position(|v| v.meta_data.version.as_deref() == Some(version))
+            .ok_or_else(|| {
+                RedisError::String(format!(
+                    "Library {} has no previous version {}",
+                    library_name, version
+                ))
+            })?,
+        None if library.previous_versions.len() > 0 => 0,
+        None => {
+            return Err(RedisError::String(format!(
+                "Library {} has no previous versions",
+                library_name
+            )))
+        }
+    };
+    let previous_version = &library.previous_versions[index];
+    let code = previous_version.meta_data.code.to_string();
+    let config = serde_json::to_string(&previous_version.config).unwrap();
+    let maxmemory = previous_version
+        .maxmemory
+        .map_or("".to_string(), |m| m.to_string());
+    function_rollback_internal(library_name, index)?;
+    // the history is not persisted nor synced, replicate the code so
+    // replicas that do not have it can still perform the rollback.
+    redis_module::replicate(
+        ctx.ctx,
+        "_rg_internals.function_rollback",
+        &[library_name, &code, &config, &maxmemory],
+    );
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+// The internal commands are replicated by the module and must only be accepted
+// from the master or while loading the AOF, never from a regular client.
+fn verify_internal_command_caller(ctx: &Context) -> Result<(), RedisError> {
+    let flags = unsafe { raw::RedisModule_GetContextFlags.unwrap()(ctx.ctx) } as u32;
+    if flags & (raw::REDISMODULE_CTX_FLAGS_REPLICATED | raw::REDISMODULE_CTX_FLAGS_LOADING) as u32
+        == 0
+    {
+        return Err(RedisError::Str(
+            "Internal command can only be sent by the master or loaded from the AOF",
+        ));
+    }
+    Ok(())
+}
+
+// _rg_internals.function_rollback <library> <code> [<config> <maxmemory>]
+fn function_rollback_replicated(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    verify_internal_command_caller(ctx)?;
+    let mut args = args.into_iter().skip(1);
+    let library_name = args.next_arg()?.try_as_str()?;
+    let code = args.next_arg()?.try_as_str()?;
+    let config = match args.next() {
+        Some(c) => Some(
+            serde_json::from_str::<LibraryConfig>(c.try_as_str()?)
+                .map_err(|e| RedisError::String(format!("Invalid library config, {}", e)))?,
+        ),
+        None => None,
+    };
+    let maxmemory = match args.next() {
+        Some(m) if m.try_as_str()?.len() > 0 => Some(m.try_as_str()?.parse::<usize>()?),
+        _ => None,
+    };
+    let library = match get_libraries().get(library_name) {
+        Some(l) => l,
+        None => {
+            return Err(RedisError::String(format!(
+                "No such library '{}'",
+                library_name
+            )))
+        }
+    };
+    match library
+        .previous_versions
+        .iter()
+        .position(|v| v.meta_data.code == code)
+    {
+        Some(index) => function_rollback_internal(library_name, index)?,
+        None => {
+            let user = library.gears_lib_ctx.user.ref_cell.borrow().to_string();
+            function_load_intrernal(user, code, true, true, None, config, maxmemory)?;
+        }
+    }
+    ctx.replicate_verbatim();
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+pub(crate) fn function_load_intrernal(
+    user: String,
+    code: &str,
+    upgrade: bool,
+    force: bool,
+    gears_box_lib: Option<GearsBoxLibraryInfo>,
+    config: Option<LibraryConfig>,
+    maxmemory: Option<usize>,
+) -> RedisResult {
+    let pending =
+        function_load_prepare(user, code, upgrade, force, gears_box_lib, config, maxmemory)?;
+    function_load_commit(pending);
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
+// Loads all the given libraries or none of them, libraries that were already
+// loaded are reverted (in reverse order) if any of the libraries fails.
+fn function_deploy_internal(
+    user: &str,
+    codes: &[&str],
+    upgrade: bool,
+    force: bool,
+) -> Result<(), RedisError> {
+    let mut names = HashSet::new();
+    for code in codes {
+        let meta_data = library_extract_matadata(code)?;
+        if !names.insert(meta_data.name.to_string()) {
+            return Err(RedisError::String(format!(
+                "Library {} was given more than once",
+                meta_data.name
+            )));
+        }
+    }
+
+    let mut pending_libraries = Vec::new();
+    for code in codes {
+        match function_load_prepare(user.to_string(), code, upgrade, force, None, None, None) {
+            Ok(pending) => pending_libraries.push(pending),
+            Err(e) => {
+                let libraries = get_libraries_mut();
+                while let Some(pending) = pending_libraries.pop() {
+                    function_load_revert(pending.gears_library, libraries);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    for pending in pending_libraries {
+        function_load_commit(pending);
+    }
+    Ok(())
+}
+
+// RG.FUNCTION DEPLOY [UPGRADE] [FORCE] <library code> [<library code> ...]
+fn function_deploy_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let mut upgrade = false;
+    let mut force = false;
+    let mut codes = Vec::new();
+    while let Some(arg) = args.next() {
+        let arg_str = match arg.try_as_str() {
+            Ok(s) => s,
+            Err(_) => return Err(RedisError::Str("lib code must a valid string")),
+        };
+        if codes.len() == 0 {
+            match arg_str.to_lowercase().as_ref() {
+                "upgrade" => {
+                    upgrade = true;
+                    continue;
                 }
-                RedisValue::Array(res)
-            })
-            .collect::<Vec<RedisValue>>(),
-    ))
+                "force" => {
+                    force = true;
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        codes.push(arg_str);
+    }
+    if codes.len() == 0 {
+        return Err(RedisError::Str("Could not find library payload"));
+    }
+    let user = ctx.get_current_user()?;
+    function_deploy_internal(&user, &codes, upgrade, force)?;
+    ctx.replicate_verbatim();
+    Ok(RedisValue::SimpleStringStatic("OK"))
 }
 
-pub(crate) fn function_load_revert(
-    mut gears_library: GearsLibraryCtx,
-    libraries: &mut HashMap<String, GearsLibrary>,
-) {
-    if let Some(old_lib) = gears_library.old_lib.take() {
-        for (name, old_ctx, old_window, old_trim) in gears_library.revert_stream_consumers {
-            let stream_data = gears_library.stream_consumers.get(&name).unwrap();
-            let mut s_d = stream_data.ref_cell.borrow_mut();
-            s_d.set_consumer(old_ctx);
-            s_d.set_window(old_window);
-            s_d.set_trim(old_trim);
+// The streams of a consumer as fixed keys, "prefix" is only set for prefix
+// consumers so existing clients can keep reading it.
+fn consumer_streams_to_redis_values(streams: &ConsumerStreams) -> Vec<RedisValue> {
+    let spec = match streams {
+        ConsumerStreams::Prefix(p) | ConsumerStreams::Pattern(p) => {
+            RedisValue::BulkString(p.to_string())
         }
+        ConsumerStreams::Streams(s) => RedisValue::Array(
+            s.iter()
+                .map(|s| RedisValue::BulkString(s.to_string()))
+                .collect::<Vec<RedisValue>>(),
+        ),
+    };
+    vec![
+        RedisValue::BulkString("prefix".to_string()),
+        match streams {
+            ConsumerStreams::Prefix(p) => RedisValue::BulkString(p.to_string()),
+            _ => RedisValue::Null,
+        },
+        RedisValue::BulkString("streams_type".to_string()),
+        RedisValue::BulkString(streams.get_type().to_string()),
+        RedisValue::BulkString("streams_spec".to_string()),
+        spec,
+    ]
+}
 
-        for (name, key, callback) in gears_library.revert_notifications_consumers {
-            let notification_consumer = gears_library.notifications_consumers.get(&name).unwrap();
-            let mut s_d = notification_consumer.borrow_mut();
-            s_d.set_key(key);
-            let _ = s_d.set_callback(callback);
+// Reports the registrations of a library that was loaded with DRYRUN that
+// conflicts with the loaded libraries.
+fn function_dry_run_conflicts(
+    meta_data: &GearsLibraryMataData,
+    dry_run: &DryRunRegistrations,
+    upgrade: bool,
+    force: bool,
+) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    let libraries = get_libraries();
+    if let Some(old_lib) = libraries.get(&meta_data.name) {
+        if !upgrade {
+            conflicts.push(format!("Library {} already exists", &meta_data.name));
+        } else if !force {
+            if let Err(e) = verify_no_downgrade(old_lib, meta_data) {
+                conflicts.push(e.to_string());
+            }
         }
+    }
 
-        libraries.insert(gears_library.meta_data.name, *old_lib);
+    for (name, streams) in dry_run.stream_consumers.iter() {
+        for (lib_name, lib) in libraries.iter() {
+            for (consumer_name, consumer) in lib.gears_lib_ctx.stream_consumers.iter() {
+                let c = consumer.ref_cell.borrow();
+                if *lib_name == meta_data.name {
+                    // the consumers of the old library are replaced on upgrade
+                    if consumer_name == name && c.streams != *streams {
+                        conflicts.push(format!("Can not upgrade an existing consumer with different streams, consumer: '{}', old: {}, new: {}.",
+                            name, c.streams, streams));
+                    }
+                    continue;
+                }
+                if streams.overlaps(&c.streams) {
+                    conflicts.push(format!(
+                        "Stream consumer '{}' {} overlaps with stream consumer '{}.{}' {}",
+                        name, streams, lib_name, consumer_name, c.streams
+                    ));
+                }
+            }
+        }
+    }
+
+    for (name, key) in dry_run.notifications_consumers.iter() {
+        for (lib_name, lib) in libraries.iter() {
+            if *lib_name == meta_data.name {
+                continue;
+            }
+            for (consumer_name, consumer) in lib.gears_lib_ctx.notifications_consumers.iter() {
+                if key.overlaps(consumer.borrow().get_key()) {
+                    conflicts.push(format!(
+                        "Notification consumer '{}' keys overlaps with notification consumer '{}.{}'",
+                        name, lib_name, consumer_name
+                    ));
+                }
+            }
+        }
     }
+    conflicts
 }
 
-pub(crate) fn function_load_intrernal(
+// Compiles and loads the library on a throw-away library ctx and reports
+// what would have been registered, nothing is kept nor replicated.
+fn function_load_dry_run(
     user: String,
     code: &str,
     upgrade: bool,
-    gears_box_lib: Option<GearsBoxLibraryInfo>,
+    force: bool,
+    config: Option<LibraryConfig>,
+    maxmemory: Option<usize>,
 ) -> RedisResult {
     let meta_data = library_extract_matadata(code)?;
-    let backend_name = meta_data.engine.as_str();
-    let backend = get_backends_mut().get_mut(backend_name);
-    if backend.is_none() {
-        return Err(RedisError::String(format!(
-            "Unknown backend {}",
-            backend_name
-        )));
-    }
-    let backend = backend.unwrap();
-    let compile_lib_ctx = CompiledLibraryAPI::new();
-    let compile_lib_internals = compile_lib_ctx.take_internals();
-    let lib_ctx = backend.compile_library(code, Box::new(compile_lib_ctx));
-    let lib_ctx = match lib_ctx {
-        Err(e) => match e {
-            GearsApiError::Msg(s) => {
-                return Err(RedisError::String(format!(
-                    "Failed library compilation {}",
-                    s
-                )))
-            }
-        },
-        Ok(lib_ctx) => lib_ctx,
-    };
-    let libraries = get_libraries_mut();
-    let old_lib = libraries.remove(&meta_data.name);
-    if old_lib.is_some() && !upgrade {
-        let err = Err(RedisError::String(format!(
-            "Library {} already exists",
-            &meta_data.name
-        )));
-        libraries.insert(meta_data.name, old_lib.unwrap());
-        return err;
+    if let Some(min_gears_version) = &meta_data.min_gears_version {
+        verify_min_gears_version(min_gears_version)?;
     }
+    let old_lib = get_libraries().get(&meta_data.name).filter(|_| upgrade);
+    let maxmemory = maxmemory.or_else(|| old_lib.and_then(|l| l.gears_lib_ctx.maxmemory));
+    let (lib_ctx, _compile_lib_internals) = function_compile_library(&meta_data, code, maxmemory)?;
+    let config = config.unwrap_or_else(|| {
+        old_lib.map_or_else(LibraryConfig::new, |l| l.gears_lib_ctx.config.clone())
+    });
     let mut gears_library = GearsLibraryCtx {
         user: Arc::new(RefCellWrapper {
             ref_cell: RefCell::new(user),
         }),
         meta_data: meta_data,
+        config: config,
+        maxmemory: maxmemory,
         functions: HashMap::new(),
         stream_consumers: HashMap::new(),
         notifications_consumers: HashMap::new(),
         revert_stream_consumers: Vec::new(),
         revert_notifications_consumers: Vec::new(),
-        old_lib: old_lib.map_or(None, |v| Some(Box::new(v))),
+        old_lib: None,
+        dry_run: Some(DryRunRegistrations {
+            stream_consumers: Vec::new(),
+            notifications_consumers: Vec::new(),
+        }),
     };
-    let res = lib_ctx.load_library(&mut gears_library);
-    if let Err(err) = res {
-        let ret = match err {
-            GearsApiError::Msg(s) => {
-                let msg = format!("Failed loading library, {}", s);
-                Err(RedisError::String(msg))
-            }
-        };
-        function_load_revert(gears_library, libraries);
-        return ret;
+    if let Err(err) = lib_ctx.load_library(&mut gears_library) {
+        return Err(gears_api_error_to_redis_error(
+            "Failed loading library, ",
+            &err,
+        ));
     }
+    let dry_run = gears_library.dry_run.take().unwrap();
     if gears_library.functions.len() == 0
-        && gears_library.stream_consumers.len() == 0
-        && gears_library.notifications_consumers.len() == 0
+        && dry_run.stream_consumers.len() == 0
+        && dry_run.notifications_consumers.len() == 0
     {
-        function_load_revert(gears_library, libraries);
         return Err(RedisError::Str(
             "No function nor registrations was registered",
         ));
     }
-    gears_library.old_lib = None;
-    libraries.insert(
-        gears_library.meta_data.name.to_string(),
-        GearsLibrary {
-            gears_lib_ctx: gears_library,
-            _lib_ctx: lib_ctx,
-            compile_lib_internals: compile_lib_internals,
-            gears_box_lib: gears_box_lib,
+
+    let conflicts = function_dry_run_conflicts(&gears_library.meta_data, &dry_run, upgrade, force);
+    let mut functions = gears_library
+        .functions
+        .keys()
+        .map(|k| k.to_string())
+        .collect::<Vec<String>>();
+    functions.sort();
+    Ok(RedisValue::Array(vec![
+        RedisValue::BulkString("name".to_string()),
+        RedisValue::BulkString(gears_library.meta_data.name.to_string()),
+        RedisValue::BulkString("functions".to_string()),
+        RedisValue::Array(
+            functions
+                .into_iter()
+                .map(|f| RedisValue::BulkString(f))
+                .collect::<Vec<RedisValue>>(),
+        ),
+        RedisValue::BulkString("stream_consumers".to_string()),
+        RedisValue::Array(
+            dry_run
+                .stream_consumers
+                .iter()
+                .map(|(name, streams)| {
+                    let mut res = vec![
+                        RedisValue::BulkString("name".to_string()),
+                        RedisValue::BulkString(name.to_string()),
+                    ];
+                    res.extend(consumer_streams_to_redis_values(streams));
+                    RedisValue::Array(res)
+                })
+                .collect::<Vec<RedisValue>>(),
+        ),
+        RedisValue::BulkString("notifications_consumers".to_string()),
+        RedisValue::Array(
+            dry_run
+                .notifications_consumers
+                .iter()
+                .map(|(name, key)| {
+                    let (key_type, key) = match key {
+                        ConsumerKey::Key(k) => ("key", k),
+                        ConsumerKey::Prefix(p) => ("prefix", p),
+                    };
+                    RedisValue::Array(vec![
+                        RedisValue::BulkString("name".to_string()),
+                        RedisValue::BulkString(name.to_string()),
+                        RedisValue::BulkString(key_type.to_string()),
+                        RedisValue::BulkString(key.to_string()),
+                    ])
+                })
+                .collect::<Vec<RedisValue>>(),
+        ),
+        RedisValue::BulkString("conflicts".to_string()),
+        RedisValue::Array(
+            conflicts
+                .into_iter()
+                .map(|c| RedisValue::BulkString(c))
+                .collect::<Vec<RedisValue>>(),
+        ),
+    ]))
+}
+
+// A module is compiled by the libraries that import it, the new code of an upgraded module
+// is first compiled by a library that only imports it. Then the libraries that import
+// the module are reloaded with it, all of them or none of them.
+fn function_reload_module_dependents(name: &str) -> Result<(), RedisError> {
+    let module = get_globals().modules.get(name).unwrap();
+    let import_code = format!("import {};", serde_json::to_string(name).unwrap());
+    function_compile_library(&module.meta_data, &import_code, None)
+        .map_err(|e| RedisError::String(format!("Failed compiling module {}, {}", name, e)))?;
+
+    let mut dependents = get_libraries()
+        .values()
+        .filter(|l| l.compile_lib_internals.uses_module(name))
+        .map(|l| {
+            (
+                l.gears_lib_ctx.meta_data.name.to_string(),
+                l.gears_lib_ctx.user.ref_cell.borrow().to_string(),
+                l.gears_lib_ctx.meta_data.code.to_string(),
+                l.gears_box_lib.clone(),
+            )
+        })
+        .collect::<Vec<(String, String, String, Option<GearsBoxLibraryInfo>)>>();
+    // a consistent order, so the same library fails on every replica
+    dependents.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut pending_libraries = Vec::new();
+    for (_, user, code, gears_box_lib) in dependents {
+        match function_load_prepare(user, &code, true, true, gears_box_lib, None, None) {
+            Ok(pending) => pending_libraries.push(pending),
+            Err(e) => {
+                let libraries = get_libraries_mut();
+                while let Some(pending) = pending_libraries.pop() {
+                    function_load_revert(pending.gears_library, libraries);
+                }
+                return Err(RedisError::String(format!(
+                    "Failed reloading the libraries that import module {}, {}",
+                    name, e
+                )));
+            }
+        }
+    }
+
+    for mut pending in pending_libraries {
+        // the library code did not change, the replaced library is not a previous version
+        let previous_versions = pending
+            .gears_library
+            .old_lib
+            .as_mut()
+            .map(|l| std::mem::take(&mut l.previous_versions))
+            .unwrap_or_default();
+        let library_name = pending.gears_library.meta_data.name.to_string();
+        function_load_commit(pending);
+        get_libraries_mut()
+            .get_mut(&library_name)
+            .unwrap()
+            .previous_versions = previous_versions;
+    }
+    Ok(())
+}
+
+pub(crate) fn function_load_module_internal(
+    user: String,
+    code: &str,
+    upgrade: bool,
+) -> Result<(), RedisError> {
+    let meta_data = library_extract_matadata(code)?;
+    if let Some(min_gears_version) = &meta_data.min_gears_version {
+        verify_min_gears_version(min_gears_version)?;
+    }
+    if !get_backends_mut().contains_key(&meta_data.engine) {
+        return Err(RedisError::String(format!(
+            "Unknown backend {}",
+            meta_data.engine
+        )));
+    }
+    let modules = &mut get_globals_mut().modules;
+    if modules.contains_key(&meta_data.name) && !upgrade {
+        return Err(RedisError::String(format!(
+            "Module {} already exists",
+            meta_data.name
+        )));
+    }
+    let name = meta_data.name.to_string();
+    let old_module = modules.insert(
+        name.to_string(),
+        GearsModule {
+            meta_data: meta_data,
+            user: user,
         },
     );
-    Ok(RedisValue::SimpleStringStatic("OK"))
+    if let Some(old_module) = old_module {
+        if let Err(e) = function_reload_module_dependents(&name) {
+            get_globals_mut().modules.insert(name, old_module);
+            return Err(e);
+        }
+    }
+    Ok(())
 }
 
-fn function_load_command(
-    ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
-) -> RedisResult {
+fn parse_library_config(config: &str) -> Result<LibraryConfig, RedisError> {
+    match serde_json::from_str(config) {
+        Ok(serde_json::Value::Object(o)) => Ok(o),
+        _ => Err(RedisError::Str(
+            "Library configuration must be a json object",
+        )),
+    }
+}
+
+// RG.FUNCTION CONFIG GET <library> [<key>]
+// RG.FUNCTION CONFIG SET <library> <key> <value>
+fn function_config_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let sub_command = args.next_arg()?.try_as_str()?.to_lowercase();
+    let library_name = args.next_arg()?.try_as_str()?;
+    let library = match get_libraries_mut().get_mut(library_name) {
+        Some(l) => l,
+        None => {
+            return Err(RedisError::String(format!(
+                "Unknown library {}",
+                library_name
+            )))
+        }
+    };
+    match sub_command.as_ref() {
+        "get" => match args.next() {
+            Some(key) => Ok(library
+                .gears_lib_ctx
+                .config
+                .get(key.try_as_str()?)
+                .map_or(RedisValue::Null, |v| to_redis_value(v.clone()))),
+            None => Ok(to_redis_value(serde_json::Value::Object(
+                library.gears_lib_ctx.config.clone(),
+            ))),
+        },
+        "set" => {
+            let key = args.next_arg()?.try_as_str()?;
+            let value = args.next_arg()?.try_as_str()?;
+            // values that are not a valid json are kept as strings
+            let value = serde_json::from_str(value)
+                .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+            let config = &mut library.gears_lib_ctx.config;
+            let old_value = config.insert(key.to_string(), value);
+            let res = {
+                let _running_library_guard = get_running_library_guard(library_name);
+                library
+                    .lib_ctx
+                    .on_config_changed(&serde_json::to_string(config).unwrap())
+            };
+            if let Err(e) = res {
+                match old_value {
+                    Some(v) => config.insert(key.to_string(), v),
+                    None => config.remove(key),
+                };
+                return Err(gears_api_error_to_redis_error(
+                    "Failed applying library configuration, ",
+                    &e,
+                ));
+            }
+            ctx.replicate_verbatim();
+            Ok(RedisValue::SimpleStringStatic("OK"))
+        }
+        _ => Err(RedisError::String(format!(
+            "Unknown subcommand {}",
+            sub_command
+        ))),
+    }
+}
+
+fn function_load_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
     let mut upgrade = false;
+    let mut force = false;
+    let mut dry_run = false;
+    let mut module = false;
+    let mut config = None;
+    let mut maxmemory = None;
     let lib_code = loop {
         let arg = args.next_arg();
         if arg.is_err() {
@@ -1191,15 +3512,53 @@ fn function_load_command(
         let arg_str = arg_str.to_lowercase();
         match arg_str.as_ref() {
             "upgrade" => upgrade = true,
+            "force" => force = true,
+            "dryrun" => dry_run = true,
+            "module" => module = true,
+            "config" => config = Some(parse_library_config(args.next_arg()?.try_as_str()?)?),
+            "maxmemory" => {
+                maxmemory = Some(parse_library_maxmemory(args.next_arg()?.try_as_str()?)?)
+            }
             _ => break Ok(arg),
         }
     }?;
-    let lib_code_slice = match lib_code.try_as_str() {
-        Ok(s) => s,
-        Err(_) => return Err(RedisError::Str("lib code must a valid string")),
+    let bundle_code;
+    let lib_code_slice = if bundle::is_zip_payload(lib_code.as_slice()) {
+        bundle_code = bundle::bundle_code_from_payload(lib_code.as_slice());
+        bundle_code.as_str()
+    } else {
+        match lib_code.try_as_str() {
+            Ok(s) => s,
+            Err(_) => return Err(RedisError::Str("lib code must a valid string")),
+        }
     };
     let user = ctx.get_current_user()?;
-    match function_load_intrernal(user, lib_code_slice, upgrade, None) {
+    if module {
+        if dry_run {
+            return Err(RedisError::Str("DRYRUN is not supported for modules"));
+        }
+        if config.is_some() {
+            return Err(RedisError::Str("CONFIG is not supported for modules"));
+        }
+        if maxmemory.is_some() {
+            return Err(RedisError::Str("MAXMEMORY is not supported for modules"));
+        }
+        function_load_module_internal(user, lib_code_slice, upgrade)?;
+        ctx.replicate_verbatim();
+        return Ok(RedisValue::SimpleStringStatic("OK"));
+    }
+    if dry_run {
+        return function_load_dry_run(user, lib_code_slice, upgrade, force, config, maxmemory);
+    }
+    match function_load_intrernal(
+        user,
+        lib_code_slice,
+        upgrade,
+        force,
+        None,
+        config,
+        maxmemory,
+    ) {
         Ok(r) => {
             ctx.replicate_verbatim();
             Ok(r)
@@ -1240,7 +3599,7 @@ fn to_redis_value(val: serde_json::Value) -> RedisValue {
 
 fn function_search_lib_command(
     _ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
+    mut args: Skip<IntoIter<RedisString>>,
 ) -> RedisResult {
     let search_token = args.next_arg()?.try_as_str()?;
     let search_result = gears_box_search(search_token)?;
@@ -1249,13 +3608,21 @@ fn function_search_lib_command(
 
 fn function_install_lib_command(
     ctx: &Context,
-    mut args: Skip<IntoIter<redis_module::RedisString>>,
+    mut args: Skip<IntoIter<RedisString>>,
 ) -> RedisResult {
     let id = args.next_arg()?.try_as_str()?;
     let gear_box_lib = gears_box_get_library(id)?;
     let function_code = do_http_get_text(&gear_box_lib.versions.get(0).unwrap().url)?;
     let user = ctx.get_current_user()?;
-    match function_load_intrernal(user, &function_code, false, Some(gear_box_lib)) {
+    match function_load_intrernal(
+        user,
+        &function_code,
+        false,
+        false,
+        Some(gear_box_lib),
+        None,
+        None,
+    ) {
         Ok(r) => {
             ctx.replicate_verbatim();
             Ok(r)
@@ -1272,7 +3639,17 @@ fn function_command(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
         "call" => function_call_command(ctx, args),
         "list" => function_list_command(ctx, args),
         "del" => function_del_command(ctx, args),
+        "delmodule" => function_delmodule_command(ctx, args),
         "debug" => function_debug_command(ctx, args),
+        "backends" => function_backends_command(ctx, args),
+        "stats" => function_stats_command(ctx, args),
+        "dump" => function_dump_command(ctx, args),
+        "restore" => function_restore_command(ctx, args),
+        "deploy" => function_deploy_command(ctx, args),
+        "rollback" => function_rollback_command(ctx, args),
+        "modules" => function_modules_command(ctx, args),
+        "config" => function_config_command(ctx, args),
+        "stream" => function_stream_command(ctx, args),
         _ => Err(RedisError::String(format!(
             "Unknown subcommand {}",
             sub_command
@@ -1339,6 +3716,7 @@ fn key_space_notification(_ctx: &Context, _event_type: NotifyEvent, event: &str,
 }
 
 fn update_stream_last_read_id(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+    verify_internal_command_caller(ctx)?;
     let mut args = args.into_iter().skip(1);
     let library_name = args.next_arg()?.try_as_str()?;
     let stream_consumer = args.next_arg()?.try_as_str()?;
@@ -1368,6 +3746,178 @@ fn update_stream_last_read_id(ctx: &Context, args: Vec<RedisString>) -> RedisRes
     Ok(RedisValue::SimpleStringStatic("OK"))
 }
 
+// parse a stream id of the form <ms>[-<seq>]
+fn parse_stream_id(id: &str) -> Result<(u64, u64), RedisError> {
+    let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+    match (ms.parse::<u64>(), seq.parse::<u64>()) {
+        (Ok(ms), Ok(seq)) => Ok((ms, seq)),
+        _ => Err(RedisError::String(format!("Invalid stream id '{}'", id))),
+    }
+}
+
+// the id of the last entry of the stream, 0-0 if the stream is empty
+fn get_stream_last_id(stream: &str) -> Result<(u64, u64), RedisError> {
+    let res = get_ctx().call_ext(
+        "xrevrange",
+        &CallOptionsBuilder::new().constract(),
+        &[
+            stream.as_bytes(),
+            "+".as_bytes(),
+            "-".as_bytes(),
+            "count".as_bytes(),
+            "1".as_bytes(),
+        ],
+    )?;
+    let entry = match redis_value_to_call_reply(res) {
+        CallResult::Array(entries) => entries.into_iter().next(),
+        _ => None,
+    };
+    match entry {
+        None => Ok((0, 0)),
+        Some(CallResult::Array(entry)) => match entry.into_iter().next() {
+            Some(CallResult::BulkStr(id)) | Some(CallResult::SimpleStr(id)) => parse_stream_id(&id),
+            _ => Err(RedisError::String(format!(
+                "Failed reading the last id of stream {}",
+                stream
+            ))),
+        },
+        Some(_) => Err(RedisError::String(format!(
+            "Failed reading the last id of stream {}",
+            stream
+        ))),
+    }
+}
+
+// RG.FUNCTION STREAM SEEK <library> <consumer> <stream> <id>
+// RG.FUNCTION STREAM PAUSE|RESUME|RESET <library> <consumer> [<stream>]
+fn function_stream_command(ctx: &Context, mut args: Skip<IntoIter<RedisString>>) -> RedisResult {
+    let sub_command = args.next_arg()?.try_as_str()?.to_lowercase();
+    if !["seek", "pause", "resume", "reset"].contains(&sub_command.as_str()) {
+        return Err(RedisError::String(format!(
+            "Unknown subcommand {}",
+            sub_command
+        )));
+    }
+    let library_name = args.next_arg()?.try_as_str()?;
+    let consumer_name = args.next_arg()?.try_as_str()?;
+    let stream = if sub_command == "seek" {
+        Some(args.next_arg()?.try_as_str()?)
+    } else {
+        match args.next() {
+            Some(s) => Some(s.try_as_str()?),
+            None => None,
+        }
+    };
+    let library = match get_libraries().get(library_name) {
+        Some(l) => l,
+        None => {
+            return Err(RedisError::String(format!(
+                "Unknown library {}",
+                library_name
+            )))
+        }
+    };
+    let consumer = match library.gears_lib_ctx.stream_consumers.get(consumer_name) {
+        Some(c) => c,
+        None => {
+            return Err(RedisError::String(format!(
+                "Unknown stream consumer {}",
+                consumer_name
+            )))
+        }
+    };
+    if let Some(stream) = stream {
+        let c = consumer.ref_cell.borrow();
+        let consumer_streams = &c.streams;
+        if !consumer_streams.matches(stream) {
+            return Err(RedisError::String(format!(
+                "Stream {} does not match the {} of stream consumer {}",
+                stream,
+                consumer_streams.get_type(),
+                consumer_name
+            )));
+        }
+    }
+    let stream_ctx = &mut get_globals_mut().stream_ctx;
+    // the streams to continue reading from after the command
+    let streams_to_touch = match sub_command.as_str() {
+        "seek" => {
+            let stream = stream.unwrap();
+            let id = args.next_arg()?.try_as_str()?;
+            let (ms, seq) = match id {
+                "$" => get_stream_last_id(stream)?,
+                _ => parse_stream_id(id)?,
+            };
+            stream_ctx.seek_stream_for_consumer(stream, consumer, ms, seq);
+            redis_module::replicate(
+                ctx.ctx,
+                "_rg_internals.update_stream_last_read_id",
+                &[
+                    library_name,
+                    consumer_name,
+                    stream,
+                    &ms.to_string(),
+                    &seq.to_string(),
+                ],
+            );
+            vec![stream.to_string()]
+        }
+        "pause" => {
+            match stream {
+                Some(stream) => stream_ctx.set_stream_paused(stream, consumer, true),
+                None => consumer.ref_cell.borrow_mut().paused = true,
+            }
+            ctx.replicate_verbatim();
+            Vec::new()
+        }
+        "resume" => {
+            let streams = match stream {
+                Some(stream) => vec![stream.to_string()],
+                None => {
+                    consumer.ref_cell.borrow_mut().paused = false;
+                    consumer
+                        .ref_cell
+                        .borrow()
+                        .consumed_streams
+                        .keys()
+                        .cloned()
+                        .collect()
+                }
+            };
+            for stream in streams.iter() {
+                stream_ctx.set_stream_paused(stream, consumer, false);
+            }
+            ctx.replicate_verbatim();
+            streams
+        }
+        _ => {
+            let streams = match stream {
+                Some(stream) => vec![stream.to_string()],
+                None => consumer
+                    .ref_cell
+                    .borrow()
+                    .consumed_streams
+                    .keys()
+                    .cloned()
+                    .collect(),
+            };
+            for stream in streams.iter() {
+                if let Some(info) = consumer.ref_cell.borrow().consumed_streams.get(stream) {
+                    info.ref_cell.borrow_mut().reset();
+                }
+            }
+            ctx.replicate_verbatim();
+            streams
+        }
+    };
+    if ctx.is_primary() {
+        for stream in streams_to_touch {
+            stream_ctx.on_stream_touched(&sub_command, &stream);
+        }
+    }
+    Ok(RedisValue::SimpleStringStatic("OK"))
+}
+
 fn scan_key_space_for_streams() {
     get_globals().mgmt_pool.execute(|| {
         let cursor = KeysCursor::new();
@@ -1420,7 +3970,20 @@ fn on_loading_event(ctx: &Context, event_data: ServerEventData) {
                     ctx.log_notice("Got a loading start event, clear the entire functions data.");
                     let globals = get_globals_mut();
                     globals.libraries.clear();
+                    globals.modules.clear();
                     globals.stream_ctx.clear();
+                    globals.loading_from_disk =
+                        !matches!(loading_sub_event, LoadingSubevent::ReplStarted);
+                }
+                LoadingSubevent::Ended => {
+                    // replicas get their libraries from the primary
+                    if get_globals().loading_from_disk {
+                        get_globals_mut().loading_from_disk = false;
+                        load_libraries_from_path(ctx);
+                    }
+                }
+                LoadingSubevent::Failed => {
+                    get_globals_mut().loading_from_disk = false;
                 }
                 _ => {}
             }
@@ -1456,16 +4019,18 @@ fn on_flush_event(ctx: &Context, event_data: ServerEventData) {
 
 redis_module! {
     name: "redisgears_2",
-    version: 999999,
+    version: REDIS_GEARS_MODULE_VERSION,
     data_types: [REDIS_GEARS_TYPE],
     init: js_init,
     post_init: js_post_init,
     info: js_info,
     commands: [
         ["rg.function", function_command, "readonly deny-script", 0,0,0],
+        ["rg.fcall", function_fcall_command, "readonly deny-script getkeys-api", 4,4,1],
         ["rg.box", gears_box_command, "readonly deny-script", 0,0,0],
         ["rg.config", config_command, "readonly deny-script", 0,0,0],
-        ["_rg_internals.update_stream_last_read_id", update_stream_last_read_id, "readonly", 0,0,0],
+        ["_rg_internals.update_stream_last_read_id", update_stream_last_read_id, "write", 0,0,0],
+        ["_rg_internals.function_rollback", function_rollback_replicated, "write", 0,0,0],
     ],
     event_handlers: [
         [@STREAM: on_stream_touched],
@@ -1480,13 +4045,17 @@ redis_module! {
     ],
     string_configurations: [
         &get_globals().config.gears_box_address,
+        &get_globals().config.library_path,
+        &get_globals().config.library_path_user,
     ],
     numeric_configurations: [
         &get_globals().config.execution_threads,
         &get_globals().config.library_maxmemory,
         &get_globals().config.lock_regis_timeout,
+        &get_globals().config.library_history_size,
     ],
     enum_configurations: [
         &get_globals().config.libraray_fatal_failure_policy,
+        &get_globals().config.library_path_conflict,
     ]
 }
@@ -1,12 +1,21 @@
-use crate::{function_load_intrernal, get_ctx, get_globals_mut, get_libraries};
+use crate::{
+    function_load_intrernal, function_load_module_internal, get_ctx, get_globals, get_globals_mut,
+    get_libraries,
+};
 
 use redis_module::{
-    native_types::RedisType, raw, raw::REDISMODULE_AUX_BEFORE_RDB, RedisModuleTypeMethods,
+    native_types::RedisType, raw, raw::REDISMODULE_AUX_BEFORE_RDB, Context, RedisModuleTypeMethods,
+    RedisString,
 };
 
-use std::os::raw::c_int;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
 
-pub(crate) static REDIS_GEARS_VERSION: i32 = 1;
+// version 2 added the modules, saved before the libraries that might import them
+// version 3 added the libraries configuration
+// version 4 added the libraries heap limit that was given on load
+// version 5 added the paused stream consumers and streams
+pub(crate) static REDIS_GEARS_VERSION: i32 = 5;
 pub(crate) static REDIS_GEARS_TYPE: RedisType = RedisType::new(
     "GearsType",
     REDIS_GEARS_VERSION,
@@ -15,8 +24,8 @@ pub(crate) static REDIS_GEARS_TYPE: RedisType = RedisType::new(
 
         rdb_load: None,
         rdb_save: None,
-        aof_rewrite: None, // TODO add support
-        free: None,
+        aof_rewrite: Some(aof_rewrite),
+        free: Some(free),
         mem_usage: None,
         digest: None,
 
@@ -37,7 +46,130 @@ pub(crate) static REDIS_GEARS_TYPE: RedisType = RedisType::new(
     },
 );
 
+// Modules can only emit AOF commands from the aof_rewrite callback of keys of their own
+// data type. When the AOF is rewritten without the RDB preamble (that holds the aux data)
+// this key is added on the rewrite child, so it is never visible to the clients.
+pub(crate) const AOF_REWRITE_KEY: &str = "_rg_internals.aof_rewrite";
+
+struct AofRewriteKey;
+
+pub(crate) fn add_aof_rewrite_key(ctx: &Context) {
+    let key_name = ctx.create_string(AOF_REWRITE_KEY);
+    let key = ctx.open_key_writable(&key_name);
+    if key.key_type() != raw::KeyType::Empty {
+        ctx.log_warning(&format!(
+            "Key '{}' already exists, libraries will not be part of the rewritten AOF.",
+            AOF_REWRITE_KEY
+        ));
+        return;
+    }
+    if let Err(e) = key.set_value(&REDIS_GEARS_TYPE, AofRewriteKey) {
+        ctx.log_warning(&format!(
+            "Failed adding key '{}', libraries will not be part of the rewritten AOF, {}.",
+            AOF_REWRITE_KEY, e
+        ));
+    }
+}
+
+extern "C" fn free(value: *mut c_void) {
+    drop(unsafe { Box::from_raw(value.cast::<AofRewriteKey>()) });
+}
+
+fn emit_aof(aof: *mut raw::RedisModuleIO, command: &str, args: &[&str]) {
+    let command = CString::new(command).unwrap();
+    let redis_args = args
+        .iter()
+        .map(|a| RedisString::create(std::ptr::null_mut(), *a))
+        .collect::<Vec<RedisString>>();
+    let mut raw_args = redis_args
+        .iter()
+        .map(|a| a.inner)
+        .collect::<Vec<*mut raw::RedisModuleString>>();
+    unsafe {
+        raw::RedisModule_EmitAOF.unwrap()(
+            aof,
+            command.as_ptr(),
+            "v\0".as_ptr().cast::<c_char>(),
+            raw_args.as_mut_ptr(),
+            raw_args.len(),
+        )
+    };
+}
+
+// Emits the same commands that are replicated when the modules and libraries are loaded
+// and the stream consumers progress, the modules first as the libraries might import them.
+extern "C" fn aof_rewrite(
+    aof: *mut raw::RedisModuleIO,
+    _key: *mut raw::RedisModuleString,
+    _value: *mut c_void,
+) {
+    for module in get_globals().modules.values() {
+        emit_aof(
+            aof,
+            "RG.FUNCTION",
+            &["LOAD", "MODULE", &module.meta_data.code],
+        );
+    }
+
+    for (name, val) in get_libraries().iter() {
+        let config = serde_json::to_string(&val.gears_lib_ctx.config).unwrap();
+        let maxmemory = val.gears_lib_ctx.maxmemory.map(|m| m.to_string());
+        let mut args = vec!["LOAD"];
+        if !val.gears_lib_ctx.config.is_empty() {
+            args.push("CONFIG");
+            args.push(&config);
+        }
+        if let Some(maxmemory) = &maxmemory {
+            args.push("MAXMEMORY");
+            args.push(maxmemory);
+        }
+        args.push(&val.gears_lib_ctx.meta_data.code);
+        emit_aof(aof, "RG.FUNCTION", &args);
+
+        for (consumer_name, stream_consumer) in val.gears_lib_ctx.stream_consumers.iter() {
+            let stream_consumer = stream_consumer.ref_cell.borrow();
+            for (stream, ms, seq) in stream_consumer.get_streams_info() {
+                emit_aof(
+                    aof,
+                    "_rg_internals.update_stream_last_read_id",
+                    &[
+                        name,
+                        consumer_name,
+                        &stream,
+                        &ms.to_string(),
+                        &seq.to_string(),
+                    ],
+                );
+            }
+            if stream_consumer.paused {
+                emit_aof(
+                    aof,
+                    "RG.FUNCTION",
+                    &["STREAM", "PAUSE", name, consumer_name],
+                );
+            }
+            for stream in stream_consumer.get_paused_streams() {
+                emit_aof(
+                    aof,
+                    "RG.FUNCTION",
+                    &["STREAM", "PAUSE", name, consumer_name, &stream],
+                );
+            }
+        }
+    }
+}
+
 extern "C" fn aux_save(rdb: *mut raw::RedisModuleIO, _when: c_int) {
+    let modules = &get_globals().modules;
+
+    // save the number of modules
+    raw::save_unsigned(rdb, modules.len() as u64);
+
+    for module in modules.values() {
+        raw::save_string(rdb, &module.meta_data.code);
+        raw::save_string(rdb, &module.user);
+    }
+
     let libraries = get_libraries();
 
     // save the number of libraries
@@ -53,6 +185,12 @@ extern "C" fn aux_save(rdb: *mut raw::RedisModuleIO, _when: c_int) {
         } else {
             raw::save_unsigned(rdb, 0);
         }
+        raw::save_string(
+            rdb,
+            &serde_json::to_string(&val.gears_lib_ctx.config).unwrap(),
+        );
+        // 0 indicates that no heap limit was given on load
+        raw::save_unsigned(rdb, val.gears_lib_ctx.maxmemory.unwrap_or(0) as u64);
         // save the number of streams consumer
         raw::save_unsigned(rdb, val.gears_lib_ctx.stream_consumers.len() as u64);
         for (name, stream_consumer) in val.gears_lib_ctx.stream_consumers.iter() {
@@ -72,6 +210,14 @@ extern "C" fn aux_save(rdb: *mut raw::RedisModuleIO, _when: c_int) {
                 raw::save_unsigned(rdb, ms);
                 raw::save_unsigned(rdb, seq);
             }
+            let stream_consumer = stream_consumer.ref_cell.borrow();
+            raw::save_unsigned(rdb, stream_consumer.paused as u64);
+            let paused_streams = stream_consumer.get_paused_streams();
+            // save the number of paused streams for this consumer
+            raw::save_unsigned(rdb, paused_streams.len() as u64);
+            for stream in paused_streams {
+                raw::save_string(rdb, &stream);
+            }
         }
     }
 }
@@ -85,6 +231,53 @@ unsafe extern "C" fn aux_load(rdb: *mut raw::RedisModuleIO, encver: c_int, _when
         return raw::REDISMODULE_ERR as i32;
     }
 
+    let num_of_modules = if encver >= 2 {
+        match raw::load_unsigned(rdb) {
+            Ok(n) => n,
+            Err(e) => {
+                get_ctx().log_notice(&format!("Failed reading number of modules from rdb, {}", e));
+                return raw::REDISMODULE_ERR as i32;
+            }
+        }
+    } else {
+        0
+    };
+
+    for _ in 0..num_of_modules {
+        let code = match raw::load_string_buffer(rdb) {
+            Ok(s) => match s.to_string() {
+                Ok(s) => s,
+                Err(e) => {
+                    get_ctx()
+                        .log_notice(&format!("Failed converting module code to string, {}", e));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            },
+            Err(e) => {
+                get_ctx().log_notice(&format!("Failed reading module code from rdb, {}", e));
+                return raw::REDISMODULE_ERR as i32;
+            }
+        };
+        let user = match raw::load_string_buffer(rdb) {
+            Ok(s) => match s.to_string() {
+                Ok(s) => s,
+                Err(e) => {
+                    get_ctx()
+                        .log_notice(&format!("Failed converting module user to string, {}", e));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            },
+            Err(e) => {
+                get_ctx().log_notice(&format!("Failed reading module user from rdb, {}", e));
+                return raw::REDISMODULE_ERR as i32;
+            }
+        };
+        if let Err(e) = function_load_module_internal(user, &code, false) {
+            get_ctx().log_notice(&format!("Failed loading module, {}", e));
+            return raw::REDISMODULE_ERR as i32;
+        }
+    }
+
     let num_of_libs = match raw::load_unsigned(rdb) {
         Ok(n) => n,
         Err(e) => {
@@ -172,7 +365,50 @@ unsafe extern "C" fn aux_load(rdb: *mut raw::RedisModuleIO, encver: c_int, _when
             None
         };
 
-        match function_load_intrernal(user, &code, false, gears_box_info) {
+        let config = if encver >= 3 {
+            let config_str = match raw::load_string_buffer(rdb) {
+                Ok(s) => match s.to_string() {
+                    Ok(s) => s,
+                    Err(e) => {
+                        get_ctx().log_notice(&format!(
+                            "Failed converting library config to string, {}",
+                            e
+                        ));
+                        return raw::REDISMODULE_ERR as i32;
+                    }
+                },
+                Err(e) => {
+                    get_ctx().log_notice(&format!("Failed reading library config from rdb, {}", e));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            };
+            match serde_json::from_str(&config_str) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    get_ctx().log_notice(&format!("Failed parsing library config, {}", e));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            }
+        } else {
+            None
+        };
+
+        let maxmemory = if encver >= 4 {
+            match raw::load_unsigned(rdb) {
+                Ok(0) => None,
+                Ok(n) => Some(n as usize),
+                Err(e) => {
+                    get_ctx()
+                        .log_notice(&format!("Failed reading library maxmemory from rdb, {}", e));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            }
+        } else {
+            None
+        };
+
+        match function_load_intrernal(user, &code, false, false, gears_box_info, config, maxmemory)
+        {
             Ok(_) => {}
             Err(e) => {
                 get_ctx().log_notice(&format!("Failed loading librart, {}", e));
@@ -266,6 +502,55 @@ unsafe extern "C" fn aux_load(rdb: *mut raw::RedisModuleIO, encver: c_int, _when
                     seq,
                 );
             }
+
+            if encver < 5 {
+                continue;
+            }
+            let paused = match raw::load_unsigned(rdb) {
+                Ok(n) => n > 0,
+                Err(e) => {
+                    get_ctx().log_notice(&format!(
+                        "Failed reading consumer paused state from rdb, {}",
+                        e
+                    ));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            };
+            consumer.ref_cell.borrow_mut().paused = paused;
+            let num_of_paused_streams = match raw::load_unsigned(rdb) {
+                Ok(n) => n,
+                Err(e) => {
+                    get_ctx().log_notice(&format!(
+                        "Failed reading number of paused steams from rdb, {}",
+                        e
+                    ));
+                    return raw::REDISMODULE_ERR as i32;
+                }
+            };
+            for _ in 0..num_of_paused_streams {
+                let stream_name = match raw::load_string_buffer(rdb) {
+                    Ok(s) => match s.to_string() {
+                        Ok(s) => s,
+                        Err(e) => {
+                            get_ctx().log_notice(&format!(
+                                "Failed converting paused stream name to string, {}",
+                                e
+                            ));
+                            return raw::REDISMODULE_ERR as i32;
+                        }
+                    },
+                    Err(e) => {
+                        get_ctx().log_notice(&format!(
+                            "Failed reading paused stream name from rdb, {}",
+                            e
+                        ));
+                        return raw::REDISMODULE_ERR as i32;
+                    }
+                };
+                get_globals_mut()
+                    .stream_ctx
+                    .set_stream_paused(&stream_name, consumer, true);
+            }
         }
     }
 
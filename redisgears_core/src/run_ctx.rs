@@ -1,12 +1,12 @@
 use redis_module::{
-    context::{CallOptions, CallOptionsBuilder},
-    Context, ThreadSafeContext,
+    context::{CallOptionResp, CallOptions, CallOptionsBuilder},
+    raw, Context, RedisValue, ThreadSafeContext,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::{
     load_library_ctx::FUNCTION_FLAG_NO_WRITES, run_function_ctx::BackgroundRunFunctionCtxInterface,
     run_function_ctx::RedisClientCtxInterface, run_function_ctx::ReplyCtxInterface,
-    run_function_ctx::RunFunctionCtxInterface, CallResult,
+    run_function_ctx::RunFunctionCtxInterface, CallResult, GearsApiError,
 };
 
 use crate::call_redis_command;
@@ -15,28 +15,122 @@ use std::slice::Iter;
 
 use crate::background_run_ctx::BackgroundRunCtx;
 
+use std::os::raw::{c_char, c_int, c_long};
+
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Clone)]
+pub(crate) struct FunctionStats {
+    pub(crate) num_calls: usize,
+    pub(crate) num_errors: usize,
+    pub(crate) num_sync_completions: usize,
+    pub(crate) num_async_completions: usize,
+    pub(crate) last_error: Option<GearsApiError>,
+    pub(crate) total_execution_time: u128, // in us
+    pub(crate) max_execution_time: u128,   // in us
+}
+
+impl FunctionStats {
+    pub(crate) fn new() -> FunctionStats {
+        FunctionStats {
+            num_calls: 0,
+            num_errors: 0,
+            num_sync_completions: 0,
+            num_async_completions: 0,
+            last_error: None,
+            total_execution_time: 0,
+            max_execution_time: 0,
+        }
+    }
+
+    fn on_done(&mut self, start_time: SystemTime, error: Option<GearsApiError>, is_async: bool) {
+        let duration = match SystemTime::now().duration_since(start_time) {
+            Ok(d) => d.as_micros(),
+            Err(_) => 0,
+        };
+        self.total_execution_time += duration;
+        if duration > self.max_execution_time {
+            self.max_execution_time = duration;
+        }
+        if is_async {
+            self.num_async_completions += 1;
+        } else {
+            self.num_sync_completions += 1;
+        }
+        if error.is_some() {
+            self.num_errors += 1;
+            self.last_error = error;
+        }
+    }
+}
+
+// Redis takes care of downgrading those replies when the client uses RESP2.
+fn reply_with_map(ctx: &Context, size: usize) {
+    unsafe { raw::RedisModule_ReplyWithMap.unwrap()(ctx.ctx, size as c_long) };
+}
+
+fn reply_with_set(ctx: &Context, size: usize) {
+    unsafe { raw::RedisModule_ReplyWithSet.unwrap()(ctx.ctx, size as c_long) };
+}
+
+fn reply_with_bool(ctx: &Context, val: bool) {
+    unsafe { raw::RedisModule_ReplyWithBool.unwrap()(ctx.ctx, val as c_int) };
+}
+
+fn reply_with_big_number(ctx: &Context, val: &str) {
+    unsafe {
+        raw::RedisModule_ReplyWithBigNumber.unwrap()(
+            ctx.ctx,
+            val.as_ptr() as *const c_char,
+            val.len(),
+        )
+    };
+}
+
+fn reply_with_verbatim_string(ctx: &Context, val: &str) {
+    unsafe {
+        raw::RedisModule_ReplyWithVerbatimString.unwrap()(
+            ctx.ctx,
+            val.as_ptr() as *const c_char,
+            val.len(),
+        )
+    };
+}
+
 #[derive(Clone)]
 pub(crate) struct RedisClientCallOptions {
     pub(crate) call_options: CallOptions,
+    pub(crate) resp3_call_options: CallOptions,
     pub(crate) flags: u8,
+    pub(crate) allowed_keys: Option<Vec<Vec<u8>>>,
+}
+
+fn build_call_options(flags: u8, resp: CallOptionResp) -> CallOptions {
+    let call_options = CallOptionsBuilder::new()
+        .script_mode()
+        .replicate()
+        .verify_acl()
+        .errors_as_replies()
+        .resp(resp);
+    let call_options = if flags & FUNCTION_FLAG_NO_WRITES != 0 {
+        call_options.no_writes()
+    } else {
+        call_options
+    };
+    call_options.constract()
 }
 
 impl RedisClientCallOptions {
     pub(crate) fn new(flags: u8) -> RedisClientCallOptions {
-        let call_options = CallOptionsBuilder::new()
-            .script_mode()
-            .replicate()
-            .verify_acl()
-            .errors_as_replies();
-        let call_options = if flags & FUNCTION_FLAG_NO_WRITES != 0 {
-            call_options.no_writes()
-        } else {
-            call_options
-        };
-
         RedisClientCallOptions {
-            call_options: call_options.constract(),
+            // RESP3 replies are only given when explicitly asked for (client.call_resp3),
+            // so the reply types do not depend on the protocol of the calling client.
+            call_options: build_call_options(flags, CallOptionResp::Resp2),
+            resp3_call_options: build_call_options(flags, CallOptionResp::Resp3),
             flags: flags,
+            allowed_keys: None,
         }
     }
 }
@@ -51,21 +145,27 @@ unsafe impl Send for RedisClient {}
 
 impl RedisClient {
     pub(crate) fn new(user: Option<String>, flags: u8) -> RedisClient {
+        Self::with_call_options(user, RedisClientCallOptions::new(flags))
+    }
+
+    pub(crate) fn with_call_options(
+        user: Option<String>,
+        call_options: RedisClientCallOptions,
+    ) -> RedisClient {
         RedisClient {
-            call_options: RedisClientCallOptions::new(flags),
+            call_options: call_options,
             user: user,
         }
     }
 }
 
 impl RedisClientCtxInterface for RedisClient {
-    fn call(&self, command: &str, args: &[&str]) -> CallResult {
-        call_redis_command(
-            self.user.as_ref(),
-            command,
-            &self.call_options.call_options,
-            args,
-        )
+    fn call(&self, command: &str, args: &[&[u8]]) -> CallResult {
+        call_redis_command(self.user.as_ref(), command, &self.call_options, false, args)
+    }
+
+    fn call_resp3(&self, command: &str, args: &[&[u8]]) -> CallResult {
+        call_redis_command(self.user.as_ref(), command, &self.call_options, true, args)
     }
 
     fn as_redis_client(&self) -> &dyn RedisClientCtxInterface {
@@ -83,7 +183,47 @@ impl RedisClientCtxInterface for RedisClient {
 pub(crate) struct RunCtx<'a> {
     pub(crate) ctx: &'a Context,
     pub(crate) iter: Iter<'a, redis_module::RedisString>,
+    pub(crate) keys: Option<&'a [redis_module::RedisString]>,
     pub(crate) flags: u8,
+    stats: &'a Arc<Mutex<FunctionStats>>,
+    start_time: SystemTime,
+    error: RefCell<Option<GearsApiError>>,
+    // set when the reply was moved to a background client,
+    // the stats will be updated when the background client is done.
+    is_async: Cell<bool>,
+}
+
+impl<'a> RunCtx<'a> {
+    pub(crate) fn new(
+        ctx: &'a Context,
+        args: &'a [redis_module::RedisString],
+        keys: Option<&'a [redis_module::RedisString]>,
+        flags: u8,
+        stats: &'a Arc<Mutex<FunctionStats>>,
+    ) -> RunCtx<'a> {
+        stats.lock().unwrap().num_calls += 1;
+        RunCtx {
+            ctx: ctx,
+            iter: args.iter(),
+            keys: keys,
+            flags: flags,
+            stats: stats,
+            start_time: SystemTime::now(),
+            error: RefCell::new(None),
+            is_async: Cell::new(false),
+        }
+    }
+}
+
+impl<'a> Drop for RunCtx<'a> {
+    fn drop(&mut self) {
+        if !self.is_async.get() {
+            self.stats
+                .lock()
+                .unwrap()
+                .on_done(self.start_time, self.error.take(), false);
+        }
+    }
 }
 
 impl<'a> ReplyCtxInterface for RunCtx<'a> {
@@ -93,6 +233,12 @@ impl<'a> ReplyCtxInterface for RunCtx<'a> {
 
     fn reply_with_error(&self, val: &str) {
         self.ctx.reply_error_string(val);
+        *self.error.borrow_mut() = Some(GearsApiError::new(val.to_string()));
+    }
+
+    fn reply_with_gears_error(&self, err: &GearsApiError) {
+        self.ctx.reply_error_string(&err.get_error_string());
+        *self.error.borrow_mut() = Some(err.clone());
     }
 
     fn reply_with_long(&self, val: i64) {
@@ -107,10 +253,34 @@ impl<'a> ReplyCtxInterface for RunCtx<'a> {
         self.ctx.reply_bulk_string(val);
     }
 
+    fn reply_with_bulk_buffer(&self, val: &[u8]) {
+        self.ctx.reply(Ok(RedisValue::StringBuffer(val.to_vec())));
+    }
+
     fn reply_with_array(&self, size: usize) {
         self.ctx.reply_array(size);
     }
 
+    fn reply_with_map(&self, size: usize) {
+        reply_with_map(&self.ctx, size);
+    }
+
+    fn reply_with_set(&self, size: usize) {
+        reply_with_set(&self.ctx, size);
+    }
+
+    fn reply_with_bool(&self, val: bool) {
+        reply_with_bool(&self.ctx, val);
+    }
+
+    fn reply_with_big_number(&self, val: &str) {
+        reply_with_big_number(&self.ctx, val);
+    }
+
+    fn reply_with_verbatim_string(&self, val: &str) {
+        reply_with_verbatim_string(&self.ctx, val);
+    }
+
     fn as_client(&self) -> &dyn ReplyCtxInterface {
         self
     }
@@ -124,13 +294,21 @@ impl<'a> RunFunctionCtxInterface for RunCtx<'a> {
         Some(self.iter.next()?.as_slice())
     }
 
+    fn get_keys(&self) -> Option<Vec<&[u8]>> {
+        Some(self.keys?.iter().map(|k| k.as_slice()).collect())
+    }
+
     fn get_background_client(&self) -> Box<dyn ReplyCtxInterface> {
         let blocked_client = self.ctx.block_client();
         let thread_ctx = ThreadSafeContext::with_blocked_client(blocked_client);
         let ctx = thread_ctx.get_ctx();
+        self.is_async.set(true);
         Box::new(BackgroundClientCtx {
             _thread_ctx: thread_ctx,
             ctx: ctx,
+            stats: Arc::clone(self.stats),
+            start_time: self.start_time,
+            error: Mutex::new(None),
         })
     }
 
@@ -139,13 +317,30 @@ impl<'a> RunFunctionCtxInterface for RunCtx<'a> {
             Ok(u) => Some(u),
             Err(_) => None,
         };
-        Box::new(RedisClient::new(user, self.flags))
+        let mut call_options = RedisClientCallOptions::new(self.flags);
+        call_options.allowed_keys = self
+            .keys
+            .map(|keys| keys.iter().map(|k| k.as_slice().to_vec()).collect());
+        Box::new(RedisClient::with_call_options(user, call_options))
     }
 }
 
 pub(crate) struct BackgroundClientCtx {
     _thread_ctx: ThreadSafeContext<redis_module::BlockedClient>,
     ctx: Context,
+    stats: Arc<Mutex<FunctionStats>>,
+    start_time: SystemTime,
+    error: Mutex<Option<GearsApiError>>,
+}
+
+impl Drop for BackgroundClientCtx {
+    fn drop(&mut self) {
+        let error = self.error.lock().unwrap().take();
+        self.stats
+            .lock()
+            .unwrap()
+            .on_done(self.start_time, error, true);
+    }
 }
 
 unsafe impl Sync for BackgroundClientCtx {}
@@ -158,6 +353,12 @@ impl ReplyCtxInterface for BackgroundClientCtx {
 
     fn reply_with_error(&self, val: &str) {
         self.ctx.reply_error_string(val);
+        *self.error.lock().unwrap() = Some(GearsApiError::new(val.to_string()));
+    }
+
+    fn reply_with_gears_error(&self, err: &GearsApiError) {
+        self.ctx.reply_error_string(&err.get_error_string());
+        *self.error.lock().unwrap() = Some(err.clone());
     }
 
     fn reply_with_long(&self, val: i64) {
@@ -172,10 +373,34 @@ impl ReplyCtxInterface for BackgroundClientCtx {
         self.ctx.reply_bulk_string(val);
     }
 
+    fn reply_with_bulk_buffer(&self, val: &[u8]) {
+        self.ctx.reply(Ok(RedisValue::StringBuffer(val.to_vec())));
+    }
+
     fn reply_with_array(&self, size: usize) {
         self.ctx.reply_array(size);
     }
 
+    fn reply_with_map(&self, size: usize) {
+        reply_with_map(&self.ctx, size);
+    }
+
+    fn reply_with_set(&self, size: usize) {
+        reply_with_set(&self.ctx, size);
+    }
+
+    fn reply_with_bool(&self, val: bool) {
+        reply_with_bool(&self.ctx, val);
+    }
+
+    fn reply_with_big_number(&self, val: &str) {
+        reply_with_big_number(&self.ctx, val);
+    }
+
+    fn reply_with_verbatim_string(&self, val: &str) {
+        reply_with_verbatim_string(&self.ctx, val);
+    }
+
     fn as_client(&self) -> &dyn ReplyCtxInterface {
         self
     }
@@ -27,12 +27,225 @@ pub(crate) enum StreamReaderAck {
 }
 
 pub(crate) trait StreamConsumer<T: StreamReaderRecord> {
+    // the acks are given per record, in the same order as the records
     fn new_data(
         &self,
         stream_name: &str,
-        record: T,
-        ack_callback: Box<dyn FnOnce(StreamReaderAck) + Send>,
-    ) -> Option<StreamReaderAck>;
+        records: Vec<T>,
+        ack_callback: Box<dyn FnOnce(Vec<StreamReaderAck>) + Send>,
+    ) -> Option<Vec<StreamReaderAck>>;
+}
+
+// run the given callback after the given amount of ms
+pub(crate) type DelayExecutor = Box<dyn Fn(u64, Box<dyn FnOnce()>) + Sync + Send>;
+
+pub(crate) type StreamReaderFn<T> =
+    Box<dyn Fn(&str, Option<RedisModuleStreamID>, bool) -> Result<Option<T>, String> + Sync + Send>;
+
+// the id of the last entry of the stream, None if the stream is empty
+pub(crate) type StreamLastIdFn = Box<dyn Fn(&str) -> Option<RedisModuleStreamID> + Sync + Send>;
+
+// called with the dead letter stream, the stream name, the failed record,
+// the error and the number of attempts.
+pub(crate) type RecordFailedCallback<T> = Box<dyn Fn(&str, &str, &T, &str, usize)>;
+
+// where a consumer starts reading a stream it did not read before
+#[derive(Clone, Copy)]
+pub(crate) enum ConsumerStart {
+    // right after the given id, None to read from the beginning
+    After(Option<RedisModuleStreamID>),
+    // after the last entry the stream had when the consumer started tracking it,
+    // the same as seeking the stream to '$'.
+    LastEntry,
+}
+
+pub(crate) struct ConsumerOptions {
+    pub(crate) window: usize,
+    pub(crate) trim: bool,
+    pub(crate) batch_size: usize,
+    pub(crate) batch_max_wait: u64,
+    pub(crate) start: ConsumerStart,
+    pub(crate) max_attempts: usize,
+    pub(crate) retry_backoff: u64,
+    pub(crate) dead_letter_stream: Option<String>,
+}
+
+// match a string against a glob-style pattern, a port of redis stringmatchlen
+// (used by the KEYS command), except that '*' also matches an empty string.
+fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut skip_longer_matches = false;
+    glob_match_impl(pattern, string, &mut skip_longer_matches, 0)
+}
+
+fn glob_match_impl(
+    pattern: &[u8],
+    string: &[u8],
+    skip_longer_matches: &mut bool,
+    nesting: usize,
+) -> bool {
+    // protection against abusive patterns
+    if nesting > 1000 {
+        return false;
+    }
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                while s < string.len() {
+                    if glob_match_impl(
+                        &pattern[p + 1..],
+                        &string[s..],
+                        skip_longer_matches,
+                        nesting + 1,
+                    ) {
+                        return true;
+                    }
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    s += 1;
+                }
+                // the rest of the pattern does not match anywhere in the rest of the
+                // string, so there is no point in letting an earlier '*' match more.
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => s += 1,
+            b'[' => {
+                p += 1;
+                let not = p < pattern.len() && pattern[p] == b'^';
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    if p >= pattern.len() {
+                        // unterminated class, like redis, treat the end as ']'
+                        p -= 1;
+                        break;
+                    }
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        if pattern[p] == string[s] {
+                            matched = true;
+                        }
+                    } else if pattern[p] == b']' {
+                        break;
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        p += 2;
+                        if string[s] >= start && string[s] <= end {
+                            matched = true;
+                        }
+                    } else if pattern[p] == string[s] {
+                        matched = true;
+                    }
+                    p += 1;
+                }
+                if matched == not {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                let c = if c == b'\\' && p + 1 < pattern.len() {
+                    p += 1;
+                    pattern[p]
+                } else {
+                    c
+                };
+                if c != string[s] {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+    // a '*' at the end of the pattern matches the empty rest of the string
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+    p == pattern.len() && s == string.len()
+}
+
+// the part of the pattern before the first special character
+fn glob_literal_prefix(pattern: &str) -> &str {
+    match pattern.find(|c| ['*', '?', '[', '\\'].contains(&c)) {
+        Some(i) => &pattern[..i],
+        None => pattern,
+    }
+}
+
+// the streams a consumer reads from
+#[derive(Clone, PartialEq)]
+pub(crate) enum ConsumerStreams {
+    Prefix(String),
+    Pattern(String),
+    // sorted, so a stream can be found with a binary search
+    Streams(Vec<String>),
+}
+
+impl ConsumerStreams {
+    pub(crate) fn matches(&self, stream: &str) -> bool {
+        match self {
+            ConsumerStreams::Prefix(p) => stream.starts_with(p.as_str()),
+            ConsumerStreams::Pattern(p) => glob_match(p.as_bytes(), stream.as_bytes()),
+            ConsumerStreams::Streams(s) => s.binary_search_by(|v| v.as_str().cmp(stream)).is_ok(),
+        }
+    }
+
+    // whether there are streams that are matched by both, patterns are
+    // compared by their literal prefix so they might be reported as
+    // overlapping even if they are not.
+    pub(crate) fn overlaps(&self, other: &ConsumerStreams) -> bool {
+        match (self, other) {
+            (ConsumerStreams::Streams(s), o) | (o, ConsumerStreams::Streams(s)) => {
+                s.iter().any(|s| o.matches(s))
+            }
+            (p1, p2) => {
+                let p1 = p1.get_literal_prefix();
+                let p2 = p2.get_literal_prefix();
+                p1.starts_with(p2) || p2.starts_with(p1)
+            }
+        }
+    }
+
+    fn get_literal_prefix(&self) -> &str {
+        match self {
+            ConsumerStreams::Prefix(p) => p,
+            ConsumerStreams::Pattern(p) => glob_literal_prefix(p),
+            ConsumerStreams::Streams(_) => "",
+        }
+    }
+
+    pub(crate) fn get_type(&self) -> &'static str {
+        match self {
+            ConsumerStreams::Prefix(_) => "prefix",
+            ConsumerStreams::Pattern(_) => "pattern",
+            ConsumerStreams::Streams(_) => "streams",
+        }
+    }
+}
+
+impl std::fmt::Display for ConsumerStreams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsumerStreams::Prefix(p) | ConsumerStreams::Pattern(p) => {
+                write!(f, "{} '{}'", self.get_type(), p)
+            }
+            ConsumerStreams::Streams(s) => write!(f, "streams '{}'", s.join("', '")),
+        }
+    }
 }
 
 pub(crate) struct TrackedStream {
@@ -103,8 +316,20 @@ pub(crate) struct ConsumerInfo {
     pub(crate) total_lag: u128,           // average lag in ms
     pub(crate) records_processed: usize,  // average lag in ms
     pub(crate) pending_ids: LinkedList<RedisModuleStreamID>,
+    pub(crate) pending_batches: usize,
     pub(crate) last_read_id: Option<RedisModuleStreamID>,
     pub(crate) last_error: Option<String>,
+    pub(crate) records_retried: usize,
+    pub(crate) records_dead_lettered: usize,
+    pub(crate) paused: bool,
+    // whether we are already waiting for a partial batch to fill up
+    batch_wait_scheduled: bool,
+    // the number of records after last_read_id that are waiting for the batch to
+    // fill up and the id of the last one, so only newer records are counted.
+    partial_batch: Option<(usize, RedisModuleStreamID)>,
+    // increased when the position is moved (seek or reset), the acks of batches
+    // that were delivered before are ignored so they will not move it back.
+    generation: usize,
 }
 
 impl ConsumerInfo {
@@ -121,27 +346,66 @@ impl ConsumerInfo {
         self.total_lag = self.total_lag + lag;
 
         let mut temp_list = LinkedList::new();
+        let mut found = false;
         while let Some(curr) = self.pending_ids.pop_front() {
             if curr.ms == id.ms && curr.seq == id.seq {
+                found = true;
                 break;
             }
             temp_list.push_back(curr);
         }
         if temp_list.len() == 0 {
-            return true; // indicate that the first element was removed
+            // indicate that the first element was removed, the id might
+            // not be found if the pending ids were reset.
+            return found;
         }
-        self.pending_ids.append(&mut temp_list);
+        temp_list.append(&mut self.pending_ids);
+        self.pending_ids = temp_list;
         false
     }
+
+    // clear the statistics and forget about the records that are currently processed
+    pub(crate) fn reset(&mut self) {
+        self.last_processed_time = 0;
+        self.total_processed_time = 0;
+        self.last_lag = 0;
+        self.total_lag = 0;
+        self.records_processed = 0;
+        self.records_retried = 0;
+        self.records_dead_lettered = 0;
+        self.last_error = None;
+        self.pending_ids.clear();
+        self.pending_batches = 0;
+        self.partial_batch = None;
+        self.generation += 1;
+    }
+
+    // continue reading right after the given id, the records that are currently
+    // processed are forgotten.
+    fn seek(&mut self, id: RedisModuleStreamID) {
+        self.last_read_id = Some(id);
+        self.pending_ids.clear();
+        self.pending_batches = 0;
+        self.partial_batch = None;
+        self.generation += 1;
+    }
 }
 
 pub(crate) struct ConsumerData<T: StreamReaderRecord, C: StreamConsumer<T>> {
-    pub(crate) prefix: String,
+    pub(crate) streams: ConsumerStreams,
     pub(crate) consumer: Option<C>,
     pub(crate) consumed_streams: HashMap<String, Arc<RefCellWrapper<ConsumerInfo>>>,
-    pub(crate) window: usize, // represent the max amount of elements that can be processed at the same time
+    pub(crate) window: usize, // represent the max amount of batches that can be processed at the same time
     pub(crate) trim: bool,
+    pub(crate) batch_size: usize,
+    pub(crate) batch_max_wait: u64, // max time in ms to wait for a batch to fill up
+    pub(crate) max_attempts: usize, // max times to process a record before giving up on it
+    pub(crate) retry_backoff: u64, // time in ms to wait before the first retry, doubled on each retry
+    pub(crate) dead_letter_stream: Option<String>,
+    pub(crate) start: ConsumerStart,
     pub(crate) on_record_acked: Option<Box<dyn Fn(&str, u64, u64)>>,
+    pub(crate) on_record_failed: Option<RecordFailedCallback<T>>,
+    pub(crate) paused: bool,
     phantom: std::marker::PhantomData<T>,
 }
 
@@ -168,16 +432,48 @@ where
         old_trim
     }
 
+    pub(crate) fn set_batch(&mut self, batch_size: usize, batch_max_wait: u64) -> (usize, u64) {
+        let old_batch = (self.batch_size, self.batch_max_wait);
+        self.batch_size = batch_size;
+        self.batch_max_wait = batch_max_wait;
+        old_batch
+    }
+
+    pub(crate) fn set_retry_policy(
+        &mut self,
+        max_attempts: usize,
+        retry_backoff: u64,
+        dead_letter_stream: Option<String>,
+    ) -> (usize, u64, Option<String>) {
+        let old_retry_policy = (
+            self.max_attempts,
+            self.retry_backoff,
+            self.dead_letter_stream.take(),
+        );
+        self.max_attempts = max_attempts;
+        self.retry_backoff = retry_backoff;
+        self.dead_letter_stream = dead_letter_stream;
+        old_retry_policy
+    }
+
+    // the last id of a stream is only read when the stream is new to the
+    // consumer and the consumer starts from the stream last entry.
     pub(crate) fn get_or_create_consumed_stream(
         &mut self,
         name: &str,
+        last_id: impl FnOnce() -> Option<RedisModuleStreamID>,
     ) -> (Arc<RefCellWrapper<ConsumerInfo>>, bool) {
         let mut is_new = false;
+        let start = self.start;
         let res = self
             .consumed_streams
             .entry(name.to_string())
             .or_insert_with(|| {
                 is_new = true;
+                let start = match start {
+                    ConsumerStart::After(id) => id,
+                    ConsumerStart::LastEntry => last_id(),
+                };
                 Arc::new(RefCellWrapper {
                     ref_cell: RefCell::new(ConsumerInfo {
                         last_processed_time: 0,
@@ -186,8 +482,15 @@ where
                         total_lag: 0,
                         records_processed: 0,
                         pending_ids: LinkedList::new(),
+                        pending_batches: 0,
                         last_error: None,
-                        last_read_id: None,
+                        records_retried: 0,
+                        records_dead_lettered: 0,
+                        paused: false,
+                        last_read_id: start,
+                        batch_wait_scheduled: false,
+                        partial_batch: None,
+                        generation: 0,
                     }),
                 })
             });
@@ -213,6 +516,14 @@ where
         )
     }
 
+    pub(crate) fn get_paused_streams(&self) -> Vec<String> {
+        self.consumed_streams
+            .iter()
+            .filter(|(_, v)| v.ref_cell.borrow().paused)
+            .map(|(s, _)| s.to_string())
+            .collect()
+    }
+
     pub(crate) fn clear_streams_info(&mut self) {
         self.consumed_streams.clear();
     }
@@ -225,15 +536,14 @@ where
 {
     // map between consumers to streams the consumer is reading from
     consumers: Vec<Weak<RefCellWrapper<ConsumerData<T, C>>>>,
-    stream_reader: Arc<
-        Box<
-            dyn Fn(&str, Option<RedisModuleStreamID>, bool) -> Result<Option<T>, String>
-                + Sync
-                + Send,
-        >,
-    >,
+    stream_reader: Arc<StreamReaderFn<T>>,
     stream_trimmer: Arc<Box<dyn Fn(&str, RedisModuleStreamID) + Sync + Send>>,
+    delay_executor: Arc<DelayExecutor>,
+    stream_last_id: StreamLastIdFn,
     tracked_streams: HashMap<String, Arc<RefCellWrapper<TrackedStream>>>,
+    // the consumers that matches each stream, so the consumers will not be
+    // matched against the stream on every touch. Cleared when a consumer is added.
+    matched_consumers: HashMap<String, Vec<Weak<RefCellWrapper<ConsumerData<T, C>>>>>,
 }
 
 fn read_next_data<T: StreamReaderRecord>(
@@ -241,13 +551,7 @@ fn read_next_data<T: StreamReaderRecord>(
     id: Option<RedisModuleStreamID>,
     include_id: bool,
     consumer_info: &Arc<RefCellWrapper<ConsumerInfo>>,
-    stream_reader: &Arc<
-        Box<
-            dyn Fn(&str, Option<RedisModuleStreamID>, bool) -> Result<Option<T>, String>
-                + Sync
-                + Send,
-        >,
-    >,
+    stream_reader: &Arc<StreamReaderFn<T>>,
 ) -> Result<Option<T>, String> {
     let r = stream_reader(name, id, include_id);
     if r.is_err() {
@@ -264,160 +568,332 @@ fn read_next_data<T: StreamReaderRecord>(
     r
 }
 
-fn send_new_data<T: StreamReaderRecord + 'static, C: StreamConsumer<T> + 'static>(
+// read up to batch_size records that comes after the consumer last read id
+fn read_next_batch<T: StreamReaderRecord>(
+    name: &str,
+    batch_size: usize,
+    consumer_info: &Arc<RefCellWrapper<ConsumerInfo>>,
+    stream_reader: &Arc<StreamReaderFn<T>>,
+) -> Result<Vec<T>, String> {
+    let mut records = Vec::new();
+    while records.len() < batch_size {
+        let last_read_id = consumer_info.ref_cell.borrow().last_read_id;
+        match read_next_data(name, last_read_id, false, consumer_info, stream_reader) {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => break,
+            Err(e) => {
+                if records.is_empty() {
+                    return Err(e);
+                }
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+// read the record with the exact given id, None if it is no longer in the stream
+fn read_record<T: StreamReaderRecord>(
+    name: &str,
+    id: RedisModuleStreamID,
+    stream_reader: &Arc<StreamReaderFn<T>>,
+) -> Option<T> {
+    match stream_reader(name, Some(id), true) {
+        Ok(Some(record)) => {
+            let record_id = record.get_id();
+            if record_id.ms == id.ms && record_id.seq == id.seq {
+                Some(record)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Everything needed to pass the records of a stream to a consumer
+struct StreamProcessor<T: StreamReaderRecord, C: StreamConsumer<T>> {
     stream: Arc<RefCellWrapper<TrackedStream>>,
-    consumer_weak: Weak<RefCellWrapper<ConsumerData<T, C>>>,
-    mut actual_record: Result<Option<T>, String>,
-    consumer_info: Arc<RefCellWrapper<ConsumerInfo>>,
-    stream_reader: Arc<
-        Box<
-            dyn Fn(&str, Option<RedisModuleStreamID>, bool) -> Result<Option<T>, String>
-                + Sync
-                + Send,
-        >,
-    >,
-) {
-    let consumer = match consumer_weak.upgrade() {
-        Some(c) => c,
-        None => return,
-    };
-    let trim = { consumer.ref_cell.borrow().trim };
-    loop {
-        let (id, record) = {
+    consumer: Weak<RefCellWrapper<ConsumerData<T, C>>>,
+    consumer_info: Weak<RefCellWrapper<ConsumerInfo>>,
+    stream_reader: Arc<StreamReaderFn<T>>,
+    delay_executor: Arc<DelayExecutor>,
+}
+
+impl<T: StreamReaderRecord, C: StreamConsumer<T>> Clone for StreamProcessor<T, C> {
+    fn clone(&self) -> Self {
+        StreamProcessor {
+            stream: Arc::clone(&self.stream),
+            consumer: Weak::clone(&self.consumer),
+            consumer_info: Weak::clone(&self.consumer_info),
+            stream_reader: Arc::clone(&self.stream_reader),
+            delay_executor: Arc::clone(&self.delay_executor),
+        }
+    }
+}
+
+impl<T: StreamReaderRecord + 'static, C: StreamConsumer<T> + 'static> StreamProcessor<T, C> {
+    fn ack_batch(
+        &self,
+        ids: &[RedisModuleStreamID],
+        acks: Vec<StreamReaderAck>,
+        attempt: usize,
+        start_time: u128,
+        generation: usize,
+    ) {
+        // if weak ref returns None it means that stream was deleted
+        let consumer_info = match self.consumer_info.upgrade() {
+            Some(c_i) => c_i,
+            None => return,
+        };
+        if consumer_info.ref_cell.borrow().generation != generation {
+            // the stream was seeked or reset while the batch was processed
+            return;
+        }
+        let consumer = self.consumer.upgrade();
+        let (trim, max_attempts, retry_backoff, dead_letter_stream) = match consumer.as_ref() {
+            Some(c) => {
+                let c = c.ref_cell.borrow();
+                (
+                    c.trim,
+                    c.max_attempts,
+                    c.retry_backoff,
+                    c.dead_letter_stream.clone(),
+                )
+            }
+            None => (false, 1, 0, None),
+        };
+        let mut ids_to_retry = Vec::new();
+        let mut ids_to_dead_letter = Vec::new();
+        let mut t_s = self.stream.ref_cell.borrow_mut();
+        let trimmed_first = {
             let mut c_i = consumer_info.ref_cell.borrow_mut();
-            if actual_record.is_err() {
-                return;
+            let mut last_trimmed_id = None;
+            for (id, ack) in ids.iter().zip(acks) {
+                if let StreamReaderAck::Nack(msg) = ack {
+                    if attempt < max_attempts {
+                        // keep the id pending so it will not be trimmed
+                        ids_to_retry.push(*id);
+                        c_i.last_error = Some(msg);
+                        continue;
+                    }
+                    if dead_letter_stream.is_some() {
+                        ids_to_dead_letter.push((*id, msg.clone()));
+                    }
+                    c_i.last_error = Some(msg);
+                }
+                if c_i.ack_id(*id, start_time) {
+                    last_trimmed_id = Some(*id);
+                }
             }
-            let record = actual_record.unwrap();
-            if record.is_none() {
-                return;
+            if ids_to_retry.is_empty() {
+                // might already be 0 if the consumer was reset
+                c_i.pending_batches = c_i.pending_batches.saturating_sub(1);
+            } else {
+                c_i.records_retried += ids_to_retry.len();
+            }
+            match (last_trimmed_id, consumer.as_ref()) {
+                (Some(id), Some(c)) => {
+                    // consumer is still allive, fire the on acked event.
+                    // only if we trimmed the first element we
+                    // can fire the acked callback to notify
+                    // that it is safe to continue from this ID
+                    // in case of a crash.
+                    if let Some(on_record_acked) = c.ref_cell.borrow().on_record_acked.as_ref() {
+                        on_record_acked(&t_s.name, id.ms, id.seq);
+                    }
+                    true
+                }
+                // nothing was trimmed or the consumer is dead, lets not trim the stream.
+                _ => false,
             }
-            let record = record.unwrap();
-            let id = record.get_id();
-            c_i.pending_ids.push_back(id);
-            (id, record)
         };
+
+        // the records must be written to the dead letter stream before they can be trimmed
+        if let (Some(dead_letter_stream), Some(c)) = (dead_letter_stream, consumer.as_ref()) {
+            for (id, error) in ids_to_dead_letter {
+                let record = match read_record(&t_s.name, id, &self.stream_reader) {
+                    Some(r) => r,
+                    None => continue,
+                };
+                if let Some(on_record_failed) = c.ref_cell.borrow().on_record_failed.as_ref() {
+                    on_record_failed(&dead_letter_stream, &t_s.name, &record, &error, attempt);
+                }
+                consumer_info.ref_cell.borrow_mut().records_dead_lettered += 1;
+            }
+        }
+
+        if trimmed_first && trim {
+            t_s.trim();
+        }
+
+        if ids_to_retry.is_empty() {
+            return;
+        }
+        let processor = self.clone();
+        let backoff = retry_backoff.saturating_mul(1 << (attempt - 1).min(32));
+        (self.delay_executor)(
+            backoff,
+            Box::new(move || processor.retry_batch(ids_to_retry, attempt + 1, generation)),
+        );
+    }
+
+    fn retry_batch(&self, ids: Vec<RedisModuleStreamID>, attempt: usize, generation: usize) {
+        let (consumer, consumer_info) =
+            match (self.consumer.upgrade(), self.consumer_info.upgrade()) {
+                (Some(c), Some(c_i)) => (c, c_i),
+                _ => return,
+            };
+        if consumer_info.ref_cell.borrow().generation != generation {
+            return;
+        }
+        let name = self.stream.ref_cell.borrow().name.clone();
+        let mut records = Vec::new();
+        {
+            let start_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            let mut c_i = consumer_info.ref_cell.borrow_mut();
+            for id in ids {
+                match read_record(&name, id, &self.stream_reader) {
+                    Some(r) => records.push(r),
+                    // the record was deleted from the stream, nothing to retry.
+                    None => {
+                        c_i.ack_id(id, start_time);
+                    }
+                }
+            }
+            if records.is_empty() {
+                c_i.pending_batches = c_i.pending_batches.saturating_sub(1);
+            }
+        }
+        if !records.is_empty() {
+            self.deliver_batch(&consumer, records, attempt, generation);
+        }
+        self.send_new_data(false);
+    }
+
+    fn deliver_batch(
+        &self,
+        consumer: &Arc<RefCellWrapper<ConsumerData<T, C>>>,
+        records: Vec<T>,
+        attempt: usize,
+        generation: usize,
+    ) {
+        let ids = records
+            .iter()
+            .map(|r| r.get_id())
+            .collect::<Vec<RedisModuleStreamID>>();
         let start_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
         let res = {
-            let t_s = stream.ref_cell.borrow();
+            let t_s = self.stream.ref_cell.borrow();
             let c = consumer.ref_cell.borrow();
-            let clone_consumer_weak = Weak::clone(&consumer_weak);
-            let clone_consumer_info = Arc::downgrade(&consumer_info);
-            let clone_stream = Arc::clone(&stream);
-            let clone_stream_reader = Arc::clone(&stream_reader);
+            let processor = self.clone();
+            let clone_ids = ids.clone();
             c.consumer.as_ref().unwrap().new_data(
                 &t_s.name,
-                record,
-                Box::new(move |ack| {
-                    // if weak ref returns None it means that stream was deleted
-                    if let Some(clone_consumer_info) = clone_consumer_info.upgrade() {
-                        let record = {
-                            let mut t_s = clone_stream.ref_cell.borrow_mut();
-                            let last_read_id = {
-                                let (trimmed_first, last_read_id) = {
-                                    let mut c_i = clone_consumer_info.ref_cell.borrow_mut();
-                                    let mut trimmed_first = c_i.ack_id(id, start_time);
-                                    if let Some(c) = clone_consumer_weak.upgrade() {
-                                        // consumer is still allive, fire the on acked event.
-                                        if trimmed_first {
-                                            // only if we trimmed the first element we
-                                            // can fire the acked callback to notify
-                                            // that it is safe to continue from this ID
-                                            // in case of a crash.
-                                            if let Some(on_record_acked) =
-                                                c.ref_cell.borrow().on_record_acked.as_ref()
-                                            {
-                                                on_record_acked(&t_s.name, id.ms, id.seq);
-                                            }
-                                        }
-                                    } else {
-                                        // consumer is dead, lets not trim the stream.
-                                        trimmed_first = false;
-                                    }
-                                    match ack {
-                                        StreamReaderAck::Ack => {}
-                                        StreamReaderAck::Nack(msg) => c_i.last_error = Some(msg),
-                                    }
-                                    (trimmed_first, c_i.last_read_id)
-                                };
-                                if trimmed_first && trim {
-                                    t_s.trim();
-                                }
-                                last_read_id
-                            };
-                            read_next_data(
-                                &t_s.name,
-                                last_read_id,
-                                false,
-                                &clone_consumer_info,
-                                &clone_stream_reader,
-                            )
-                        };
-                        send_new_data(
-                            clone_stream,
-                            clone_consumer_weak,
-                            record,
-                            clone_consumer_info,
-                            clone_stream_reader,
-                        );
-                    }
+                records,
+                Box::new(move |acks| {
+                    processor.ack_batch(&clone_ids, acks, attempt, start_time, generation);
+                    processor.send_new_data(false);
                 }),
             )
         };
 
-        let mut t_s = stream.ref_cell.borrow_mut();
-        let last_read_id = match res {
-            Some(r) => {
-                let (trimmed_first, last_read_id) = {
-                    let mut c_i = consumer_info.ref_cell.borrow_mut();
-                    let mut trimmed_first = c_i.ack_id(id, start_time);
-                    if let Some(c) = consumer_weak.upgrade() {
-                        // consumer is still allive, fire the on acked event.
-                        if trimmed_first {
-                            // only if we trimmed the first element we
-                            // can fire the acked callback to notify
-                            // that it is safe to continue from this ID
-                            // in case of a crash.
-                            if let Some(on_record_acked) =
-                                c.ref_cell.borrow().on_record_acked.as_ref()
-                            {
-                                on_record_acked(&t_s.name, id.ms, id.seq);
-                            }
-                        }
-                    } else {
-                        // consumer is dead, lets not trim the stream.
-                        trimmed_first = false;
-                    }
-                    match r {
-                        StreamReaderAck::Ack => {}
-                        StreamReaderAck::Nack(msg) => c_i.last_error = Some(msg),
-                    }
-                    (trimmed_first, c_i.last_read_id)
-                };
-                if trimmed_first && trim {
-                    t_s.trim();
-                }
-                last_read_id
+        if let Some(acks) = res {
+            self.ack_batch(&ids, acks, attempt, start_time, generation);
+        }
+    }
+
+    fn send_new_data(&self, mut flush: bool) {
+        let (consumer, consumer_info) =
+            match (self.consumer.upgrade(), self.consumer_info.upgrade()) {
+                (Some(c), Some(c_i)) => (c, c_i),
+                _ => return,
+            };
+        let (window, batch_size, batch_max_wait) = {
+            let c = consumer.ref_cell.borrow();
+            if c.paused {
+                return;
             }
-            None => {
-                let window = { consumer.ref_cell.borrow().window };
+            (c.window, c.batch_size, c.batch_max_wait)
+        };
+        let name = self.stream.ref_cell.borrow().name.clone();
+        loop {
+            let last_read_id = {
                 let c_i = consumer_info.ref_cell.borrow();
-                if c_i.pending_ids.len() >= window {
+                if c_i.paused || c_i.pending_batches >= window {
                     return;
                 }
                 c_i.last_read_id
+            };
+            if batch_max_wait > 0 && !flush {
+                // count the records that were added after the ones that were already
+                // counted, the batch is only read once it is full.
+                let (mut count, mut last_counted_id) =
+                    match consumer_info.ref_cell.borrow().partial_batch {
+                        Some((count, id)) => (count, Some(id)),
+                        None => (0, last_read_id),
+                    };
+                while count < batch_size {
+                    match (self.stream_reader)(&name, last_counted_id, false) {
+                        Ok(Some(record)) => {
+                            count += 1;
+                            last_counted_id = Some(record.get_id());
+                        }
+                        Ok(None) => break,
+                        Err(_) => return,
+                    }
+                }
+                if count < batch_size {
+                    if count == 0 {
+                        return;
+                    }
+                    // wait for the batch to fill up
+                    let mut c_i = consumer_info.ref_cell.borrow_mut();
+                    c_i.partial_batch = last_counted_id.map(|id| (count, id));
+                    if !c_i.batch_wait_scheduled {
+                        c_i.batch_wait_scheduled = true;
+                        let processor = self.clone();
+                        (self.delay_executor)(
+                            batch_max_wait,
+                            Box::new(move || {
+                                // if weak ref returns None it means that stream was deleted
+                                if let Some(consumer_info) = processor.consumer_info.upgrade() {
+                                    consumer_info.ref_cell.borrow_mut().batch_wait_scheduled =
+                                        false;
+                                    processor.send_new_data(true);
+                                }
+                            }),
+                        );
+                    }
+                    return;
+                }
             }
-        };
-        actual_record = read_next_data(
-            &t_s.name,
-            last_read_id,
-            false,
-            &consumer_info,
-            &stream_reader,
-        );
+            flush = false;
+            consumer_info.ref_cell.borrow_mut().partial_batch = None;
+
+            let records =
+                match read_next_batch(&name, batch_size, &consumer_info, &self.stream_reader) {
+                    Ok(r) => r,
+                    Err(_) => return,
+                };
+            if records.is_empty() {
+                return;
+            }
+
+            let generation = {
+                let mut c_i = consumer_info.ref_cell.borrow_mut();
+                c_i.pending_ids.extend(records.iter().map(|r| r.get_id()));
+                c_i.pending_batches += 1;
+                c_i.generation
+            };
+            self.deliver_batch(&consumer, records, 1, generation);
+        }
     }
 }
 
@@ -436,52 +912,64 @@ where
     C: StreamConsumer<T> + 'static,
 {
     pub(crate) fn new(
-        stream_reader: Box<
-            dyn Fn(&str, Option<RedisModuleStreamID>, bool) -> Result<Option<T>, String>
-                + Sync
-                + Send,
-        >,
+        stream_reader: StreamReaderFn<T>,
         steam_trimmer: Box<dyn Fn(&str, RedisModuleStreamID) + Sync + Send>,
+        delay_executor: DelayExecutor,
+        stream_last_id: StreamLastIdFn,
     ) -> Self {
         StreamReaderCtx {
             consumers: Vec::new(),
             stream_reader: Arc::new(stream_reader),
             stream_trimmer: Arc::new(steam_trimmer),
+            delay_executor: Arc::new(delay_executor),
+            stream_last_id: stream_last_id,
             tracked_streams: HashMap::new(),
+            matched_consumers: HashMap::new(),
         }
     }
 
     pub(crate) fn clear(&mut self) {
         self.consumers.clear();
         self.tracked_streams.clear();
+        self.matched_consumers.clear();
     }
 
     pub(crate) fn add_consumer(
         &'static mut self,
-        prefix: &str,
+        streams: ConsumerStreams,
         consumer: C,
-        window: usize,
-        trim: bool,
+        options: ConsumerOptions,
         on_record_acked: Option<Box<dyn Fn(&str, u64, u64)>>,
+        on_record_failed: Option<RecordFailedCallback<T>>,
     ) -> Arc<RefCellWrapper<ConsumerData<T, C>>> {
         let consumer_data = Arc::new(RefCellWrapper {
             ref_cell: RefCell::new(ConsumerData {
-                prefix: prefix.to_string(),
+                streams: streams,
                 consumer: Some(consumer),
                 consumed_streams: HashMap::new(),
                 phantom: std::marker::PhantomData::<T>,
-                window: window,
-                trim: trim,
+                window: options.window,
+                trim: options.trim,
+                batch_size: options.batch_size,
+                batch_max_wait: options.batch_max_wait,
+                max_attempts: options.max_attempts,
+                retry_backoff: options.retry_backoff,
+                dead_letter_stream: options.dead_letter_stream,
+                start: options.start,
                 on_record_acked: on_record_acked,
+                on_record_failed: on_record_failed,
+                paused: false,
             }),
         });
         self.consumers.push(Arc::downgrade(&consumer_data));
+        self.matched_consumers.clear();
         consumer_data
     }
 
     pub(crate) fn on_stream_deleted(&mut self, _event: &str, key: &str) {
         let mut ids_to_remove = Vec::new();
         self.tracked_streams.remove(key);
+        self.matched_consumers.remove(key);
         for (i, c) in self.consumers.iter().enumerate() {
             let c = c.upgrade();
             if c.is_none() {
@@ -509,15 +997,15 @@ where
             }))
     }
 
-    pub(crate) fn update_stream_for_consumer(
+    fn get_or_create_consumer_info(
         &mut self,
         stream_name: &str,
         consumer_data: &Arc<RefCellWrapper<ConsumerData<T, C>>>,
-        ms: u64,
-        seq: u64,
-    ) {
+    ) -> Arc<RefCellWrapper<ConsumerInfo>> {
         let mut c_d = consumer_data.ref_cell.borrow_mut();
-        let (stream_info, is_new) = c_d.get_or_create_consumed_stream(stream_name);
+        let stream_last_id = &self.stream_last_id;
+        let (stream_info, is_new) =
+            c_d.get_or_create_consumed_stream(stream_name, || stream_last_id(stream_name));
         if is_new {
             let mut t_s = self
                 .get_or_create_tracked_stream(stream_name)
@@ -525,86 +1013,123 @@ where
                 .borrow_mut();
             t_s.consumers_data.push(Arc::downgrade(&stream_info));
         }
-        stream_info.ref_cell.borrow_mut().last_read_id =
-            Some(RedisModuleStreamID { ms: ms, seq: seq });
+        stream_info
+    }
+
+    pub(crate) fn update_stream_for_consumer(
+        &mut self,
+        stream_name: &str,
+        consumer_data: &Arc<RefCellWrapper<ConsumerData<T, C>>>,
+        ms: u64,
+        seq: u64,
+    ) {
+        let stream_info = self.get_or_create_consumer_info(stream_name, consumer_data);
+        let mut stream_info = stream_info.ref_cell.borrow_mut();
+        stream_info.last_read_id = Some(RedisModuleStreamID { ms: ms, seq: seq });
+        stream_info.partial_batch = None;
+    }
+
+    // same as update_stream_for_consumer, but also forgets the records the
+    // consumer currently processes from the stream, their acks are ignored.
+    pub(crate) fn seek_stream_for_consumer(
+        &mut self,
+        stream_name: &str,
+        consumer_data: &Arc<RefCellWrapper<ConsumerData<T, C>>>,
+        ms: u64,
+        seq: u64,
+    ) {
+        let stream_info = self.get_or_create_consumer_info(stream_name, consumer_data);
+        stream_info
+            .ref_cell
+            .borrow_mut()
+            .seek(RedisModuleStreamID { ms: ms, seq: seq });
+    }
+
+    pub(crate) fn set_stream_paused(
+        &mut self,
+        stream_name: &str,
+        consumer_data: &Arc<RefCellWrapper<ConsumerData<T, C>>>,
+        paused: bool,
+    ) {
+        let stream_info = self.get_or_create_consumer_info(stream_name, consumer_data);
+        stream_info.ref_cell.borrow_mut().paused = paused;
     }
 
     pub(crate) fn clear_tracked_streams(&mut self) {
         self.tracked_streams.clear();
     }
 
-    pub(crate) fn on_stream_touched(&mut self, _event: &str, key: &str) {
+    pub(crate) fn on_stream_touched(&mut self, event: &str, key: &str) {
         let mut ids_to_remove = Vec::new();
 
         let tracked_stream = Arc::clone(self.get_or_create_tracked_stream(key));
+        let stream_last_id = &self.stream_last_id;
 
-        let _ = self
-            .consumers
-            .iter()
-            .enumerate()
-            .filter(|(i, v)| {
-                let v = v.upgrade();
-                if v.is_none() {
-                    ids_to_remove.push(*i);
-                    return false;
-                }
-                let v = v.unwrap();
-                let v = v.ref_cell.borrow();
-                if key.starts_with(&v.prefix) {
-                    true
-                } else {
-                    false
-                }
-            })
-            .map(|(_, v)| {
-                let consumer = v.upgrade().unwrap();
-                let (record, consumer_info) = {
-                    let mut c = consumer.ref_cell.borrow_mut();
-                    let (consumer_info, is_new) = c.get_or_create_consumed_stream(key);
-                    if is_new {
-                        let mut t_s = tracked_stream.ref_cell.borrow_mut();
-                        t_s.consumers_data.push(Arc::downgrade(&consumer_info));
+        if !self.matched_consumers.contains_key(key) {
+            let matched_consumers = self
+                .consumers
+                .iter()
+                .enumerate()
+                .filter(|(i, v)| {
+                    let v = v.upgrade();
+                    if v.is_none() {
+                        ids_to_remove.push(*i);
+                        return false;
                     }
-                    let last_read_id = {
-                        let c_i = consumer_info.ref_cell.borrow();
-                        if c_i.pending_ids.len() >= c.window {
-                            return None;
-                        }
-                        c_i.last_read_id
-                    };
+                    let v = v.unwrap();
+                    let v = v.ref_cell.borrow();
+                    v.streams.matches(key)
+                })
+                .map(|(_, v)| Weak::clone(v))
+                .collect();
+            self.matched_consumers
+                .insert(key.to_string(), matched_consumers);
+        }
 
-                    (
-                        read_next_data(
-                            key,
-                            last_read_id,
-                            false,
-                            &consumer_info,
-                            &self.stream_reader,
-                        ),
-                        Arc::clone(&consumer_info),
-                    )
-                };
-                let res = (Weak::clone(v), record, consumer_info);
-                Some(res)
+        let _ = self.matched_consumers[key]
+            .iter()
+            .filter_map(|v| v.upgrade().map(|c| (v, c)))
+            .map(|(v, consumer)| {
+                let mut c = consumer.ref_cell.borrow_mut();
+                let (consumer_info, is_new) = c.get_or_create_consumed_stream(key, || {
+                    let last_id = stream_last_id(key);
+                    if event != "xadd" {
+                        return last_id;
+                    }
+                    // the stream was first touched by adding an entry to it, the
+                    // added entry (the last one) was added after the consumer started.
+                    last_id.and_then(|id| match id {
+                        RedisModuleStreamID { ms: 0, seq: 0 } => None,
+                        RedisModuleStreamID { ms, seq: 0 } => Some(RedisModuleStreamID {
+                            ms: ms - 1,
+                            seq: u64::MAX,
+                        }),
+                        RedisModuleStreamID { ms, seq } => Some(RedisModuleStreamID {
+                            ms: ms,
+                            seq: seq - 1,
+                        }),
+                    })
+                });
+                if is_new {
+                    let mut t_s = tracked_stream.ref_cell.borrow_mut();
+                    t_s.consumers_data.push(Arc::downgrade(&consumer_info));
+                }
+                (Weak::clone(v), consumer_info)
             })
-            .collect::<Vec<
-                Option<(
-                    Weak<RefCellWrapper<ConsumerData<T, C>>>,
-                    Result<Option<T>, String>,
-                    Arc<RefCellWrapper<ConsumerInfo>>,
-                )>,
-            >>()
+            .collect::<Vec<(
+                Weak<RefCellWrapper<ConsumerData<T, C>>>,
+                Arc<RefCellWrapper<ConsumerInfo>>,
+            )>>()
             .into_iter()
-            .map(|res| {
-                if let Some((consumer_weak, record, consumer_info)) = res {
-                    send_new_data(
-                        Arc::clone(&tracked_stream),
-                        consumer_weak,
-                        record,
-                        consumer_info,
-                        Arc::clone(&self.stream_reader),
-                    );
+            .map(|(consumer_weak, consumer_info)| {
+                StreamProcessor {
+                    stream: Arc::clone(&tracked_stream),
+                    consumer: consumer_weak,
+                    consumer_info: Arc::downgrade(&consumer_info),
+                    stream_reader: Arc::clone(&self.stream_reader),
+                    delay_executor: Arc::clone(&self.delay_executor),
                 }
+                .send_new_data(false);
             })
             .collect::<Vec<()>>();
 
@@ -1,7 +1,8 @@
 use redisgears_plugin_api::redisgears_plugin_api::{
     run_function_ctx::BackgroundRunFunctionCtxInterface, run_function_ctx::RedisClientCtxInterface,
-    stream_ctx::StreamCtxInterface, stream_ctx::StreamProcessCtxInterface,
-    stream_ctx::StreamRecordAck, stream_ctx::StreamRecordInterface,
+    stream_ctx::StreamBatchAck, stream_ctx::StreamCtxInterface,
+    stream_ctx::StreamProcessCtxInterface, stream_ctx::StreamRecordAck,
+    stream_ctx::StreamRecordInterface, GearsApiError, GearsApiErrorKind,
 };
 
 use redis_module::{
@@ -17,7 +18,7 @@ use crate::{
 
 use crate::stream_reader::{StreamConsumer, StreamReaderAck};
 
-use crate::get_notification_blocker;
+use crate::{get_notification_blocker, get_running_library_guard};
 
 use crate::RefCellWrapper;
 use std::sync::Arc;
@@ -80,24 +81,65 @@ impl StreamRecordInterface for GearsStreamRecord {
 
 pub(crate) struct GearsStreamConsumer {
     pub(crate) ctx: Box<dyn StreamCtxInterface>,
+    library_name: String,
     user: Arc<RefCellWrapper<String>>,
     flags: u8,
     permissions: AclPermissions,
+    // whether the records are passed to the consumer in batches or one by one
+    batched: bool,
+}
+
+fn record_ack_to_reader_ack(ack: StreamRecordAck) -> StreamReaderAck {
+    match ack {
+        StreamRecordAck::Ack => StreamReaderAck::Ack,
+        StreamRecordAck::Nack(err) => StreamReaderAck::Nack(err.get_error_string()),
+    }
+}
+
+fn batch_ack_to_reader_acks(ack: StreamBatchAck, num_records: usize) -> Vec<StreamReaderAck> {
+    match ack {
+        StreamBatchAck::Batch(ack) => {
+            let ack = record_ack_to_reader_ack(ack);
+            (0..num_records)
+                .map(|_| match &ack {
+                    StreamReaderAck::Ack => StreamReaderAck::Ack,
+                    StreamReaderAck::Nack(msg) => StreamReaderAck::Nack(msg.clone()),
+                })
+                .collect()
+        }
+        StreamBatchAck::Records(acks) => {
+            if acks.len() != num_records {
+                let msg = format!(
+                    "Got {} acks for a batch of {} records",
+                    acks.len(),
+                    num_records
+                );
+                return (0..num_records)
+                    .map(|_| StreamReaderAck::Nack(msg.clone()))
+                    .collect();
+            }
+            acks.into_iter().map(record_ack_to_reader_ack).collect()
+        }
+    }
 }
 
 impl GearsStreamConsumer {
     pub(crate) fn new(
+        library_name: &str,
         user: &Arc<RefCellWrapper<String>>,
         flags: u8,
+        batched: bool,
         ctx: Box<dyn StreamCtxInterface>,
     ) -> GearsStreamConsumer {
         let mut permissions = AclPermissions::new();
         permissions.add_full_permission();
         GearsStreamConsumer {
             ctx: ctx,
+            library_name: library_name.to_string(),
             user: Arc::clone(user),
             flags: flags,
             permissions: permissions,
+            batched: batched,
         }
     }
 }
@@ -106,41 +148,61 @@ impl StreamConsumer<GearsStreamRecord> for GearsStreamConsumer {
     fn new_data(
         &self,
         stream_name: &str,
-        record: GearsStreamRecord,
-        ack_callback: Box<dyn FnOnce(StreamReaderAck) + Send>,
-    ) -> Option<StreamReaderAck> {
+        mut records: Vec<GearsStreamRecord>,
+        ack_callback: Box<dyn FnOnce(Vec<StreamReaderAck>) + Send>,
+    ) -> Option<Vec<StreamReaderAck>> {
+        let num_records = records.len();
         let user = self.user.ref_cell.borrow();
         let key_redis_str = RedisString::create(std::ptr::null_mut(), stream_name);
         if let Err(e) = get_ctx().acl_check_key_permission(&user, &key_redis_str, &self.permissions)
         {
-            return Some(StreamReaderAck::Nack(format!(
-                "User '{}' has no permissions on key '{}', {}.",
-                user, stream_name, e
-            )));
+            let msg = GearsApiError::new_with_kind(
+                GearsApiErrorKind::NoPermission,
+                format!(
+                    "User '{}' has no permissions on key '{}', {}.",
+                    user, stream_name, e
+                ),
+            )
+            .get_error_string();
+            return Some(
+                (0..num_records)
+                    .map(|_| StreamReaderAck::Nack(msg.clone()))
+                    .collect(),
+            );
         }
 
-        let res = {
-            let _notification_blocker = get_notification_blocker();
-            self.ctx.process_record(
+        let _notification_blocker = get_notification_blocker();
+        let _running_library_guard = get_running_library_guard(&self.library_name);
+        let run_ctx = StreamRunCtx::new(self.user.ref_cell.borrow().clone(), self.flags);
+        if !self.batched {
+            let res = self.ctx.process_record(
                 stream_name,
-                Box::new(record),
-                &mut StreamRunCtx::new(self.user.ref_cell.borrow().clone(), self.flags),
+                Box::new(records.pop().unwrap()),
+                &run_ctx,
                 Box::new(|ack| {
                     // here we must take the redis lock
                     let ctx = ThreadSafeContext::new();
                     let _gaurd = ctx.lock();
-                    ack_callback(match ack {
-                        StreamRecordAck::Ack => StreamReaderAck::Ack,
-                        StreamRecordAck::Nack(msg) => StreamReaderAck::Nack(msg),
-                    })
+                    ack_callback(vec![record_ack_to_reader_ack(ack)])
                 }),
-            )
-        };
-        res.map_or(None, |r| {
-            Some(match r {
-                StreamRecordAck::Ack => StreamReaderAck::Ack,
-                StreamRecordAck::Nack(msg) => StreamReaderAck::Nack(msg),
-            })
-        })
+            );
+            return res.map(|r| vec![record_ack_to_reader_ack(r)]);
+        }
+
+        let res = self.ctx.process_batch(
+            stream_name,
+            records
+                .into_iter()
+                .map(|r| Box::new(r) as Box<dyn StreamRecordInterface + Send>)
+                .collect(),
+            &run_ctx,
+            Box::new(move |ack| {
+                // here we must take the redis lock
+                let ctx = ThreadSafeContext::new();
+                let _gaurd = ctx.lock();
+                ack_callback(batch_ack_to_reader_acks(ack, num_records))
+            }),
+        );
+        res.map(|r| batch_ack_to_reader_acks(r, num_records))
     }
 }
@@ -2,11 +2,36 @@ use crate::redisgears_plugin_api::load_library_ctx::LibraryCtxInterface;
 use crate::redisgears_plugin_api::CallResult;
 use crate::redisgears_plugin_api::GearsApiError;
 use std::alloc::GlobalAlloc;
+use std::os::raw::c_char;
+
+// Must be increased on any change to the plugin API that breaks
+// compatibility with plugins that were built against an older version.
+pub const GEARS_PLUGIN_API_VERSION: u32 = 12;
+
+// Name of the descriptor symbol each backend plugin must export.
+pub const GEARS_PLUGIN_DESCRIPTOR_SYMBOL: &[u8] = b"gears_plugin_descriptor";
+
+// Exported by the plugin so the core can verify it was built against the same
+// plugin API before calling any other plugin code. Must keep a stable layout.
+#[repr(C)]
+pub struct BackendPluginDescriptor {
+    pub api_version: u32,
+    pub name: *const c_char,
+    pub build_info: *const c_char,
+}
+
+unsafe impl Sync for BackendPluginDescriptor {}
 
 pub trait CompiledLibraryInterface {
     fn log(&self, msg: &str);
     fn run_on_background(&self, job: Box<dyn FnOnce() + Send>);
     fn get_maxmemory(&self) -> usize;
+    // code of a module that was loaded for the backend the library is compiled by,
+    // a library that got a module is considered as depending on it. Relative names
+    // ('./', '../') are files of the library bundle, resolved from the referrer (the
+    // resolved name of the importing module, None for the library itself). Returns
+    // the resolved name of the module, to be used as its referrer, and its code.
+    fn get_module(&self, name: &str, referrer: Option<&str>) -> Option<(String, String)>;
 }
 
 #[derive(Clone)]
@@ -24,6 +49,7 @@ pub struct BackendCtx {
 
 pub trait BackendCtxInterface {
     fn get_name(&self) -> &'static str;
+    fn get_version(&self) -> String;
     fn initialize(&self, backend_ctx: BackendCtx) -> Result<(), GearsApiError>;
     fn compile_library(
         &mut self,
@@ -31,4 +57,6 @@ pub trait BackendCtxInterface {
         compiled_library_api: Box<dyn CompiledLibraryInterface + Send + Sync>,
     ) -> Result<Box<dyn LibraryCtxInterface>, GearsApiError>;
     fn debug(&mut self, args: &[&str]) -> Result<CallResult, GearsApiError>;
+    // (name, value) pairs that will be shown on the backend section of the module info
+    fn get_info(&self) -> Vec<(String, String)>;
 }
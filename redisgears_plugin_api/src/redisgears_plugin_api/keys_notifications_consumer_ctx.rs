@@ -1,5 +1,6 @@
 use crate::redisgears_plugin_api::run_function_ctx::BackgroundRunFunctionCtxInterface;
 use crate::redisgears_plugin_api::run_function_ctx::RedisClientCtxInterface;
+use crate::redisgears_plugin_api::GearsApiError;
 use std::any::Any;
 
 pub trait NotificationFiredDataInterface {}
@@ -21,6 +22,6 @@ pub trait KeysNotificationsConsumerCtxInterface {
         &self,
         notificaion_data: Option<Box<dyn Any>>,
         notification_ctx: Box<dyn NotificationRunCtxInterface>,
-        ack_callback: Box<dyn FnOnce(Result<(), String>) + Send + Sync>,
+        ack_callback: Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>,
     );
 }
@@ -8,6 +8,13 @@ pub trait LibraryCtxInterface {
         &self,
         load_library_ctx: &mut dyn LoadLibraryCtxInterface,
     ) -> Result<(), GearsApiError>;
+    // (name, value) pairs that will be shown on the library entry of the module info
+    fn get_info(&self) -> Vec<(String, String)>;
+    // current heap usage of the library, in bytes
+    fn get_used_memory(&self) -> usize;
+    // called with the new library configuration (a json object) after it was
+    // changed, an error reverts the configuration to its previous value.
+    fn on_config_changed(&self, config: &str) -> Result<(), GearsApiError>;
 }
 
 pub enum RegisteredKeys<'a> {
@@ -15,8 +22,43 @@ pub enum RegisteredKeys<'a> {
     Prefix(&'a str),
 }
 
+// the streams a stream consumer reads from
+pub enum RegisteredStreams {
+    Prefix(String),
+    // glob-style pattern, same as the pattern given to the KEYS command
+    Pattern(String),
+    Streams(Vec<String>),
+}
+
+// where a stream consumer starts reading a stream it did not read before
+pub enum StreamStartPosition {
+    Beginning,
+    // only entries that were added after the consumer was registered
+    NewEntries,
+    // entries that comes after the given id (ms, seq)
+    Id(u64, u64),
+    // entries that were added at or after the given unix time in ms
+    Timestamp(u64),
+}
+
+pub struct StreamConsumerOptions {
+    pub start: StreamStartPosition,
+    // max amount of records to pass on each call to the consumer, None to pass
+    // the records one by one.
+    pub batch_size: Option<usize>,
+    // max time in ms to wait for a batch to fill up before passing it to the consumer
+    pub batch_max_wait: u64,
+    // max times to pass a record to the consumer until it is acknowledged, 1 for no retries
+    pub max_attempts: usize,
+    // time in ms to wait before the first retry, doubled on each retry
+    pub retry_backoff: u64,
+    // stream to add the records that failed on all attempts to
+    pub dead_letter_stream: Option<String>,
+}
+
 pub const FUNCTION_FLAG_NO_WRITES: u8 = 0x01;
 pub const FUNCTION_FLAG_ALLOW_OOM: u8 = 0x02;
+pub const FUNCTION_FLAG_RAW_ARGUMENTS: u8 = 0x04;
 
 pub trait LoadLibraryCtxInterface {
     fn register_function(
@@ -28,10 +70,11 @@ pub trait LoadLibraryCtxInterface {
     fn register_stream_consumer(
         &mut self,
         name: &str,
-        prefix: &str,
+        streams: RegisteredStreams,
         stream_ctx: Box<dyn StreamCtxInterface>,
         window: usize,
         trim: bool,
+        options: StreamConsumerOptions,
     ) -> Result<(), GearsApiError>;
     fn register_key_space_notification_consumer(
         &mut self,
@@ -39,4 +82,6 @@ pub trait LoadLibraryCtxInterface {
         key: RegisteredKeys,
         keys_notifications_consumer_ctx: Box<dyn KeysNotificationsConsumerCtxInterface>,
     ) -> Result<(), GearsApiError>;
+    // the library configuration, a json object
+    fn get_library_config(&self) -> String;
 }
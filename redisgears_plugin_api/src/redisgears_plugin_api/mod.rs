@@ -7,14 +7,118 @@ pub mod load_library_ctx;
 pub mod run_function_ctx;
 pub mod stream_ctx;
 
-pub enum GearsApiError {
-    Msg(String),
+#[derive(Clone, Copy, PartialEq)]
+pub enum GearsApiErrorKind {
+    Generic,
+    Compilation,
+    Exception,
+    Timeout,
+    OutOfMemory,
+    NoPermission,
+}
+
+impl GearsApiErrorKind {
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            GearsApiErrorKind::Generic => "ERR",
+            GearsApiErrorKind::Compilation => "COMPILE",
+            GearsApiErrorKind::Exception => "ERR",
+            GearsApiErrorKind::Timeout => "TIMEOUT",
+            GearsApiErrorKind::OutOfMemory => "OOM",
+            GearsApiErrorKind::NoPermission => "NOPERM",
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            GearsApiErrorKind::Generic => "generic",
+            GearsApiErrorKind::Compilation => "compilation",
+            GearsApiErrorKind::Exception => "exception",
+            GearsApiErrorKind::Timeout => "timeout",
+            GearsApiErrorKind::OutOfMemory => "oom",
+            GearsApiErrorKind::NoPermission => "noperm",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GearsApiError {
+    kind: GearsApiErrorKind,
+    msg: String,
+    stack_trace: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
 }
 
 impl GearsApiError {
+    pub fn new(msg: String) -> GearsApiError {
+        GearsApiError::new_with_kind(GearsApiErrorKind::Generic, msg)
+    }
+
+    pub fn new_with_kind(kind: GearsApiErrorKind, msg: String) -> GearsApiError {
+        GearsApiError {
+            kind: kind,
+            msg: msg,
+            stack_trace: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn new_exception(
+        msg: String,
+        stack_trace: Option<String>,
+        line: Option<usize>,
+        column: Option<usize>,
+    ) -> GearsApiError {
+        GearsApiError {
+            kind: GearsApiErrorKind::Exception,
+            msg: msg,
+            stack_trace: stack_trace,
+            line: line,
+            column: column,
+        }
+    }
+
+    // keep the error details but change the kind and prefix the message with more context
+    pub fn wrap(self, kind: GearsApiErrorKind, context: &str) -> GearsApiError {
+        GearsApiError {
+            kind: kind,
+            msg: format!("{}{}", context, self.msg),
+            ..self
+        }
+    }
+
+    pub fn get_kind(&self) -> GearsApiErrorKind {
+        self.kind
+    }
+
     pub fn get_msg(&self) -> &str {
-        match self {
-            GearsApiError::Msg(s) => &s,
+        &self.msg
+    }
+
+    pub fn get_stack_trace(&self) -> Option<&str> {
+        self.stack_trace.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn get_line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn get_column(&self) -> Option<usize> {
+        self.column
+    }
+
+    // the error as it should be replied to the client, generic errors
+    // are expected to already contain their error code (if any).
+    pub fn get_error_string(&self) -> String {
+        let msg = match self.kind {
+            GearsApiErrorKind::Generic => self.msg.to_string(),
+            _ => format!("{} {}", self.kind.get_code(), self.msg),
+        };
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{} (line {}, column {})", msg, line, column),
+            _ => msg,
         }
     }
 }
@@ -28,9 +132,17 @@ pub enum CallResult {
     Error(String),
     SimpleStr(String),
     BulkStr(String),
+    StringBuffer(Vec<u8>),
     Long(i64),
     Double(f64),
     Array(Vec<CallResult>),
+    Map(Vec<(CallResult, CallResult)>),
+    Set(Vec<CallResult>),
+    Bool(bool),
+    BigNumber(String),
+    VerbatimString(String),
+    // attributes followed by the reply they describe
+    Attribute(Vec<(CallResult, CallResult)>, Box<CallResult>),
     Null,
 }
 
@@ -2,7 +2,8 @@ use crate::redisgears_plugin_api::CallResult;
 use crate::redisgears_plugin_api::GearsApiError;
 
 pub trait RedisClientCtxInterface: Send + Sync {
-    fn call(&self, command: &str, args: &[&str]) -> CallResult;
+    fn call(&self, command: &str, args: &[&[u8]]) -> CallResult;
+    fn call_resp3(&self, command: &str, args: &[&[u8]]) -> CallResult;
     fn get_background_redis_client(&self) -> Box<dyn BackgroundRunFunctionCtxInterface>;
     fn as_redis_client(&self) -> &dyn RedisClientCtxInterface;
 }
@@ -10,10 +11,17 @@ pub trait RedisClientCtxInterface: Send + Sync {
 pub trait ReplyCtxInterface: Send + Sync {
     fn reply_with_simple_string(&self, val: &str);
     fn reply_with_error(&self, val: &str);
+    fn reply_with_gears_error(&self, err: &GearsApiError);
     fn reply_with_long(&self, val: i64);
     fn reply_with_double(&self, val: f64);
     fn reply_with_bulk_string(&self, val: &str);
+    fn reply_with_bulk_buffer(&self, val: &[u8]);
     fn reply_with_array(&self, size: usize);
+    fn reply_with_map(&self, size: usize);
+    fn reply_with_set(&self, size: usize);
+    fn reply_with_bool(&self, val: bool);
+    fn reply_with_big_number(&self, val: &str);
+    fn reply_with_verbatim_string(&self, val: &str);
     fn as_client(&self) -> &dyn ReplyCtxInterface;
 }
 
@@ -23,6 +31,8 @@ pub trait BackgroundRunFunctionCtxInterface: Send + Sync {
 
 pub trait RunFunctionCtxInterface: ReplyCtxInterface {
     fn next_arg<'a>(&'a mut self) -> Option<&'a [u8]>;
+    // None if the caller did not declare the keys (RG.FUNCTION CALL)
+    fn get_keys<'a>(&'a self) -> Option<Vec<&'a [u8]>>;
     fn get_background_client(&self) -> Box<dyn ReplyCtxInterface>;
     fn get_redis_client(&self) -> Box<dyn RedisClientCtxInterface>;
 }
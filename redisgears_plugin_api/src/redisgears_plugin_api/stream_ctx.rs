@@ -1,5 +1,6 @@
 use crate::redisgears_plugin_api::run_function_ctx::BackgroundRunFunctionCtxInterface;
 use crate::redisgears_plugin_api::run_function_ctx::RedisClientCtxInterface;
+use crate::redisgears_plugin_api::GearsApiError;
 
 pub trait StreamProcessCtxInterface {
     fn get_redis_client(&self) -> Box<dyn RedisClientCtxInterface>;
@@ -13,7 +14,14 @@ pub trait StreamRecordInterface {
 
 pub enum StreamRecordAck {
     Ack,
-    Nack(String),
+    Nack(GearsApiError),
+}
+
+pub enum StreamBatchAck {
+    // the same ack for all the records of the batch
+    Batch(StreamRecordAck),
+    // an ack per record, in the same order the records were given
+    Records(Vec<StreamRecordAck>),
 }
 
 pub trait StreamCtxInterface {
@@ -24,4 +32,12 @@ pub trait StreamCtxInterface {
         run_ctx: &dyn StreamProcessCtxInterface,
         ack_callback: Box<dyn FnOnce(StreamRecordAck) + Send>,
     ) -> Option<StreamRecordAck>;
+    // used by consumers that were registered with a batch size
+    fn process_batch(
+        &self,
+        stream_name: &str,
+        records: Vec<Box<dyn StreamRecordInterface + Send>>,
+        run_ctx: &dyn StreamProcessCtxInterface,
+        ack_callback: Box<dyn FnOnce(StreamBatchAck) + Send>,
+    ) -> Option<StreamBatchAck>;
 }
@@ -1,11 +1,14 @@
 use v8_rs::v8::{
     isolate::V8Isolate, try_catch::V8TryCatch, v8_array::V8LocalArray,
-    v8_context_scope::V8ContextScope,
+    v8_context_scope::V8ContextScope, v8_object::V8LocalObject, v8_value::V8LocalValue,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::{
-    backend_ctx::BackendCtxInterface, load_library_ctx::FUNCTION_FLAG_ALLOW_OOM,
-    load_library_ctx::FUNCTION_FLAG_NO_WRITES,
+    backend_ctx::BackendCtxInterface, backend_ctx::BackendPluginDescriptor,
+    backend_ctx::GEARS_PLUGIN_API_VERSION, load_library_ctx::RegisteredStreams,
+    load_library_ctx::StreamConsumerOptions, load_library_ctx::StreamStartPosition,
+    load_library_ctx::FUNCTION_FLAG_ALLOW_OOM, load_library_ctx::FUNCTION_FLAG_NO_WRITES,
+    load_library_ctx::FUNCTION_FLAG_RAW_ARGUMENTS, GearsApiError, GearsApiErrorKind,
 };
 
 mod v8_backend;
@@ -16,15 +19,94 @@ mod v8_script_ctx;
 mod v8_stream_ctx;
 
 use crate::v8_backend::V8Backend;
+use crate::v8_script_ctx::V8ScriptCtx;
+use std::os::raw::c_char;
 use std::sync::{Arc, Mutex};
 
-pub(crate) fn get_exception_msg(isolate: &V8Isolate, trycatch: V8TryCatch) -> String {
+// search the first stack frame for the error location, frames looks like:
+// "    at foo (<anonymous>:3:11)" or "    at <anonymous>:3:11"
+fn get_error_location(stack: &str) -> (Option<usize>, Option<usize>) {
+    let frame = match stack.lines().find(|l| l.trim_start().starts_with("at ")) {
+        Some(f) => f.trim_end().trim_end_matches(')'),
+        None => return (None, None),
+    };
+    let mut location = frame.rsplitn(3, ':');
+    let column = location.next().and_then(|c| c.parse::<usize>().ok());
+    let line = location.next().and_then(|l| l.parse::<usize>().ok());
+    match (line, column) {
+        (Some(line), Some(column)) => (Some(line), Some(column)),
+        _ => (None, None),
+    }
+}
+
+pub(crate) fn get_error_from_value(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    val: &V8LocalValue,
+) -> GearsApiError {
+    let msg = val.to_utf8(isolate).unwrap().as_str().to_string();
+    if !val.is_object() {
+        return GearsApiError::new_exception(msg, None, None, None);
+    }
+    let stack = val
+        .as_object()
+        .get(ctx_scope, &isolate.new_string("stack").to_value());
+    if !stack.is_string() {
+        return GearsApiError::new_exception(msg, None, None, None);
+    }
+    let stack = stack.to_utf8(isolate).unwrap().as_str().to_string();
+    let (line, column) = get_error_location(&stack);
+    GearsApiError::new_exception(msg, Some(stack), line, column)
+}
+
+// the bytes of an ArrayBuffer, or of an ArrayBuffer view (a typed array or a
+// DataView), only the part of the buffer that is covered by the view.
+pub(crate) fn get_buffer_data(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    val: &V8LocalValue,
+) -> Option<Vec<u8>> {
+    if val.is_array_buffer() {
+        return Some(val.as_array_buffer().data().to_vec());
+    }
+    if !val.is_object() || val.is_array() {
+        return None;
+    }
+    let view = val.as_object();
+    let buffer = view.get(ctx_scope, &isolate.new_string("buffer").to_value());
+    if !buffer.is_array_buffer() {
+        return None;
+    }
+    let offset = view.get(ctx_scope, &isolate.new_string("byteOffset").to_value());
+    let length = view.get(ctx_scope, &isolate.new_string("byteLength").to_value());
+    if !offset.is_long() || !length.is_long() || offset.get_long() < 0 || length.get_long() < 0 {
+        return None;
+    }
+    let offset = offset.get_long() as usize;
+    let end = offset.checked_add(length.get_long() as usize)?;
+    buffer
+        .as_array_buffer()
+        .data()
+        .get(offset..end)
+        .map(|data| data.to_vec())
+}
+
+pub(crate) fn get_exception_error(
+    script_ctx: &V8ScriptCtx,
+    ctx_scope: &V8ContextScope,
+    trycatch: V8TryCatch,
+) -> GearsApiError {
     if trycatch.has_terminated() {
-        isolate.cancel_terminate_execution();
-        "Err Execution was terminated due to OOM or timeout".to_string()
+        script_ctx.isolate.cancel_terminate_execution();
+        let kind = script_ctx
+            .take_termination_reason()
+            .unwrap_or(GearsApiErrorKind::Timeout);
+        GearsApiError::new_with_kind(
+            kind,
+            "Execution was terminated due to OOM or timeout".to_string(),
+        )
     } else {
-        let error_utf8 = trycatch.get_exception().to_utf8(isolate).unwrap();
-        error_utf8.as_str().to_string()
+        get_error_from_value(&script_ctx.isolate, ctx_scope, &trycatch.get_exception())
     }
 }
 
@@ -43,12 +125,156 @@ pub(crate) fn get_function_flags(
         match flag_str.as_str() {
             "no-writes" => flags_val |= FUNCTION_FLAG_NO_WRITES,
             "allow-oom" => flags_val |= FUNCTION_FLAG_ALLOW_OOM,
+            "raw-arguments" => flags_val |= FUNCTION_FLAG_RAW_ARGUMENTS,
             _ => return Err(format!("Unknow flag '{}' was given", flag_str.as_str())),
         }
     }
     Ok(flags_val)
 }
 
+// 'beginning', '$' (only new entries), a stream id or a unix time in ms
+fn get_stream_start_position(
+    isolate: &V8Isolate,
+    start: &V8LocalValue,
+) -> Result<StreamStartPosition, String> {
+    if start.is_long() {
+        let timestamp = start.get_long();
+        if timestamp < 0 {
+            return Err("start timestamp can not be negative".to_string());
+        }
+        return Ok(StreamStartPosition::Timestamp(timestamp as u64));
+    }
+    if !start.is_string() {
+        return Err("start must be a string or a timestamp".to_string());
+    }
+    let start_str = start.to_utf8(isolate).unwrap();
+    match start_str.as_str() {
+        "beginning" => Ok(StreamStartPosition::Beginning),
+        "$" => Ok(StreamStartPosition::NewEntries),
+        id => {
+            let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+            match (ms.parse::<u64>(), seq.parse::<u64>()) {
+                (Ok(ms), Ok(seq)) => Ok(StreamStartPosition::Id(ms, seq)),
+                _ => Err(format!("Invalid start position '{}'", id)),
+            }
+        }
+    }
+}
+
+// a prefix string, an array of stream names or an object with a glob 'pattern'
+pub(crate) fn get_stream_consumer_streams(
+    isolate: &V8Isolate,
+    curr_ctx_scope: &V8ContextScope,
+    streams: &V8LocalValue,
+) -> Result<RegisteredStreams, String> {
+    if streams.is_string() {
+        let prefix = streams.to_utf8(isolate).unwrap();
+        return Ok(RegisteredStreams::Prefix(prefix.as_str().to_string()));
+    }
+    if streams.is_array() {
+        let streams = streams.as_array();
+        let mut res = Vec::new();
+        for i in 0..streams.len() {
+            let stream = streams.get(curr_ctx_scope, i);
+            if !stream.is_string() {
+                return Err("must be an array of strings".to_string());
+            }
+            res.push(stream.to_utf8(isolate).unwrap().as_str().to_string());
+        }
+        if res.is_empty() {
+            return Err("can not be an empty array".to_string());
+        }
+        return Ok(RegisteredStreams::Streams(res));
+    }
+    if streams.is_object() {
+        let pattern = streams
+            .as_object()
+            .get(curr_ctx_scope, &isolate.new_string("pattern").to_value());
+        if !pattern.is_string() {
+            return Err("must have a string pattern".to_string());
+        }
+        let pattern = pattern.to_utf8(isolate).unwrap();
+        return Ok(RegisteredStreams::Pattern(pattern.as_str().to_string()));
+    }
+    Err("must be a prefix string, an array of streams or an object with a pattern".to_string())
+}
+
+pub(crate) fn get_stream_consumer_options(
+    isolate: &V8Isolate,
+    curr_ctx_scope: &V8ContextScope,
+    options: Option<&V8LocalObject>,
+) -> Result<StreamConsumerOptions, String> {
+    let mut res = StreamConsumerOptions {
+        start: StreamStartPosition::Beginning,
+        batch_size: None,
+        batch_max_wait: 0,
+        max_attempts: 1,
+        retry_backoff: 0,
+        dead_letter_stream: None,
+    };
+    let options = match options {
+        Some(o) => o,
+        None => return Ok(res),
+    };
+    let keys = options.get_property_names(curr_ctx_scope);
+    for i in 0..keys.len() {
+        let key = keys.get(curr_ctx_scope, i);
+        let key_str = key.to_utf8(isolate).unwrap();
+        let val = options.get(curr_ctx_scope, &key);
+        match key_str.as_str() {
+            "start" => res.start = get_stream_start_position(isolate, &val)?,
+            "batch_size" => {
+                if !val.is_long() || val.get_long() <= 0 {
+                    return Err("batch_size must be a positive number".to_string());
+                }
+                res.batch_size = Some(val.get_long() as usize);
+            }
+            "batch_max_wait" => {
+                if !val.is_long() || val.get_long() < 0 {
+                    return Err(
+                        "batch_max_wait must be a non negative number of milliseconds".to_string(),
+                    );
+                }
+                res.batch_max_wait = val.get_long() as u64;
+            }
+            "max_attempts" => {
+                if !val.is_long() || val.get_long() <= 0 {
+                    return Err("max_attempts must be a positive number".to_string());
+                }
+                res.max_attempts = val.get_long() as usize;
+            }
+            "retry_backoff" => {
+                if !val.is_long() || val.get_long() < 0 {
+                    return Err(
+                        "retry_backoff must be a non negative number of milliseconds".to_string(),
+                    );
+                }
+                res.retry_backoff = val.get_long() as u64;
+            }
+            "dead_letter_stream" => {
+                if !val.is_string() {
+                    return Err("dead_letter_stream must be a string".to_string());
+                }
+                res.dead_letter_stream = Some(val.to_utf8(isolate).unwrap().as_str().to_string());
+            }
+            _ => return Err(format!("Unknown option '{}'", key_str.as_str())),
+        }
+    }
+    if res.batch_max_wait > 0 && res.batch_size.is_none() {
+        return Err("batch_max_wait can only be set together with batch_size".to_string());
+    }
+    Ok(res)
+}
+
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static gears_plugin_descriptor: BackendPluginDescriptor = BackendPluginDescriptor {
+    api_version: GEARS_PLUGIN_API_VERSION,
+    name: "js\0".as_ptr() as *const c_char,
+    build_info: concat!("redisgears_v8_plugin ", env!("CARGO_PKG_VERSION"), "\0").as_ptr()
+        as *const c_char,
+};
+
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 pub extern "C" fn initialize_plugin() -> *mut dyn BackendCtxInterface {
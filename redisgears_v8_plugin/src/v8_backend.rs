@@ -1,22 +1,27 @@
 use redisgears_plugin_api::redisgears_plugin_api::{
     backend_ctx::BackendCtx, backend_ctx::BackendCtxInterface,
     backend_ctx::CompiledLibraryInterface, backend_ctx::LibraryFatalFailurePolicy,
-    load_library_ctx::LibraryCtxInterface, CallResult, GearsApiError,
+    load_library_ctx::LibraryCtxInterface, CallResult, GearsApiError, GearsApiErrorKind,
 };
 
 use crate::v8_script_ctx::V8ScriptCtx;
 
-use v8_rs::v8::{isolate::V8Isolate, v8_init_with_error_handlers};
+use v8_rs::v8::{
+    isolate::V8Isolate, v8_context_scope::V8ContextScope, v8_init_with_error_handlers,
+    v8_module::V8LocalModule, v8_module::V8PersistedModule, v8_string::V8LocalString,
+};
 
 use crate::v8_native_functions::initialize_globals;
 
-use crate::get_exception_msg;
-use crate::v8_script_ctx::V8LibraryCtx;
+use crate::get_error_from_value;
+use crate::v8_script_ctx::{V8LibraryCode, V8LibraryCtx};
 
 use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::str;
 
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 
 struct Globals {
@@ -54,6 +59,72 @@ pub(crate) fn gil_lock_timeout() -> u128 {
     unsafe { (GLOBAL.backend_ctx.as_ref().unwrap().get_lock_timeout)() }
 }
 
+// number of times a script was found to hold the Redis lock for longer than the configured timeout
+static LOCK_TIMEOUTS: AtomicUsize = AtomicUsize::new(0);
+// number of times a script was aborted because it reached its memory limit
+static OOM_ABORTS: AtomicUsize = AtomicUsize::new(0);
+
+// The modules that were resolved while compiling a library. Each module is
+// compiled once per library, so modules that are imported more than once
+// (shared or circular imports) are the same module instance.
+#[derive(Default)]
+struct ResolvedModules {
+    modules: HashMap<String, V8PersistedModule>,
+    // resolved module name by the module identity hash, used to resolve
+    // the relative imports of the module
+    names: HashMap<i64, String>,
+}
+
+// Resolves an import of the library (or of one of the modules it imports)
+// from the library bundle or from the modules that were loaded to RedisGears.
+fn resolve_module<'isolate>(
+    compiled_library_api: &dyn CompiledLibraryInterface,
+    resolved_modules: &RefCell<ResolvedModules>,
+    isolate: &'isolate V8Isolate,
+    ctx_scope: &V8ContextScope,
+    name: &V8LocalString,
+    referrer_identity_hash: i64,
+) -> Option<V8LocalModule<'isolate>> {
+    let name = name.to_value().to_utf8(isolate).unwrap();
+    let referrer = resolved_modules
+        .borrow()
+        .names
+        .get(&referrer_identity_hash)
+        .cloned();
+    let (resolved_name, code) =
+        match compiled_library_api.get_module(name.as_str(), referrer.as_deref()) {
+            Some(m) => m,
+            None => {
+                isolate.raise_exception_str(&format!("Unknown module '{}'", name.as_str()));
+                return None;
+            }
+        };
+    if let Some(module) = resolved_modules.borrow().modules.get(&resolved_name) {
+        return Some(module.to_local(isolate));
+    }
+    // json assets (validated when the bundle is loaded) are imported as a module
+    // that exports the parsed json
+    let code = if resolved_name.ends_with(".json") {
+        format!(
+            "export default JSON.parse({});",
+            serde_json::to_string(&code).unwrap()
+        )
+    } else {
+        code
+    };
+    let v8code_str = isolate.new_string(&code);
+    let v8name_str = isolate.new_string(&resolved_name);
+    let module = ctx_scope.compile_as_module(&v8name_str, &v8code_str)?;
+    let mut resolved_modules = resolved_modules.borrow_mut();
+    resolved_modules
+        .names
+        .insert(module.get_identity_hash(), resolved_name.to_string());
+    resolved_modules
+        .modules
+        .insert(resolved_name, module.persist(isolate));
+    Some(module)
+}
+
 pub(crate) struct V8Backend {
     pub(crate) script_ctx_vec: Arc<Mutex<Vec<Weak<V8ScriptCtx>>>>,
 }
@@ -78,6 +149,10 @@ impl BackendCtxInterface for V8Backend {
         "js"
     }
 
+    fn get_version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
     fn initialize(&self, backend_ctx: BackendCtx) -> Result<(), GearsApiError> {
         unsafe {
             GLOBAL.backend_ctx = Some(backend_ctx);
@@ -123,6 +198,7 @@ impl BackendCtxInterface for V8Backend {
                                 let gil_lock_configured_timeout = gil_lock_timeout();
                                 if gil_lock_duration > gil_lock_configured_timeout {
                                     script_ctx.set_lock_timedout();
+                                    LOCK_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
                                     script_ctx.compiled_library_api.log(&format!("Script locks Redis for about {}ms which is more then the configured timeout {}ms.", gil_lock_duration, gil_lock_configured_timeout));
                                     match get_fatal_failure_policy() {
                                         LibraryFatalFailurePolicy::Kill => {
@@ -130,6 +206,7 @@ impl BackendCtxInterface for V8Backend {
                                         }
                                         LibraryFatalFailurePolicy::Abort => {
                                             script_ctx.compiled_library_api.log("Aborting script with timeout error.");
+                                            script_ctx.set_termination_reason(GearsApiErrorKind::Timeout);
                                             isolate.terminate_execution();
                                         }
                                     }
@@ -156,7 +233,7 @@ impl BackendCtxInterface for V8Backend {
         );
 
         let script_ctx = {
-            let (ctx, script) = {
+            let (ctx, code) = {
                 let isolate_scope = isolate.enter();
                 let _handlers_scope = isolate.new_handlers_scope();
 
@@ -164,23 +241,64 @@ impl BackendCtxInterface for V8Backend {
                 let ctx_scope = ctx.enter();
 
                 let v8code_str = isolate.new_string(blob);
+                let v8name_str = isolate.new_string("main");
 
-                let trycatch = isolate.new_try_catch();
-                let script = match ctx_scope.compile(&v8code_str) {
-                    Some(s) => s,
+                // libraries are compiled as a classic script, only libraries that can not
+                // be compiled as a script (use import or export) are compiled as a module.
+                let script = {
+                    let _trycatch = isolate.new_try_catch();
+                    ctx_scope.compile(&v8code_str)
+                };
+                let code = match script {
+                    Some(s) => V8LibraryCode::Script(s.persist(&isolate)),
                     None => {
-                        let error_msg = get_exception_msg(&isolate, trycatch);
-                        return Err(GearsApiError::Msg(format!(
-                            "Failed compiling code, {}",
-                            error_msg
-                        )));
+                        let trycatch = isolate.new_try_catch();
+                        let module = match ctx_scope.compile_as_module(&v8name_str, &v8code_str) {
+                            Some(m) => m,
+                            None => {
+                                let error = get_error_from_value(
+                                    &isolate,
+                                    &ctx_scope,
+                                    &trycatch.get_exception(),
+                                );
+                                return Err(error.wrap(
+                                    GearsApiErrorKind::Compilation,
+                                    "Failed compiling code, ",
+                                ));
+                            }
+                        };
+
+                        let resolved_modules = RefCell::new(ResolvedModules::default());
+                        let initialized = module.initialize(
+                            &ctx_scope,
+                            |isolate, ctx_scope, name, referrer_identity_hash| {
+                                resolve_module(
+                                    &*compiled_library_api,
+                                    &resolved_modules,
+                                    isolate,
+                                    ctx_scope,
+                                    name,
+                                    referrer_identity_hash,
+                                )
+                            },
+                        );
+                        if !initialized {
+                            let error = get_error_from_value(
+                                &isolate,
+                                &ctx_scope,
+                                &trycatch.get_exception(),
+                            );
+                            return Err(error.wrap(
+                                GearsApiErrorKind::Compilation,
+                                "Failed resolving imported modules, ",
+                            ));
+                        }
+                        V8LibraryCode::Module(module.persist(&isolate))
                     }
                 };
-
-                let script = script.persist(&isolate);
-                (ctx, script)
+                (ctx, code)
             };
-            let script_ctx = Arc::new(V8ScriptCtx::new(isolate, ctx, script, compiled_library_api));
+            let script_ctx = Arc::new(V8ScriptCtx::new(isolate, ctx, code, compiled_library_api));
             let len = {
                 let mut l = self.script_ctx_vec.lock().unwrap();
                 l.push(Arc::downgrade(&script_ctx));
@@ -222,6 +340,8 @@ impl BackendCtxInterface for V8Backend {
                                 curr_limit as usize
                             }
                             LibraryFatalFailurePolicy::Abort => {
+                                script_ctx.set_termination_reason(GearsApiErrorKind::OutOfMemory);
+                                OOM_ABORTS.fetch_add(1, Ordering::Relaxed);
                                 script_ctx.isolate.terminate_execution();
 
                                 script_ctx
@@ -249,7 +369,7 @@ impl BackendCtxInterface for V8Backend {
         let sub_command = args
             .next()
             .map_or(
-                Err(GearsApiError::Msg(
+                Err(GearsApiError::new(
                     "Subcommand was not provided".to_string(),
                 )),
                 |v| Ok(v),
@@ -287,10 +407,31 @@ impl BackendCtxInterface for V8Backend {
                 self.isolates_gc();
                 Ok(CallResult::SimpleStr("OK".to_string()))
             }
-            _ => Err(GearsApiError::Msg(format!(
+            _ => Err(GearsApiError::new(format!(
                 "Unknown subcommand '{}'",
                 sub_command
             ))),
         }
     }
+
+    fn get_info(&self) -> Vec<(String, String)> {
+        let active_isolates = self
+            .script_ctx_vec
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|v| v.strong_count() > 0)
+            .count();
+        vec![
+            ("active_isolates".to_string(), active_isolates.to_string()),
+            (
+                "lock_timeouts".to_string(),
+                LOCK_TIMEOUTS.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "oom_aborts".to_string(),
+                OOM_ABORTS.load(Ordering::Relaxed).to_string(),
+            ),
+        ]
+    }
 }
@@ -9,9 +9,9 @@ use v8_rs::v8::{
     v8_value::V8LocalValue, v8_value::V8PersistValue,
 };
 
-use crate::get_exception_msg;
 use crate::v8_native_functions::{get_backgrounnd_client, RedisClient};
 use crate::v8_script_ctx::V8ScriptCtx;
+use crate::{get_buffer_data, get_error_from_value, get_exception_error};
 
 use std::cell::RefCell;
 use std::sync::Arc;
@@ -32,6 +32,7 @@ pub struct V8InternalFunction {
     persisted_client: V8PersistValue,
     persisted_function: V8PersistValue,
     script_ctx: Arc<V8ScriptCtx>,
+    raw_arguments: bool,
 }
 
 fn send_reply(
@@ -40,12 +41,18 @@ fn send_reply(
     client: &dyn ReplyCtxInterface,
     val: V8LocalValue,
 ) {
-    if val.is_long() {
+    if val.is_boolean() {
+        client.reply_with_bool(val.get_boolean());
+    } else if val.is_big_int() {
+        client.reply_with_big_number(val.to_utf8(isolate).unwrap().as_str());
+    } else if val.is_long() {
         client.reply_with_long(val.get_long());
     } else if val.is_number() {
         client.reply_with_double(val.get_number());
     } else if val.is_string() {
         client.reply_with_bulk_string(val.to_utf8(isolate).unwrap().as_str());
+    } else if let Some(data) = get_buffer_data(isolate, ctx_scope, &val) {
+        client.reply_with_bulk_buffer(&data);
     } else if val.is_array() {
         let arr = val.as_array();
         client.reply_with_array(arr.len());
@@ -53,6 +60,24 @@ fn send_reply(
             let val = arr.get(ctx_scope, i);
             send_reply(isolate, ctx_scope, client, val);
         }
+    } else if val.is_set() {
+        let arr = val.as_set().as_array();
+        client.reply_with_set(arr.len());
+        for i in 0..arr.len() {
+            let val = arr.get(ctx_scope, i);
+            send_reply(isolate, ctx_scope, client, val);
+        }
+    } else if val.is_map() {
+        // the array contains the keys and values one after the other
+        let arr = val.as_map().as_array();
+        client.reply_with_map(arr.len() / 2);
+        for i in 0..arr.len() {
+            let val = arr.get(ctx_scope, i);
+            send_reply(isolate, ctx_scope, client, val);
+        }
+    } else if val.is_string_object() {
+        // new String(...) is replied as a verbatim string
+        client.reply_with_verbatim_string(val.to_utf8(isolate).unwrap().as_str());
     } else if val.is_object() {
         let res = val.as_object();
         let keys = res.get_property_names(ctx_scope);
@@ -69,9 +94,20 @@ fn send_reply(
 }
 
 impl V8InternalFunction {
+    fn arg_to_js_object(&self, arg: &[u8]) -> Result<V8LocalValue, &'static str> {
+        if self.raw_arguments {
+            return Ok(self.script_ctx.isolate.new_array_buffer(arg).to_value());
+        }
+        match str::from_utf8(arg) {
+            Ok(s) => Ok(self.script_ctx.isolate.new_string(s).to_value()),
+            Err(_) => Err("Can not convert argument to string"),
+        }
+    }
+
     fn call_async(
         &self,
-        command_args: Vec<String>,
+        command_args: Vec<Vec<u8>>,
+        keys: Option<Vec<Vec<u8>>>,
         bg_client: Box<dyn ReplyCtxInterface>,
         redis_background_client: Box<dyn BackgroundRunFunctionCtxInterface>,
     ) -> FunctionCallResult {
@@ -86,8 +122,32 @@ impl V8InternalFunction {
             let args = {
                 let mut args = Vec::new();
                 args.push(r_client.to_value());
+                if let Some(keys) = keys.as_ref() {
+                    let mut v8_keys = Vec::new();
+                    for k in keys.iter() {
+                        match self.arg_to_js_object(k) {
+                            Ok(k) => v8_keys.push(k),
+                            Err(e) => {
+                                bg_client.reply_with_error(e);
+                                return FunctionCallResult::Done;
+                            }
+                        }
+                    }
+                    args.push(
+                        self.script_ctx
+                            .isolate
+                            .new_array(&v8_keys.iter().collect::<Vec<&V8LocalValue>>())
+                            .to_value(),
+                    );
+                }
                 for arg in command_args.iter() {
-                    args.push(self.script_ctx.isolate.new_string(arg).to_value());
+                    match self.arg_to_js_object(arg) {
+                        Ok(arg) => args.push(arg),
+                        Err(e) => {
+                            bg_client.reply_with_error(e);
+                            return FunctionCallResult::Done;
+                        }
+                    }
                 }
                 Some(args)
             };
@@ -120,36 +180,36 @@ impl V8InternalFunction {
                         if res.state() == V8PromiseState::Fulfilled {
                             send_reply(&self.script_ctx.isolate, &ctx_scope, bg_client.as_ref(), r);
                         } else {
-                            let r = r.to_utf8(&self.script_ctx.isolate).unwrap();
-                            bg_client.reply_with_error(r.as_str());
+                            let error =
+                                get_error_from_value(&self.script_ctx.isolate, &ctx_scope, &r);
+                            bg_client.reply_with_gears_error(&error);
                         }
                     } else {
                         let bg_execution_ctx = BackgroundClientHolder { c: Some(bg_client) };
                         let execution_ctx_resolve = Arc::new(RefCell::new(bg_execution_ctx));
                         let execution_ctx_reject = Arc::clone(&execution_ctx_resolve);
                         let resolve =
-                            ctx_scope.new_native_function(move |args, isolate, _context| {
+                            ctx_scope.new_native_function(move |args, isolate, context| {
                                 let reply = args.get(0);
-                                let reply = reply.to_utf8(isolate).unwrap();
                                 let mut execution_ctx = execution_ctx_resolve.borrow_mut();
-                                execution_ctx
-                                    .c
-                                    .as_ref()
-                                    .unwrap()
-                                    .reply_with_bulk_string(reply.as_str());
+                                send_reply(
+                                    isolate,
+                                    context,
+                                    execution_ctx.c.as_ref().unwrap().as_ref(),
+                                    reply,
+                                );
                                 execution_ctx.unblock();
                                 None
                             });
                         let reject =
-                            ctx_scope.new_native_function(move |args, isolate, _ctx_scope| {
-                                let reply = args.get(0);
-                                let reply = reply.to_utf8(isolate).unwrap();
+                            ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                let error = get_error_from_value(isolate, ctx_scope, &args.get(0));
                                 let mut execution_ctx = execution_ctx_reject.borrow_mut();
                                 execution_ctx
                                     .c
                                     .as_ref()
                                     .unwrap()
-                                    .reply_with_error(reply.as_str());
+                                    .reply_with_gears_error(&error);
                                 execution_ctx.unblock();
                                 None
                             });
@@ -161,8 +221,8 @@ impl V8InternalFunction {
                 }
             }
             None => {
-                let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                bg_client.reply_with_error(&error_msg);
+                let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                bg_client.reply_with_gears_error(&error);
             }
         }
         FunctionCallResult::Done
@@ -178,15 +238,33 @@ impl V8InternalFunction {
             let args = {
                 let mut args = Vec::new();
                 args.push(self.persisted_client.as_local(&self.script_ctx.isolate));
+                if let Some(keys) = run_ctx.get_keys() {
+                    let mut v8_keys = Vec::new();
+                    for k in keys {
+                        match self.arg_to_js_object(k) {
+                            Ok(k) => v8_keys.push(k),
+                            Err(e) => {
+                                run_ctx.reply_with_error(e);
+                                return FunctionCallResult::Done;
+                            }
+                        }
+                    }
+                    args.push(
+                        self.script_ctx
+                            .isolate
+                            .new_array(&v8_keys.iter().collect::<Vec<&V8LocalValue>>())
+                            .to_value(),
+                    );
+                }
                 while let Some(a) = run_ctx.next_arg() {
-                    let arg = match str::from_utf8(a) {
-                        Ok(s) => s,
-                        Err(_) => {
-                            run_ctx.reply_with_error("Can not convert argument to string");
+                    let arg = match self.arg_to_js_object(a) {
+                        Ok(arg) => arg,
+                        Err(e) => {
+                            run_ctx.reply_with_error(e);
                             return FunctionCallResult::Done;
                         }
                     };
-                    args.push(self.script_ctx.isolate.new_string(arg).to_value());
+                    args.push(arg);
                 }
                 Some(args)
             };
@@ -230,8 +308,9 @@ impl V8InternalFunction {
                                 r,
                             );
                         } else {
-                            let r = r.to_utf8(&self.script_ctx.isolate).unwrap();
-                            run_ctx.reply_with_error(r.as_str());
+                            let error =
+                                get_error_from_value(&self.script_ctx.isolate, &ctx_scope, &r);
+                            run_ctx.reply_with_gears_error(&error);
                         }
                     } else {
                         let bg_execution_ctx = BackgroundClientHolder {
@@ -240,28 +319,27 @@ impl V8InternalFunction {
                         let execution_ctx_resolve = Arc::new(RefCell::new(bg_execution_ctx));
                         let execution_ctx_reject = Arc::clone(&execution_ctx_resolve);
                         let resolve =
-                            ctx_scope.new_native_function(move |args, isolate, _context| {
+                            ctx_scope.new_native_function(move |args, isolate, context| {
                                 let reply = args.get(0);
-                                let reply = reply.to_utf8(isolate).unwrap();
                                 let mut execution_ctx = execution_ctx_resolve.borrow_mut();
-                                execution_ctx
-                                    .c
-                                    .as_ref()
-                                    .unwrap()
-                                    .reply_with_bulk_string(reply.as_str());
+                                send_reply(
+                                    isolate,
+                                    context,
+                                    execution_ctx.c.as_ref().unwrap().as_ref(),
+                                    reply,
+                                );
                                 execution_ctx.unblock();
                                 None
                             });
                         let reject =
-                            ctx_scope.new_native_function(move |args, isolate, _ctx_scope| {
-                                let reply = args.get(0);
-                                let reply = reply.to_utf8(isolate).unwrap();
+                            ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                let error = get_error_from_value(isolate, ctx_scope, &args.get(0));
                                 let mut execution_ctx = execution_ctx_reject.borrow_mut();
                                 execution_ctx
                                     .c
                                     .as_ref()
                                     .unwrap()
-                                    .reply_with_error(reply.as_str());
+                                    .reply_with_gears_error(&error);
                                 execution_ctx.unblock();
                                 None
                             });
@@ -273,8 +351,8 @@ impl V8InternalFunction {
                 }
             }
             None => {
-                let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                run_ctx.reply_with_error(&error_msg);
+                let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                run_ctx.reply_with_gears_error(&error);
             }
         }
         FunctionCallResult::Done
@@ -294,12 +372,14 @@ impl V8Function {
         persisted_client: V8PersistValue,
         client: &Arc<RefCell<RedisClient>>,
         is_async: bool,
+        raw_arguments: bool,
     ) -> V8Function {
         V8Function {
             inner_function: Arc::new(V8InternalFunction {
                 script_ctx: Arc::clone(script_ctx),
                 persisted_function: persisted_function,
                 persisted_client: persisted_client,
+                raw_arguments: raw_arguments,
             }),
             client: Arc::clone(client),
             is_async: is_async,
@@ -312,16 +392,12 @@ impl FunctionCtxInterface for V8Function {
         if self.is_async {
             let inner_function = Arc::clone(&self.inner_function);
             // if we are going to the background we must consume all the arguments
+            let keys = run_ctx
+                .get_keys()
+                .map(|keys| keys.into_iter().map(|k| k.to_vec()).collect::<Vec<_>>());
             let mut args = Vec::new();
             while let Some(a) = run_ctx.next_arg() {
-                let arg = match str::from_utf8(a) {
-                    Ok(s) => s,
-                    Err(_) => {
-                        run_ctx.reply_with_error("Can not convert argument to string");
-                        return FunctionCallResult::Done;
-                    }
-                };
-                args.push(arg.to_string());
+                args.push(a.to_vec());
             }
             let bg_client = run_ctx.get_background_client();
             let bg_redis_client = run_ctx.get_redis_client().get_background_redis_client();
@@ -329,7 +405,7 @@ impl FunctionCtxInterface for V8Function {
                 .script_ctx
                 .compiled_library_api
                 .run_on_background(Box::new(move || {
-                    inner_function.call_async(args, bg_client, bg_redis_client);
+                    inner_function.call_async(args, keys, bg_client, bg_redis_client);
                 }));
             FunctionCallResult::Done
         } else {
@@ -1,7 +1,8 @@
 use redisgears_plugin_api::redisgears_plugin_api::{
     load_library_ctx::LoadLibraryCtxInterface, load_library_ctx::RegisteredKeys,
+    load_library_ctx::FUNCTION_FLAG_RAW_ARGUMENTS,
     run_function_ctx::BackgroundRunFunctionCtxInterface, run_function_ctx::RedisClientCtxInterface,
-    CallResult, GearsApiError,
+    CallResult,
 };
 
 use v8_rs::v8::{
@@ -9,25 +10,55 @@ use v8_rs::v8::{
     v8_value::V8LocalValue, v8_version,
 };
 
-use crate::get_function_flags;
 use crate::v8_backend::log;
 use crate::v8_function_ctx::V8Function;
 use crate::v8_notifications_ctx::V8NotificationsCtx;
 use crate::v8_script_ctx::V8ScriptCtx;
 use crate::v8_stream_ctx::V8StreamCtx;
+use crate::{
+    get_buffer_data, get_function_flags, get_stream_consumer_options, get_stream_consumer_streams,
+};
 
 use std::cell::RefCell;
 use std::str;
 use std::sync::Arc;
 
+fn call_results_to_js_objects(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    res: Vec<CallResult>,
+    decode_responses: bool,
+) -> Option<Vec<V8LocalValue>> {
+    res.into_iter()
+        .map(|v| call_result_to_js_object(isolate, ctx_scope, v, decode_responses))
+        .collect::<Option<Vec<V8LocalValue>>>()
+}
+
 pub(crate) fn call_result_to_js_object(
     isolate: &V8Isolate,
     ctx_scope: &V8ContextScope,
     res: CallResult,
+    decode_responses: bool,
 ) -> Option<V8LocalValue> {
     match res {
         CallResult::SimpleStr(s) => Some(isolate.new_string(&s).to_value()),
-        CallResult::BulkStr(s) => Some(isolate.new_string(&s).to_value()),
+        CallResult::BulkStr(s) => {
+            if decode_responses {
+                Some(isolate.new_string(&s).to_value())
+            } else {
+                Some(isolate.new_array_buffer(s.as_bytes()).to_value())
+            }
+        }
+        CallResult::StringBuffer(s) => {
+            if decode_responses {
+                // keep the reply type stable, binary replies are only given by call_raw
+                isolate.raise_exception_str(
+                    "Reply is not a valid UTF8 string, use 'call_raw' to get it as an ArrayBuffer",
+                );
+                return None;
+            }
+            Some(isolate.new_array_buffer(&s).to_value())
+        }
         CallResult::Error(e) => {
             isolate.raise_exception_str(&e);
             None
@@ -35,29 +66,38 @@ pub(crate) fn call_result_to_js_object(
         CallResult::Long(l) => Some(isolate.new_long(l)),
         CallResult::Double(d) => Some(isolate.new_double(d)),
         CallResult::Array(a) => {
-            let mut has_error = false;
-            let vals = a
-                .into_iter()
-                .map(|v| {
-                    let res = call_result_to_js_object(isolate, ctx_scope, v);
-                    if res.is_none() {
-                        has_error = true;
-                    }
-                    res
-                })
-                .collect::<Vec<Option<V8LocalValue>>>();
-            if has_error {
-                return None;
-            }
-
-            let array = isolate.new_array(
-                &vals
-                    .iter()
-                    .map(|v| v.as_ref().unwrap())
-                    .collect::<Vec<&V8LocalValue>>(),
-            );
+            let vals = call_results_to_js_objects(isolate, ctx_scope, a, decode_responses)?;
+            let array = isolate.new_array(&vals.iter().collect::<Vec<&V8LocalValue>>());
             Some(array.to_value())
         }
+        CallResult::Map(m) => {
+            let map = isolate.new_map();
+            for (k, v) in m {
+                let k = call_result_to_js_object(isolate, ctx_scope, k, decode_responses)?;
+                let v = call_result_to_js_object(isolate, ctx_scope, v, decode_responses)?;
+                map.set(ctx_scope, &k, &v);
+            }
+            Some(map.to_value())
+        }
+        CallResult::Set(s) => {
+            let set = isolate.new_set();
+            for v in call_results_to_js_objects(isolate, ctx_scope, s, decode_responses)? {
+                set.add(ctx_scope, &v);
+            }
+            Some(set.to_value())
+        }
+        CallResult::Bool(b) => Some(isolate.new_bool(b)),
+        CallResult::BigNumber(s) => {
+            let big_int = ctx_scope
+                .get_globals()
+                .get(ctx_scope, &isolate.new_string("BigInt").to_value());
+            big_int.call(ctx_scope, Some(&[&isolate.new_string(&s).to_value()]))
+        }
+        CallResult::VerbatimString(s) => Some(isolate.new_string(&s).to_value()),
+        // attributes are auxiliary data, only the reply itself is given to JS
+        CallResult::Attribute(_, data) => {
+            call_result_to_js_object(isolate, ctx_scope, *data, decode_responses)
+        }
         CallResult::Null => None,
     }
 }
@@ -117,7 +157,7 @@ pub(crate) fn get_backgrounnd_client(
                         Err(err) => {
                             isolate.raise_exception_str(&format!(
                                 "Can not lock Redis, {}",
-                                err.get_msg()
+                                err.get_error_string()
                             ));
                             return None;
                         }
@@ -151,21 +191,26 @@ pub(crate) fn get_backgrounnd_client(
     bg_client
 }
 
-pub(crate) fn get_redis_client(
+fn add_call_function(
     script_ctx: &Arc<V8ScriptCtx>,
     ctx_scope: &V8ContextScope,
+    client: &V8LocalObject,
     redis_client: &Arc<RefCell<RedisClient>>,
-) -> V8LocalObject {
-    let client = script_ctx.isolate.new_object();
-
+    function_name: &'static str,
+    decode_responses: bool,
+    resp3: bool,
+) {
     let redis_client_ref = Arc::clone(redis_client);
     client.set(
         ctx_scope,
-        &script_ctx.isolate.new_string("call").to_value(),
+        &script_ctx.isolate.new_string(function_name).to_value(),
         &ctx_scope
             .new_native_function(move |args, isolate, ctx_scope| {
                 if args.len() < 1 {
-                    isolate.raise_exception_str("Wrong number of arguments to 'call' function");
+                    isolate.raise_exception_str(&format!(
+                        "Wrong number of arguments to '{}' function",
+                        function_name
+                    ));
                     return None;
                 }
 
@@ -183,28 +228,71 @@ pub(crate) fn get_redis_client(
 
                 let command_utf8 = command.to_utf8(isolate).unwrap();
 
-                let mut commands_args_str = Vec::new();
+                let mut commands_args = Vec::new();
                 for i in 1..args.len() {
-                    commands_args_str.push(args.get(i).to_utf8(isolate).unwrap());
+                    let arg = args.get(i);
+                    if let Some(data) = get_buffer_data(isolate, ctx_scope, &arg) {
+                        commands_args.push(data);
+                    } else {
+                        commands_args
+                            .push(arg.to_utf8(isolate).unwrap().as_str().as_bytes().to_vec());
+                    }
                 }
 
-                let command_args_rust_str = commands_args_str
+                let command_args_rust = commands_args
                     .iter()
-                    .map(|v| v.as_str())
-                    .collect::<Vec<&str>>();
+                    .map(|v| v.as_slice())
+                    .collect::<Vec<&[u8]>>();
 
                 let res = match redis_client_ref.borrow().client.as_ref() {
-                    Some(c) => c.call(command_utf8.as_str(), &command_args_rust_str),
+                    Some(c) if resp3 => c.call_resp3(command_utf8.as_str(), &command_args_rust),
+                    Some(c) => c.call(command_utf8.as_str(), &command_args_rust),
                     None => {
                         isolate.raise_exception_str("Used on invalid client");
                         return None;
                     }
                 };
 
-                call_result_to_js_object(isolate, ctx_scope, res)
+                call_result_to_js_object(isolate, ctx_scope, res, decode_responses)
             })
             .to_value(),
     );
+}
+
+pub(crate) fn get_redis_client(
+    script_ctx: &Arc<V8ScriptCtx>,
+    ctx_scope: &V8ContextScope,
+    redis_client: &Arc<RefCell<RedisClient>>,
+) -> V8LocalObject {
+    let client = script_ctx.isolate.new_object();
+
+    add_call_function(
+        script_ctx,
+        ctx_scope,
+        &client,
+        redis_client,
+        "call",
+        true,
+        false,
+    );
+    add_call_function(
+        script_ctx,
+        ctx_scope,
+        &client,
+        redis_client,
+        "call_raw",
+        false,
+        false,
+    );
+    add_call_function(
+        script_ctx,
+        ctx_scope,
+        &client,
+        redis_client,
+        "call_resp3",
+        true,
+        true,
+    );
 
     let script_ctx_ref = Arc::downgrade(script_ctx);
     let redis_client_ref = Arc::clone(redis_client);
@@ -284,6 +372,46 @@ pub(crate) fn get_redis_client(
     client
 }
 
+fn get_redis_object(isolate: &V8Isolate, ctx_scope: &V8ContextScope) -> V8LocalObject {
+    ctx_scope
+        .get_globals()
+        .get(ctx_scope, &isolate.new_string("redis").to_value())
+        .as_object()
+}
+
+pub(crate) fn get_library_config(isolate: &V8Isolate, ctx_scope: &V8ContextScope) -> V8LocalValue {
+    get_redis_object(isolate, ctx_scope).get(ctx_scope, &isolate.new_string("config").to_value())
+}
+
+pub(crate) fn put_library_config(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    config: &V8LocalValue,
+) {
+    get_redis_object(isolate, ctx_scope).set(
+        ctx_scope,
+        &isolate.new_string("config").to_value(),
+        config,
+    );
+}
+
+// parses the library configuration json and sets it as `redis.config`
+pub(crate) fn set_library_config(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    config: &str,
+) -> Option<V8LocalValue> {
+    let json = ctx_scope
+        .get_globals()
+        .get(ctx_scope, &isolate.new_string("JSON").to_value())
+        .as_object();
+    let config = json
+        .get(ctx_scope, &isolate.new_string("parse").to_value())
+        .call(ctx_scope, Some(&[&isolate.new_string(config).to_value()]))?;
+    put_library_config(isolate, ctx_scope, &config);
+    Some(config)
+}
+
 pub(crate) fn initialize_globals(
     script_ctx: &Arc<V8ScriptCtx>,
     globals: &V8LocalObject,
@@ -295,7 +423,7 @@ pub(crate) fn initialize_globals(
     redis.set(ctx_scope,
         &script_ctx.isolate.new_string("register_stream_consumer").to_value(), 
         &ctx_scope.new_native_function(move|args, isolate, curr_ctx_scope| {
-            if args.len() != 5 {
+            if args.len() != 5 && args.len() != 6 {
                 isolate.raise_exception_str("Wrong number of arguments to 'register_stream_consumer' function");
                 return None;
             }
@@ -307,12 +435,13 @@ pub(crate) fn initialize_globals(
             }
             let registration_name_utf8 = consumer_name.to_utf8(isolate).unwrap();
 
-            let prefix = args.get(1);
-            if !prefix.is_string() {
-                isolate.raise_exception_str("Second argument to 'register_stream_consumer' must be a string representing the prefix");
-                return None;
-            }
-            let prefix_utf8 = prefix.to_utf8(isolate).unwrap();
+            let streams = match get_stream_consumer_streams(isolate, curr_ctx_scope, &args.get(1)) {
+                Ok(streams) => streams,
+                Err(e) => {
+                    isolate.raise_exception_str(&format!("Second argument to 'register_stream_consumer' {}", e));
+                    return None;
+                }
+            };
 
             let window = args.get(2);
             if !window.is_long() {
@@ -335,6 +464,25 @@ pub(crate) fn initialize_globals(
             }
             let persisted_function = function_callback.persist(isolate);
 
+            let options = if args.len() == 6 {
+                let options = args.get(5);
+                if !options.is_object() {
+                    isolate.raise_exception_str("Sixth argument to 'register_stream_consumer' must be an object representing the consumer options");
+                    return None;
+                }
+                Some(options.as_object())
+            } else {
+                None
+            };
+            let options = match get_stream_consumer_options(isolate, curr_ctx_scope, options.as_ref()) {
+                Ok(options) => options,
+                Err(e) => {
+                    isolate.raise_exception_str(&format!("Failed parsing stream consumer options, {}", e));
+                    return None;
+                }
+            };
+            let batched = options.batch_size.is_some();
+
             let load_ctx = curr_ctx_scope.get_private_data_mut::<&mut dyn LoadLibraryCtxInterface>(0);
             if load_ctx.is_none() {
                 isolate.raise_exception_str("Called 'register_function' out of context");
@@ -349,12 +497,10 @@ pub(crate) fn initialize_globals(
                     return None;
                 }
             };
-            let v8_stream_ctx = V8StreamCtx::new(persisted_function, &script_ctx_ref, if function_callback.is_async_function() {true} else {false});
-            let res = load_ctx.register_stream_consumer(registration_name_utf8.as_str(), prefix_utf8.as_str(), Box::new(v8_stream_ctx), window as usize, trim);
+            let v8_stream_ctx = V8StreamCtx::new(persisted_function, &script_ctx_ref, if function_callback.is_async_function() {true} else {false}, batched);
+            let res = load_ctx.register_stream_consumer(registration_name_utf8.as_str(), streams, Box::new(v8_stream_ctx), window as usize, trim, options);
             if let Err(err) = res {
-                match err {
-                    GearsApiError::Msg(s) => isolate.raise_exception_str(&s),
-                }
+                isolate.raise_exception_str(err.get_msg());
                 return None;
             }
             None
@@ -407,9 +553,7 @@ pub(crate) fn initialize_globals(
             let v8_notification_ctx = V8NotificationsCtx::new(persisted_function, &script_ctx_ref, if function_callback.is_async_function() {true} else {false});
             let res = load_ctx.register_key_space_notification_consumer(registration_name_utf8.as_str(), RegisteredKeys::Prefix(prefix_utf8.as_str()), Box::new(v8_notification_ctx));
             if let Err(err) = res {
-                match err {
-                    GearsApiError::Msg(s) => isolate.raise_exception_str(&s),
-                }
+                isolate.raise_exception_str(err.get_msg());
                 return None;
             }
             None
@@ -480,18 +624,62 @@ pub(crate) fn initialize_globals(
                 redis_client.to_value().persist(isolate),
                 &c,
                 function_callback.is_async_function(),
+                function_flags & FUNCTION_FLAG_RAW_ARGUMENTS != 0,
             );
 
             let res = load_ctx.register_function(function_name_utf8.as_str(), Box::new(f), function_flags);
             if let Err(err) = res {
-                match err {
-                    GearsApiError::Msg(s) => isolate.raise_exception_str(&s),
-                }
+                isolate.raise_exception_str(err.get_msg());
                 return None;
             }
             None
     }).to_value());
 
+    let script_ctx_ref = Arc::downgrade(script_ctx);
+    redis.set(
+        ctx_scope,
+        &script_ctx
+            .isolate
+            .new_string("on_config_changed")
+            .to_value(),
+        &ctx_scope
+            .new_native_function(move |args, isolate, curr_ctx_scope| {
+                if args.len() != 1 {
+                    isolate.raise_exception_str(
+                        "Wrong number of arguments to 'on_config_changed' function",
+                    );
+                    return None;
+                }
+
+                let function_callback = args.get(0);
+                if !function_callback.is_function() || function_callback.is_async_function() {
+                    isolate.raise_exception_str(
+                        "First argument to 'on_config_changed' must be a synchronous function",
+                    );
+                    return None;
+                }
+
+                let load_ctx =
+                    curr_ctx_scope.get_private_data_mut::<&mut dyn LoadLibraryCtxInterface>(0);
+                if load_ctx.is_none() {
+                    isolate.raise_exception_str("Called 'on_config_changed' out of context");
+                    return None;
+                }
+
+                let script_ctx_ref = match script_ctx_ref.upgrade() {
+                    Some(s) => s,
+                    None => {
+                        isolate.raise_exception_str("Use of uninitialize script context");
+                        return None;
+                    }
+                };
+                *script_ctx_ref.config_changed_callback.ref_cell.borrow_mut() =
+                    Some(function_callback.persist(isolate));
+                None
+            })
+            .to_value(),
+    );
+
     redis.set(
         ctx_scope,
         &script_ctx.isolate.new_string("v8_version").to_value(),
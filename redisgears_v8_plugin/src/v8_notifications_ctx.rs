@@ -2,14 +2,14 @@ use redisgears_plugin_api::redisgears_plugin_api::{
     keys_notifications_consumer_ctx::KeysNotificationsConsumerCtxInterface,
     keys_notifications_consumer_ctx::NotificationFiredDataInterface,
     keys_notifications_consumer_ctx::NotificationRunCtxInterface,
-    run_function_ctx::BackgroundRunFunctionCtxInterface,
+    run_function_ctx::BackgroundRunFunctionCtxInterface, GearsApiError,
 };
 
 use v8_rs::v8::{v8_promise::V8PromiseState, v8_value::V8PersistValue};
 
-use crate::get_exception_msg;
 use crate::v8_native_functions::{get_backgrounnd_client, get_redis_client, RedisClient};
 use crate::v8_script_ctx::V8ScriptCtx;
+use crate::{get_error_from_value, get_exception_error};
 
 use std::any::Any;
 use std::cell::RefCell;
@@ -23,7 +23,7 @@ struct V8NotificationCtxData {
 impl NotificationFiredDataInterface for V8NotificationCtxData {}
 
 struct V8AckCallbackInternal {
-    ack_callback: Box<dyn FnOnce(Result<(), String>) + Send + Sync>,
+    ack_callback: Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>,
     locker: Box<dyn BackgroundRunFunctionCtxInterface>,
 }
 
@@ -41,7 +41,7 @@ impl V8NotificationsCtxInternal {
         &self,
         notification_ctx: Box<dyn NotificationRunCtxInterface>,
         data: Box<V8NotificationCtxData>,
-        ack_callback: Box<dyn FnOnce(Result<(), String>) + Send + Sync>,
+        ack_callback: Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>,
     ) {
         let res = {
             let _isolate_scope = self.script_ctx.isolate.enter();
@@ -91,9 +91,11 @@ impl V8NotificationsCtxInternal {
                     if res.is_promise() {
                         let res = res.as_promise();
                         if res.state() == V8PromiseState::Rejected {
-                            let error_utf8 =
-                                res.get_result().to_utf8(&self.script_ctx.isolate).unwrap();
-                            Some(Err(error_utf8.as_str().to_string()))
+                            Some(Err(get_error_from_value(
+                                &self.script_ctx.isolate,
+                                &ctx_scope,
+                                &res.get_result(),
+                            )))
                         } else if res.state() == V8PromiseState::Fulfilled {
                             Some(Ok(()))
                         } else {
@@ -116,10 +118,9 @@ impl V8NotificationsCtxInternal {
                                     None
                                 });
                             let reject =
-                                ctx_scope.new_native_function(move |args, isolate, _ctx_scope| {
-                                    let res = args.get(0);
-                                    let res = res.to_utf8(isolate).unwrap();
-                                    let res = res.as_str().to_string();
+                                ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                    let res =
+                                        get_error_from_value(isolate, ctx_scope, &args.get(0));
                                     let _unlocker = isolate.new_unlocker();
                                     if let Some(ack) =
                                         ack_callback_reject.borrow_mut().internal.take()
@@ -137,8 +138,8 @@ impl V8NotificationsCtxInternal {
                     }
                 }
                 None => {
-                    let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                    Some(Err(error_msg))
+                    let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                    Some(Err(error))
                 }
             }
         };
@@ -153,7 +154,7 @@ impl V8NotificationsCtxInternal {
         background_client: Box<dyn BackgroundRunFunctionCtxInterface>,
         locker: Box<dyn BackgroundRunFunctionCtxInterface>,
         data: Box<V8NotificationCtxData>,
-        ack_callback: Box<dyn FnOnce(Result<(), String>) + Send + Sync>,
+        ack_callback: Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>,
     ) {
         let res = {
             let _isolate_scope = self.script_ctx.isolate.enter();
@@ -191,9 +192,11 @@ impl V8NotificationsCtxInternal {
                     if res.is_promise() {
                         let res = res.as_promise();
                         if res.state() == V8PromiseState::Rejected {
-                            let error_utf8 =
-                                res.get_result().to_utf8(&self.script_ctx.isolate).unwrap();
-                            Some(Err(error_utf8.as_str().to_string()))
+                            Some(Err(get_error_from_value(
+                                &self.script_ctx.isolate,
+                                &ctx_scope,
+                                &res.get_result(),
+                            )))
                         } else if res.state() == V8PromiseState::Fulfilled {
                             Some(Ok(()))
                         } else {
@@ -216,10 +219,9 @@ impl V8NotificationsCtxInternal {
                                     None
                                 });
                             let reject =
-                                ctx_scope.new_native_function(move |args, isolate, _ctx_scope| {
-                                    let res = args.get(0);
-                                    let res = res.to_utf8(isolate).unwrap();
-                                    let res = res.as_str().to_string();
+                                ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                    let res =
+                                        get_error_from_value(isolate, ctx_scope, &args.get(0));
                                     let _unlocker = isolate.new_unlocker();
                                     if let Some(ack) =
                                         ack_callback_reject.borrow_mut().internal.take()
@@ -237,8 +239,8 @@ impl V8NotificationsCtxInternal {
                     }
                 }
                 None => {
-                    let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                    Some(Err(error_msg))
+                    let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                    Some(Err(error))
                 }
             }
         };
@@ -288,7 +290,7 @@ impl KeysNotificationsConsumerCtxInterface for V8NotificationsCtx {
         &self,
         notificaion_data: Option<Box<dyn Any>>,
         notification_ctx: Box<dyn NotificationRunCtxInterface>,
-        ack_callback: Box<dyn FnOnce(Result<(), String>) + Send + Sync>,
+        ack_callback: Box<dyn FnOnce(Result<(), GearsApiError>) + Send + Sync>,
     ) {
         let notificaion_data = notificaion_data
             .unwrap()
@@ -1,21 +1,23 @@
 use redisgears_plugin_api::redisgears_plugin_api::{
     backend_ctx::CompiledLibraryInterface, load_library_ctx::LibraryCtxInterface,
-    load_library_ctx::LoadLibraryCtxInterface, GearsApiError,
+    load_library_ctx::LoadLibraryCtxInterface, GearsApiError, GearsApiErrorKind,
 };
 
 use v8_rs::v8::{
-    isolate::V8Isolate, v8_context::V8Context, v8_promise::V8PromiseState,
-    v8_script::V8PersistedScript,
+    isolate::V8Isolate, v8_context::V8Context, v8_module::V8PersistedModule,
+    v8_promise::V8PromiseState, v8_script::V8PersistedScript, v8_value::V8PersistValue,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::RefCellWrapper;
 use std::cell::RefCell;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::SystemTime;
 
-use crate::get_exception_msg;
+use crate::v8_native_functions::{get_library_config, put_library_config, set_library_config};
+use crate::{get_error_from_value, get_exception_error};
 
 pub(crate) enum GilState {
     Lock,
@@ -71,31 +73,52 @@ impl GilStateCtx {
     }
 }
 
+// The compiled library code, a module is only used for libraries that import modules.
+pub(crate) enum V8LibraryCode {
+    Script(V8PersistedScript),
+    Module(V8PersistedModule),
+}
+
 pub(crate) struct V8ScriptCtx {
-    pub(crate) script: V8PersistedScript,
+    pub(crate) code: V8LibraryCode,
+    // registered by the library with redis.on_config_changed
+    pub(crate) config_changed_callback: RefCellWrapper<Option<V8PersistValue>>,
     pub(crate) ctx: V8Context,
     pub(crate) isolate: V8Isolate,
     pub(crate) compiled_library_api: Box<dyn CompiledLibraryInterface + Send + Sync>,
     pub(crate) is_running: AtomicBool,
     pub(crate) lock_state: RefCellWrapper<GilStateCtx>,
+    termination_reason: RefCellWrapper<Option<GearsApiErrorKind>>,
+    // the isolate might be running on another thread when the heap statistics
+    // are requested, so they are taken after each run, while the isolate is entered.
+    used_heap_size: AtomicUsize,
+    total_heap_size: AtomicUsize,
 }
 
 impl V8ScriptCtx {
     pub(crate) fn new(
         isolate: V8Isolate,
         ctx: V8Context,
-        script: V8PersistedScript,
+        code: V8LibraryCode,
         compiled_library_api: Box<dyn CompiledLibraryInterface + Send + Sync>,
     ) -> V8ScriptCtx {
         V8ScriptCtx {
             isolate: isolate,
             ctx: ctx,
-            script: script,
+            code: code,
+            config_changed_callback: RefCellWrapper {
+                ref_cell: RefCell::new(None),
+            },
             compiled_library_api: compiled_library_api,
             is_running: AtomicBool::new(false),
             lock_state: RefCellWrapper {
                 ref_cell: RefCell::new(GilStateCtx::new()),
             },
+            termination_reason: RefCellWrapper {
+                ref_cell: RefCell::new(None),
+            },
+            used_heap_size: AtomicUsize::new(0),
+            total_heap_size: AtomicUsize::new(0),
         }
     }
 
@@ -105,6 +128,10 @@ impl V8ScriptCtx {
 
     pub(crate) fn after_run(&self) {
         self.is_running.store(false, Ordering::Relaxed);
+        self.used_heap_size
+            .store(self.isolate.used_heap_size(), Ordering::Relaxed);
+        self.total_heap_size
+            .store(self.isolate.total_heap_size(), Ordering::Relaxed);
     }
 
     pub(crate) fn after_lock_gil(&self) {
@@ -130,6 +157,15 @@ impl V8ScriptCtx {
     pub(crate) fn is_lock_timedout(&self) -> bool {
         self.lock_state.ref_cell.borrow().is_lock_timedout()
     }
+
+    // must be set before terminating the execution so we will know how to report the error
+    pub(crate) fn set_termination_reason(&self, reason: GearsApiErrorKind) {
+        *self.termination_reason.ref_cell.borrow_mut() = Some(reason);
+    }
+
+    pub(crate) fn take_termination_reason(&self) -> Option<GearsApiErrorKind> {
+        self.termination_reason.ref_cell.borrow_mut().take()
+    }
 }
 
 pub(crate) struct V8LibraryCtx {
@@ -146,7 +182,16 @@ impl LibraryCtxInterface for V8LibraryCtx {
         let ctx_scope = self.script_ctx.ctx.enter();
         let trycatch = self.script_ctx.isolate.new_try_catch();
 
-        let script = self.script_ctx.script.to_local(&self.script_ctx.isolate);
+        let config = set_library_config(
+            &self.script_ctx.isolate,
+            &ctx_scope,
+            &load_library_ctx.get_library_config(),
+        );
+        if config.is_none() {
+            let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+            let kind = error.get_kind();
+            return Err(error.wrap(kind, "Failed setting library configuration: "));
+        }
 
         // set private content
         self.script_ctx
@@ -155,7 +200,14 @@ impl LibraryCtxInterface for V8LibraryCtx {
 
         self.script_ctx.before_run();
         self.script_ctx.after_lock_gil();
-        let res = script.run(&ctx_scope);
+        let res = match &self.script_ctx.code {
+            V8LibraryCode::Script(script) => {
+                script.to_local(&self.script_ctx.isolate).run(&ctx_scope)
+            }
+            V8LibraryCode::Module(module) => module
+                .to_local(&self.script_ctx.isolate)
+                .evaluate(&ctx_scope),
+        };
         self.script_ctx.before_release_gil();
         self.script_ctx.after_run();
 
@@ -165,24 +217,75 @@ impl LibraryCtxInterface for V8LibraryCtx {
             .set_private_data::<&mut dyn LoadLibraryCtxInterface>(0, None);
 
         if res.is_none() {
-            let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-            return Err(GearsApiError::Msg(format!(
-                "Failed evaluating module: {}",
-                error_msg
-            )));
+            let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+            let kind = error.get_kind();
+            return Err(error.wrap(kind, "Failed evaluating module: "));
         }
         let res = res.unwrap();
         if res.is_promise() {
             let promise = res.as_promise();
             if promise.state() == V8PromiseState::Rejected {
                 let error = promise.get_result();
-                let error_utf8 = error.to_utf8(&self.script_ctx.isolate).unwrap();
-                return Err(GearsApiError::Msg(format!(
-                    "Failed evaluating module: {}",
-                    error_utf8.as_str()
-                )));
+                let error = get_error_from_value(&self.script_ctx.isolate, &ctx_scope, &error);
+                return Err(error.wrap(GearsApiErrorKind::Exception, "Failed evaluating module: "));
             }
         }
         Ok(())
     }
+
+    fn on_config_changed(&self, config: &str) -> Result<(), GearsApiError> {
+        let _isolate_scope = self.script_ctx.isolate.enter();
+        let _handlers_scope = self.script_ctx.isolate.new_handlers_scope();
+        let ctx_scope = self.script_ctx.ctx.enter();
+        let trycatch = self.script_ctx.isolate.new_try_catch();
+
+        let old_config = get_library_config(&self.script_ctx.isolate, &ctx_scope);
+        let config = match set_library_config(&self.script_ctx.isolate, &ctx_scope, config) {
+            Some(c) => c,
+            None => return Err(get_exception_error(&self.script_ctx, &ctx_scope, trycatch)),
+        };
+
+        let callback = self.script_ctx.config_changed_callback.ref_cell.borrow();
+        let callback = match callback.as_ref() {
+            Some(c) => c.as_local(&self.script_ctx.isolate),
+            None => return Ok(()),
+        };
+
+        self.script_ctx.before_run();
+        self.script_ctx.after_lock_gil();
+        let res = callback.call(&ctx_scope, Some(&[&config]));
+        self.script_ctx.before_release_gil();
+        self.script_ctx.after_run();
+
+        if res.is_none() {
+            // the configuration change is reverted, so should be the library view of it
+            let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+            put_library_config(&self.script_ctx.isolate, &ctx_scope, &old_config);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn get_used_memory(&self) -> usize {
+        self.script_ctx.used_heap_size.load(Ordering::Relaxed)
+    }
+
+    fn get_info(&self) -> Vec<(String, String)> {
+        vec![
+            (
+                "used_heap_size".to_string(),
+                self.script_ctx
+                    .used_heap_size
+                    .load(Ordering::Relaxed)
+                    .to_string(),
+            ),
+            (
+                "total_heap_size".to_string(),
+                self.script_ctx
+                    .total_heap_size
+                    .load(Ordering::Relaxed)
+                    .to_string(),
+            ),
+        ]
+    }
 }
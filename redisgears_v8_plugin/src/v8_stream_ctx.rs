@@ -1,7 +1,11 @@
-use v8_rs::v8::{v8_promise::V8PromiseState, v8_value::V8LocalValue, v8_value::V8PersistValue};
+use v8_rs::v8::{
+    isolate::V8Isolate, v8_context_scope::V8ContextScope, v8_promise::V8PromiseState,
+    v8_value::V8LocalValue, v8_value::V8PersistValue,
+};
 
 use redisgears_plugin_api::redisgears_plugin_api::stream_ctx::{
-    StreamCtxInterface, StreamProcessCtxInterface, StreamRecordAck, StreamRecordInterface,
+    StreamBatchAck, StreamCtxInterface, StreamProcessCtxInterface, StreamRecordAck,
+    StreamRecordInterface,
 };
 
 use redisgears_plugin_api::redisgears_plugin_api::run_function_ctx::BackgroundRunFunctionCtxInterface;
@@ -14,15 +18,16 @@ use std::sync::Arc;
 
 use std::str;
 
-use crate::get_exception_msg;
+use crate::{get_error_from_value, get_exception_error};
 
 struct V8StreamAckCtx {
-    ack: Option<Box<dyn FnOnce(StreamRecordAck) + Send>>,
+    ack: Option<Box<dyn FnOnce(StreamBatchAck) + Send>>,
 }
 
 struct V8StreamCtxInternals {
     persisted_function: V8PersistValue,
     script_ctx: Arc<V8ScriptCtx>,
+    batched: bool,
 }
 
 pub struct V8StreamCtx {
@@ -35,29 +40,59 @@ impl V8StreamCtx {
         persisted_function: V8PersistValue,
         script_ctx: &Arc<V8ScriptCtx>,
         is_async: bool,
+        batched: bool,
     ) -> V8StreamCtx {
         V8StreamCtx {
             internals: Arc::new(V8StreamCtxInternals {
                 persisted_function: persisted_function,
                 script_ctx: Arc::clone(script_ctx),
+                batched: batched,
             }),
             is_async: is_async,
         }
     }
 }
 
+// A batched consumer can return an array with a result per record,
+// true acknowledges the record and any other value is the record error.
+fn get_batch_ack(
+    isolate: &V8Isolate,
+    ctx_scope: &V8ContextScope,
+    batched: bool,
+    res: &V8LocalValue,
+) -> StreamBatchAck {
+    if !batched || !res.is_array() {
+        return StreamBatchAck::Batch(StreamRecordAck::Ack);
+    }
+    let arr = res.as_array();
+    StreamBatchAck::Records(
+        (0..arr.len())
+            .map(|i| {
+                let val = arr.get(ctx_scope, i);
+                if val.is_boolean() && val.get_boolean() {
+                    StreamRecordAck::Ack
+                } else {
+                    StreamRecordAck::Nack(get_error_from_value(isolate, ctx_scope, &val))
+                }
+            })
+            .collect(),
+    )
+}
+
+fn get_record_ack(ack: StreamBatchAck) -> StreamRecordAck {
+    match ack {
+        StreamBatchAck::Batch(ack) => ack,
+        StreamBatchAck::Records(mut acks) => acks.pop().unwrap_or(StreamRecordAck::Ack),
+    }
+}
+
 impl V8StreamCtxInternals {
-    fn process_record_internal_sync(
+    fn record_to_value(
         &self,
+        ctx_scope: &V8ContextScope,
         stream_name: &str,
-        record: Box<dyn StreamRecordInterface>,
-        run_ctx: &dyn StreamProcessCtxInterface,
-    ) -> Option<StreamRecordAck> {
-        let _isolate_scope = self.script_ctx.isolate.enter();
-        let _handlers_scope = self.script_ctx.isolate.new_handlers_scope();
-        let ctx_scope = self.script_ctx.ctx.enter();
-        let trycatch = self.script_ctx.isolate.new_try_catch();
-
+        record: &dyn StreamRecordInterface,
+    ) -> V8LocalValue {
         let id = record.get_id();
         let id_v8_arr = self.script_ctx.isolate.new_array(&[
             &self.script_ctx.isolate.new_long(id.0 as i64),
@@ -94,20 +129,55 @@ impl V8StreamCtxInternals {
 
         let stream_data = self.script_ctx.isolate.new_object();
         stream_data.set(
-            &ctx_scope,
+            ctx_scope,
             &self.script_ctx.isolate.new_string("id").to_value(),
             &id_v8_arr.to_value(),
         );
         stream_data.set(
-            &ctx_scope,
+            ctx_scope,
             &self.script_ctx.isolate.new_string("stream_name").to_value(),
             &stream_name_v8_str.to_value(),
         );
         stream_data.set(
-            &ctx_scope,
+            ctx_scope,
             &self.script_ctx.isolate.new_string("record").to_value(),
             &val_v8_arr.to_value(),
         );
+        stream_data.to_value()
+    }
+
+    // a single record for a none batched consumer, otherwise an array of records
+    fn records_to_value(
+        &self,
+        ctx_scope: &V8ContextScope,
+        stream_name: &str,
+        records: &[Box<dyn StreamRecordInterface + Send>],
+    ) -> V8LocalValue {
+        if !self.batched {
+            return self.record_to_value(ctx_scope, stream_name, records[0].as_ref());
+        }
+        let vals = records
+            .iter()
+            .map(|r| self.record_to_value(ctx_scope, stream_name, r.as_ref()))
+            .collect::<Vec<V8LocalValue>>();
+        self.script_ctx
+            .isolate
+            .new_array(&vals.iter().collect::<Vec<&V8LocalValue>>())
+            .to_value()
+    }
+
+    fn process_records_internal_sync(
+        &self,
+        stream_name: &str,
+        records: Vec<Box<dyn StreamRecordInterface + Send>>,
+        run_ctx: &dyn StreamProcessCtxInterface,
+    ) -> Option<StreamBatchAck> {
+        let _isolate_scope = self.script_ctx.isolate.enter();
+        let _handlers_scope = self.script_ctx.isolate.new_handlers_scope();
+        let ctx_scope = self.script_ctx.ctx.enter();
+        let trycatch = self.script_ctx.isolate.new_try_catch();
+
+        let stream_data = self.records_to_value(&ctx_scope, stream_name, &records);
 
         let c = run_ctx.get_redis_client();
         let mut redis_client = RedisClient::new();
@@ -122,10 +192,7 @@ impl V8StreamCtxInternals {
         let res = self
             .persisted_function
             .as_local(&self.script_ctx.isolate)
-            .call(
-                &ctx_scope,
-                Some(&[&r_client.to_value(), &stream_data.to_value()]),
-            );
+            .call(&ctx_scope, Some(&[&r_client.to_value(), &stream_data]));
         self.script_ctx.before_release_gil();
         self.script_ctx.after_run();
 
@@ -134,21 +201,21 @@ impl V8StreamCtxInternals {
         redis_client.borrow_mut().make_invalid();
 
         Some(match res {
-            Some(_) => StreamRecordAck::Ack,
+            Some(res) => get_batch_ack(&self.script_ctx.isolate, &ctx_scope, self.batched, &res),
             None => {
                 // todo: handle promise
-                let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                StreamRecordAck::Nack(error_msg)
+                let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                StreamBatchAck::Batch(StreamRecordAck::Nack(error))
             }
         })
     }
 
-    fn process_record_internal_async(
+    fn process_records_internal_async(
         &self,
         stream_name: &str,
-        record: Box<dyn StreamRecordInterface>,
+        records: Vec<Box<dyn StreamRecordInterface + Send>>,
         redis_client: Box<dyn BackgroundRunFunctionCtxInterface>,
-        ack_callback: Box<dyn FnOnce(StreamRecordAck) + Send>,
+        ack_callback: Box<dyn FnOnce(StreamBatchAck) + Send>,
     ) {
         let ack_callback = Arc::new(RefCell::new(V8StreamAckCtx {
             ack: Some(ack_callback),
@@ -159,56 +226,7 @@ impl V8StreamCtxInternals {
             let ctx_scope = self.script_ctx.ctx.enter();
             let trycatch = self.script_ctx.isolate.new_try_catch();
 
-            let id = record.get_id();
-            let id_v8_arr = self.script_ctx.isolate.new_array(&[
-                &self.script_ctx.isolate.new_long(id.0 as i64),
-                &self.script_ctx.isolate.new_long(id.1 as i64),
-            ]);
-            let stream_name_v8_str = self.script_ctx.isolate.new_string(stream_name);
-
-            let vals = record
-                .fields()
-                .map(|(f, v)| (str::from_utf8(f), str::from_utf8(v)))
-                .filter(|(f, v)| {
-                    if f.is_err() || v.is_err() {
-                        false
-                    } else {
-                        true
-                    }
-                })
-                .map(|(f, v)| (f.unwrap(), v.unwrap()))
-                .map(|(f, v)| {
-                    self.script_ctx
-                        .isolate
-                        .new_array(&[
-                            &self.script_ctx.isolate.new_string(f).to_value(),
-                            &self.script_ctx.isolate.new_string(v).to_value(),
-                        ])
-                        .to_value()
-                })
-                .collect::<Vec<V8LocalValue>>();
-
-            let val_v8_arr = self
-                .script_ctx
-                .isolate
-                .new_array(&vals.iter().collect::<Vec<&V8LocalValue>>());
-
-            let stream_data = self.script_ctx.isolate.new_object();
-            stream_data.set(
-                &ctx_scope,
-                &self.script_ctx.isolate.new_string("id").to_value(),
-                &id_v8_arr.to_value(),
-            );
-            stream_data.set(
-                &ctx_scope,
-                &self.script_ctx.isolate.new_string("stream_name").to_value(),
-                &stream_name_v8_str.to_value(),
-            );
-            stream_data.set(
-                &ctx_scope,
-                &self.script_ctx.isolate.new_string("record").to_value(),
-                &val_v8_arr.to_value(),
-            );
+            let stream_data = self.records_to_value(&ctx_scope, stream_name, &records);
 
             let r_client = get_backgrounnd_client(&self.script_ctx, &ctx_scope, redis_client);
 
@@ -216,10 +234,7 @@ impl V8StreamCtxInternals {
             let res = self
                 .persisted_function
                 .as_local(&self.script_ctx.isolate)
-                .call(
-                    &ctx_scope,
-                    Some(&[&r_client.to_value(), &stream_data.to_value()]),
-                );
+                .call(&ctx_scope, Some(&[&r_client.to_value(), &stream_data]));
             self.script_ctx.after_run();
 
             match res {
@@ -227,31 +242,41 @@ impl V8StreamCtxInternals {
                     if res.is_promise() {
                         let res = res.as_promise();
                         if res.state() == V8PromiseState::Rejected {
-                            let error_utf8 =
-                                res.get_result().to_utf8(&self.script_ctx.isolate).unwrap();
-                            Some(StreamRecordAck::Nack(error_utf8.as_str().to_string()))
+                            let error = get_error_from_value(
+                                &self.script_ctx.isolate,
+                                &ctx_scope,
+                                &res.get_result(),
+                            );
+                            Some(StreamBatchAck::Batch(StreamRecordAck::Nack(error)))
                         } else if res.state() == V8PromiseState::Fulfilled {
-                            Some(StreamRecordAck::Ack)
+                            Some(get_batch_ack(
+                                &self.script_ctx.isolate,
+                                &ctx_scope,
+                                self.batched,
+                                &res.get_result(),
+                            ))
                         } else {
                             let ack_callback_resolve = Arc::clone(&ack_callback);
                             let ack_callback_reject = Arc::clone(&ack_callback);
+                            let batched = self.batched;
                             let resolve =
-                                ctx_scope.new_native_function(move |_args, isolate, _context| {
+                                ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                    let res =
+                                        get_batch_ack(isolate, ctx_scope, batched, &args.get(0));
                                     let _unlocker = isolate.new_unlocker();
                                     if let Some(ack) = ack_callback_resolve.borrow_mut().ack.take()
                                     {
-                                        ack(StreamRecordAck::Ack);
+                                        ack(res);
                                     }
                                     None
                                 });
                             let reject =
-                                ctx_scope.new_native_function(move |args, isolate, _ctx_scope| {
-                                    let res = args.get(0);
-                                    let res = res.to_utf8(isolate).unwrap();
-                                    let res = res.as_str().to_string();
+                                ctx_scope.new_native_function(move |args, isolate, ctx_scope| {
+                                    let res =
+                                        get_error_from_value(isolate, ctx_scope, &args.get(0));
                                     let _unlocker = isolate.new_unlocker();
                                     if let Some(ack) = ack_callback_reject.borrow_mut().ack.take() {
-                                        ack(StreamRecordAck::Nack(res));
+                                        ack(StreamBatchAck::Batch(StreamRecordAck::Nack(res)));
                                     }
                                     None
                                 });
@@ -259,13 +284,18 @@ impl V8StreamCtxInternals {
                             None
                         }
                     } else {
-                        Some(StreamRecordAck::Ack)
+                        Some(get_batch_ack(
+                            &self.script_ctx.isolate,
+                            &ctx_scope,
+                            self.batched,
+                            &res,
+                        ))
                     }
                 }
                 None => {
                     // todo: hanlde promise
-                    let error_msg = get_exception_msg(&self.script_ctx.isolate, trycatch);
-                    Some(StreamRecordAck::Nack(error_msg))
+                    let error = get_exception_error(&self.script_ctx, &ctx_scope, trycatch);
+                    Some(StreamBatchAck::Batch(StreamRecordAck::Nack(error)))
                 }
             }
         };
@@ -278,14 +308,14 @@ impl V8StreamCtxInternals {
     }
 }
 
-impl StreamCtxInterface for V8StreamCtx {
-    fn process_record(
+impl V8StreamCtx {
+    fn process_records(
         &self,
         stream_name: &str,
-        record: Box<dyn StreamRecordInterface + Send>,
+        records: Vec<Box<dyn StreamRecordInterface + Send>>,
         run_ctx: &dyn StreamProcessCtxInterface,
-        ack_callback: Box<dyn FnOnce(StreamRecordAck) + Send>,
-    ) -> Option<StreamRecordAck> {
+        ack_callback: Box<dyn FnOnce(StreamBatchAck) + Send>,
+    ) -> Option<StreamBatchAck> {
         if self.is_async {
             let internals = Arc::clone(&self.internals);
             let stream_name = stream_name.to_string();
@@ -294,9 +324,9 @@ impl StreamCtxInterface for V8StreamCtx {
                 .script_ctx
                 .compiled_library_api
                 .run_on_background(Box::new(move || {
-                    internals.process_record_internal_async(
-                        &stream_name.to_string(),
-                        record,
+                    internals.process_records_internal_async(
+                        &stream_name,
+                        records,
                         bg_redis_client,
                         ack_callback,
                     );
@@ -304,7 +334,35 @@ impl StreamCtxInterface for V8StreamCtx {
             None
         } else {
             self.internals
-                .process_record_internal_sync(stream_name, record, run_ctx)
+                .process_records_internal_sync(stream_name, records, run_ctx)
         }
     }
 }
+
+impl StreamCtxInterface for V8StreamCtx {
+    fn process_record(
+        &self,
+        stream_name: &str,
+        record: Box<dyn StreamRecordInterface + Send>,
+        run_ctx: &dyn StreamProcessCtxInterface,
+        ack_callback: Box<dyn FnOnce(StreamRecordAck) + Send>,
+    ) -> Option<StreamRecordAck> {
+        self.process_records(
+            stream_name,
+            vec![record],
+            run_ctx,
+            Box::new(move |ack| ack_callback(get_record_ack(ack))),
+        )
+        .map(get_record_ack)
+    }
+
+    fn process_batch(
+        &self,
+        stream_name: &str,
+        records: Vec<Box<dyn StreamRecordInterface + Send>>,
+        run_ctx: &dyn StreamProcessCtxInterface,
+        ack_callback: Box<dyn FnOnce(StreamBatchAck) + Send>,
+    ) -> Option<StreamBatchAck> {
+        self.process_records(stream_name, records, run_ctx, ack_callback)
+    }
+}